@@ -1,6 +1,10 @@
 pub struct SqlCommandHandler;
 
+use std::ops::Bound;
+
 use anyhow::Result;
+use crate::storage::index::IndexedColumn;
+use crate::storage::key::Key;
 //todo: remove when more general approach is adapted
 use crate::storage::row::Row;
 
@@ -22,7 +26,55 @@ impl SqlCommandHandler {
 
                 Ok(SqlCommand::Insert(Row::new(id, user_name, email)))
             }
-            "select" => Ok(SqlCommand::Select),
+            "update" => {
+                let id = args[0].parse::<u32>().unwrap();
+                let user_name = args[1].to_string();
+                let email = args[2].to_string();
+
+                Ok(SqlCommand::Update(Row::new(id, user_name, email)))
+            }
+            "delete" => {
+                let id = args[0].parse::<u32>().unwrap();
+
+                Ok(SqlCommand::Delete(id))
+            }
+            "select" => {
+                if let [committed] = args[..] {
+                    if committed.eq_ignore_ascii_case("committed") {
+                        return Ok(SqlCommand::SelectCommitted);
+                    }
+                }
+                let (args, descending) = select_order(&args)?;
+                if let Some((column, value)) = select_column(args)? {
+                    return Ok(SqlCommand::SelectBy(column, value, descending));
+                }
+                let (lower_bound, upper_bound) = select_range(args)?;
+                Ok(SqlCommand::Select(lower_bound, upper_bound, descending))
+            }
+            "create" => {
+                let (args, unique) = match args.split_first() {
+                    Some((unique, rest)) if unique.eq_ignore_ascii_case("unique") => (rest, true),
+                    _ => (&args[..], false),
+                };
+                match args[..] {
+                    [index, on, column] if index.eq_ignore_ascii_case("index") && on.eq_ignore_ascii_case("on") => {
+                        Ok(SqlCommand::CreateIndex(IndexedColumn::parse(column)?, unique))
+                    }
+                    _ => anyhow::bail!("Expected create [unique] index on column"),
+                }
+            }
+            "begin" => Ok(SqlCommand::Begin),
+            "commit" => Ok(SqlCommand::Commit),
+            "savepoint" => Ok(SqlCommand::Savepoint(savepoint_name(&args)?)),
+            "release" => Ok(SqlCommand::Release(savepoint_name(&args)?)),
+            "rollback" => match args.first() {
+                Some(&"to") => Ok(SqlCommand::RollbackTo(savepoint_name(&args[1..])?)),
+                _ => Ok(SqlCommand::Rollback),
+            },
+            "pragma" => {
+                let (name, value) = pragma_args(&args)?;
+                Ok(SqlCommand::Pragma(name, value))
+            }
             _ => {
                 println!("Unknown command.");
                 Ok(SqlCommand::Unknown)
@@ -33,6 +85,96 @@ impl SqlCommandHandler {
 
 pub enum SqlCommand {
     Insert(Row),
-    Select,
+    // replaces the row with the same id
+    Update(Row),
+    Delete(u32),
+    // bounds of the ids to select, and whether the rows come in descending id order
+    Select(Bound<Key>, Bound<Key>, bool),
+    // rows whose column holds the value, and whether they come in descending id order
+    SelectBy(IndexedColumn, String, bool),
+    // every row as of the last commit, read through a snapshot
+    SelectCommitted,
+    // the indexed column and whether its values have to be unique
+    CreateIndex(IndexedColumn, bool),
+    Begin,
+    Commit,
+    Rollback,
+    Savepoint(String),
+    Release(String),
+    RollbackTo(String),
+    // pragma name, with the value to set if any
+    Pragma(String, Option<String>),
     Unknown,
 }
+
+// name of the savepoint in `savepoint name`, `release [savepoint] name` and `rollback to [savepoint] name`
+fn savepoint_name(args: &[&str]) -> Result<String> {
+    let args = args.strip_prefix(&["savepoint"]).unwrap_or(args);
+    match args {
+        [name] => Ok(name.to_string()),
+        _ => anyhow::bail!("Expected a savepoint name"),
+    }
+}
+
+// `pragma name` reads a setting, `pragma name = value` changes it. Spaces around = are optional
+fn pragma_args(args: &[&str]) -> Result<(String, Option<String>)> {
+    let joined = args.join(" ");
+    let (name, value) = match joined.split_once('=') {
+        Some((name, value)) => (name.trim(), Some(value.trim())),
+        None => (joined.trim(), None),
+    };
+    if name.is_empty() || name.contains(' ') || value.is_some_and(|value| value.is_empty() || value.contains(' ')) {
+        anyhow::bail!("Expected pragma name or pragma name = value");
+    }
+    Ok((name.to_ascii_lowercase(), value.map(str::to_string)))
+}
+
+// trailing `order by id asc` or `order by id desc`, the rest of the arguments and whether the order is
+// descending. Ascending without an order by clause
+fn select_order<'a, 'b>(args: &'a [&'b str]) -> Result<(&'a [&'b str], bool)> {
+    let Some(position) = args.iter().position(|arg| arg.eq_ignore_ascii_case("order")) else {
+        return Ok((args, false));
+    };
+    let order: Vec<String> = args[position..].iter().map(|arg| arg.to_ascii_lowercase()).collect();
+    match order.iter().map(String::as_str).collect::<Vec<&str>>()[..] {
+        ["order", "by", "id"] | ["order", "by", "id", "asc"] => Ok((&args[..position], false)),
+        ["order", "by", "id", "desc"] => Ok((&args[..position], true)),
+        _ => anyhow::bail!("Expected order by id asc or order by id desc"),
+    }
+}
+
+// `where column = value` for one of the indexable columns, the value keeps its case
+fn select_column(args: &[&str]) -> Result<Option<(IndexedColumn, String)>> {
+    match args {
+        [where_, column, "=", value] if where_.eq_ignore_ascii_case("where") && !column.eq_ignore_ascii_case("id") => {
+            Ok(Some((IndexedColumn::parse(column)?, value.to_string())))
+        }
+        _ => Ok(None),
+    }
+}
+
+// `where id between a and b` or `where id op n` with op one of =, <, <=, > and >=. Every row without a where clause
+fn select_range(args: &[&str]) -> Result<(Bound<Key>, Bound<Key>)> {
+    let args: Vec<String> = args.iter().map(|arg| arg.to_ascii_lowercase()).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let id = |value: &str| match value.parse::<i64>() {
+        Ok(id) => Ok(Key::int(id)),
+        Err(_) => Err(anyhow::anyhow!("Expected an id, got {}", value)),
+    };
+    match args[..] {
+        [] => Ok((Bound::Unbounded, Bound::Unbounded)),
+        ["where", "id", "between", lower, "and", upper] => Ok((Bound::Included(id(lower)?), Bound::Included(id(upper)?))),
+        ["where", "id", operator, value] => {
+            let value = id(value)?;
+            match operator {
+                "=" => Ok((Bound::Included(value.clone()), Bound::Included(value))),
+                "<" => Ok((Bound::Unbounded, Bound::Excluded(value))),
+                "<=" => Ok((Bound::Unbounded, Bound::Included(value))),
+                ">" => Ok((Bound::Excluded(value), Bound::Unbounded)),
+                ">=" => Ok((Bound::Included(value), Bound::Unbounded)),
+                _ => anyhow::bail!("Unknown operator {}, expected =, <, <=, > or >=", operator),
+            }
+        }
+        _ => anyhow::bail!("Expected select, select where id between a and b or select where id op n"),
+    }
+}
@@ -1,11 +1,21 @@
+
+use std::ops::Bound;
+use std::sync::Arc;
+
+use anyhow::Result;
 use cli::cli_parser::CliParser;
 use command::meta_cmd_handler::MetaCommand;
 use command::meta_cmd_handler::MetaCommandHandler;
 use command::sql_cmd_handler::SqlCommand;
 use command::sql_cmd_handler::SqlCommandHandler;
+use storage::config::{ConnectionConfig, SyncMode};
 use storage::cursor::Cursor;
+use storage::database::Database;
+use storage::index::IndexedColumn;
+use storage::key::Key;
 use storage::row::Row;
 use storage::table::Table;
+use storage::vfs::MemoryVfs;
 
 mod cli;
 mod command;
@@ -15,7 +25,13 @@ fn main() {
     let cli_parser = CliParser::new();
     let file_path = cli_parser.parse_file_path();
 
-    let mut table = Table::open_db_connection(&file_path).unwrap();
+    // ":memory:" opens a database that only lives as long as the program
+    let config = if file_path == ":memory:" {
+        ConnectionConfig::new().with_vfs(Arc::new(MemoryVfs::new()))
+    } else {
+        ConnectionConfig::default()
+    };
+    let database = Database::open_with_config(&file_path, &config).unwrap();
     let meta_cmd_handler = MetaCommandHandler::new();
     let sql_cmd_handler = SqlCommandHandler::new();
 
@@ -28,6 +44,17 @@ fn main() {
         "insert 5 user5 email5",
         "insert 6 user6 email6",
         "select",
+        "select where id between 2 and 4",
+        "select where id > 3 order by id desc",
+        "create index on email",
+        "update 4 user4 email2",
+        "select where email = email2",
+        "create unique index on user_name",
+        "insert 7 user4 email7",
+        "begin",
+        "insert 8 user8 email8",
+        "commit",
+        "select committed",
     ];
 
     for command in commands.iter() {
@@ -37,6 +64,11 @@ fn main() {
         if let Some(meta_command) = meta_cmd {
             match meta_command {
                 MetaCommand::Exit => {
+                    let mut table = database.write().unwrap();
+                    // an unfinished transaction is dropped, like a connection closing in the middle of it
+                    if table.in_transaction() {
+                        table.rollback().unwrap();
+                    }
                     table.flush().unwrap();
                     break;
                 }
@@ -48,10 +80,13 @@ fn main() {
 
         match sql_cmd_handler.handle(&user_input) {
             Ok(sql_cmd) => {
-                match sql_cmd {
-                    SqlCommand::Insert(row) => execute_insert(&mut table, row),
-                    SqlCommand::Select => execute_select(&mut table),
-                    SqlCommand::Unknown => {}
+                let result = match sql_cmd {
+                    // the snapshot is read next to the table, which must not be held meanwhile
+                    SqlCommand::SelectCommitted => execute_select_committed(&database),
+                    sql_cmd => database.write().and_then(|mut table| execute(&mut table, sql_cmd)),
+                };
+                if let Err(error) = result {
+                    println!("Error: {:#}", error);
                 }
             }
             Err(error) => panic!("Failed to parse command due to {:?}", error),
@@ -59,20 +94,95 @@ fn main() {
     }
 }
 
+fn execute(table: &mut Table, sql_cmd: SqlCommand) -> Result<()> {
+    match sql_cmd {
+        SqlCommand::Insert(row) => execute_insert(table, row),
+        SqlCommand::Update(row) => execute_update(table, row),
+        SqlCommand::Delete(id) => execute_delete(table, id),
+        SqlCommand::Select(lower_bound, upper_bound, descending) => {
+            execute_select(table, (lower_bound, upper_bound), descending)
+        }
+        SqlCommand::SelectBy(column, value, descending) => execute_select_by(table, column, &value, descending),
+        SqlCommand::SelectCommitted => anyhow::bail!("Committed rows are read through the database"),
+        SqlCommand::CreateIndex(column, unique) => table.create_index(column, unique),
+        SqlCommand::Begin => table.begin_transaction(),
+        SqlCommand::Commit => table.commit(),
+        SqlCommand::Rollback => table.rollback(),
+        SqlCommand::Savepoint(name) => table.savepoint(&name),
+        SqlCommand::Release(name) => table.release_savepoint(&name),
+        SqlCommand::RollbackTo(name) => table.rollback_to_savepoint(&name),
+        SqlCommand::Pragma(name, value) => execute_pragma(table, &name, value.as_deref()),
+        SqlCommand::Unknown => Ok(()),
+    }
+}
+
+fn execute_select(table: &mut Table, range: (Bound<Key>, Bound<Key>), descending: bool) -> Result<()> {
+    let rows = Cursor::range(table, range)?;
+    let rows: Box<dyn Iterator<Item = Result<Row>>> = match descending {
+        true => Box::new(rows.rev()),
+        false => Box::new(rows),
+    };
+    for row in rows {
+        println!("{:?}", row?);
+    }
+    Ok(())
+}
+
+// the rows as of the last commit through a snapshot, without the changes not committed yet
+fn execute_select_committed(database: &Database) -> Result<()> {
+    let mut cursor = database.read_cursor()?;
+    while !cursor.is_end_of_table() {
+        println!("{:?}", Row::deserialize(&cursor.select()?)?);
+        cursor.advance()?;
+    }
+    Ok(())
+}
 
-fn execute_select(table: &mut Table) {
-    let mut cursor = Cursor::table_find(table, 0);
-    let mut table_start_cursor = cursor.to_table_start();
-    while !table_start_cursor.is_end_of_table() {
-        let row = Row::deserialize(table_start_cursor.select()).unwrap();
+fn execute_select_by(table: &mut Table, column: IndexedColumn, value: &str, descending: bool) -> Result<()> {
+    let mut rows = table.find_rows(column, value)?;
+    if descending {
+        rows.reverse();
+    }
+    for row in rows {
         println!("{:?}", row);
-        table_start_cursor.advance();
+    }
+    Ok(())
+}
+
+fn execute_pragma(table: &mut Table, name: &str, value: Option<&str>) -> Result<()> {
+    match name {
+        "synchronous" => {
+            match value {
+                Some(value) => table.set_sync_mode(SyncMode::parse(value)?),
+                None => println!("{}", table.get_sync_mode().get_name()),
+            }
+            Ok(())
+        }
+        _ => anyhow::bail!("Unknown pragma {}", name),
     }
 }
 
-fn execute_insert(table: &mut Table, row: Row) {
-    let mut cursor = Cursor::table_find(table, row.get_id() as usize);
+fn execute_insert(table: &mut Table, row: Row) -> Result<()> {
+    let mut cursor = Cursor::table_find(table, &row.get_key())?;
     cursor.insert(&row)
 }
 
-//todo: usize should be dealt with in a better way
+fn execute_update(table: &mut Table, row: Row) -> Result<()> {
+    let key = row.get_key();
+    let mut cursor = Cursor::table_find(table, &key)?;
+    if cursor.get_key()? != Some(key) {
+        println!("Key {} not found.", row.get_id());
+        return Ok(());
+    }
+    cursor.update(&row)
+}
+
+fn execute_delete(table: &mut Table, id: u32) -> Result<()> {
+    let key = Key::int(id.into());
+    let mut cursor = Cursor::table_find(table, &key)?;
+    if cursor.get_key()? != Some(key) {
+        println!("Key {} not found.", id);
+        return Ok(());
+    }
+    cursor.delete()
+}
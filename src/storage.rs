@@ -1,6 +1,22 @@
+mod buffer_pool;
+pub mod config;
 pub mod constant;
 pub(crate) mod cursor;
+pub mod database;
+pub mod error;
+#[cfg(test)]
+mod fault_vfs;
+mod header;
+pub mod index;
+pub mod key;
+mod layout;
+pub mod lock;
 mod node;
 mod pager;
 pub mod row;
+mod slotted_page;
 pub mod table;
+#[cfg(test)]
+mod test_util;
+pub mod vfs;
+mod wal;
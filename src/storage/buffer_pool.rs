@@ -0,0 +1,239 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::storage::node::Node;
+
+struct Frame {
+    node: Node,
+    dirty: bool,
+    pin_count: usize,
+    // tick of the last access, the key of the frame in the lru queue
+    last_used: u64,
+}
+
+// Bounded page cache with lru eviction. Pinned pages are never evicted, dirty pages are handed back
+// to the caller on eviction so they can be written back before being dropped.
+// The pool may temporarily grow past its capacity when every cached page is pinned.
+pub struct BufferPool {
+    capacity: usize,
+    frames: HashMap<usize, Frame>,
+    lru: BTreeMap<u64, usize>,
+    tick: u64,
+}
+
+impl BufferPool {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            frames: HashMap::new(),
+            lru: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    pub fn contains(&self, page_num: usize) -> bool {
+        self.frames.contains_key(&page_num)
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    // looks the page up without touching the lru order
+    pub fn peek(&self, page_num: usize) -> Option<&Node> {
+        self.frames.get(&page_num).map(|frame| &frame.node)
+    }
+
+    pub fn get(&mut self, page_num: usize) -> Option<&Node> {
+        self.touch(page_num);
+        self.frames.get(&page_num).map(|frame| &frame.node)
+    }
+
+    pub fn get_mut(&mut self, page_num: usize) -> Option<&mut Node> {
+        self.touch(page_num);
+        self.frames.get_mut(&page_num).map(|frame| {
+            frame.dirty = true;
+            &mut frame.node
+        })
+    }
+
+    // caches the node, replacing a cached version of the page while keeping its pins.
+    // Returns the pages evicted to make room for it, dirty ones only
+    pub fn insert(&mut self, page_num: usize, node: Node, dirty: bool) -> Vec<(usize, Node)> {
+        if let Some(frame) = self.frames.get_mut(&page_num) {
+            frame.node = node;
+            frame.dirty |= dirty;
+            self.touch(page_num);
+            return Vec::new();
+        }
+
+        let evicted = self.evict(self.capacity - 1);
+        self.tick += 1;
+        self.lru.insert(self.tick, page_num);
+        self.frames.insert(
+            page_num,
+            Frame {
+                node,
+                dirty,
+                pin_count: 0,
+                last_used: self.tick,
+            },
+        );
+        evicted
+    }
+
+    pub fn pin(&mut self, page_num: usize) {
+        if let Some(frame) = self.frames.get_mut(&page_num) {
+            frame.pin_count += 1;
+        }
+    }
+
+    pub fn unpin(&mut self, page_num: usize) {
+        if let Some(frame) = self.frames.get_mut(&page_num) {
+            frame.pin_count = frame.pin_count.saturating_sub(1);
+        }
+    }
+
+    pub fn dirty_pages(&self) -> Vec<usize> {
+        let mut page_nums: Vec<usize> = self
+            .frames
+            .iter()
+            .filter(|(_, frame)| frame.dirty)
+            .map(|(&page_num, _)| page_num)
+            .collect();
+        page_nums.sort();
+        page_nums
+    }
+
+    pub fn mark_clean(&mut self, page_num: usize) {
+        if let Some(frame) = self.frames.get_mut(&page_num) {
+            frame.dirty = false;
+        }
+    }
+
+    // forgets every modified page, the next read loads the page again
+    pub fn discard_dirty(&mut self) {
+        for page_num in self.dirty_pages() {
+            self.remove(page_num);
+        }
+    }
+
+    pub fn remove(&mut self, page_num: usize) -> Option<Node> {
+        let frame = self.frames.remove(&page_num)?;
+        self.lru.remove(&frame.last_used);
+        Some(frame.node)
+    }
+
+    fn touch(&mut self, page_num: usize) {
+        if let Some(frame) = self.frames.get_mut(&page_num) {
+            self.lru.remove(&frame.last_used);
+            self.tick += 1;
+            frame.last_used = self.tick;
+            self.lru.insert(self.tick, page_num);
+        }
+    }
+
+    // drops least recently used unpinned pages until at most max_len pages are cached
+    fn evict(&mut self, max_len: usize) -> Vec<(usize, Node)> {
+        let mut evicted = Vec::new();
+        if self.frames.len() <= max_len {
+            return evicted;
+        }
+
+        let candidates: Vec<(u64, usize)> = self
+            .lru
+            .iter()
+            .filter(|(_, page_num)| self.frames[page_num].pin_count == 0)
+            .map(|(&tick, &page_num)| (tick, page_num))
+            .take(self.frames.len() - max_len)
+            .collect();
+
+        for (tick, page_num) in candidates {
+            self.lru.remove(&tick);
+            let frame = self.frames.remove(&page_num).unwrap();
+            if frame.dirty {
+                evicted.push((page_num, frame.node));
+            }
+        }
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::storage::constant::DEFAULT_PAGE_SIZE;
+    use crate::storage::layout::PageLayout;
+
+    use super::*;
+
+    fn leaf() -> Node {
+        Node::new_leaf(&PageLayout::new(DEFAULT_PAGE_SIZE))
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let mut pool = BufferPool::new(2);
+        assert!(pool.insert(1, leaf(), false).is_empty());
+        assert!(pool.insert(2, leaf(), false).is_empty());
+        pool.get(1);
+
+        assert!(pool.insert(3, leaf(), false).is_empty());
+        assert!(pool.contains(1));
+        assert!(!pool.contains(2));
+        assert!(pool.contains(3));
+    }
+
+    #[test]
+    fn test_evicted_dirty_pages_are_returned() {
+        let mut pool = BufferPool::new(2);
+        pool.insert(1, leaf(), false);
+        pool.insert(2, leaf(), false);
+        pool.get_mut(1).unwrap().set_next_leaf_num(7);
+        pool.get(2);
+
+        let evicted = pool.insert(3, leaf(), false);
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].0, 1);
+        assert_eq!(evicted[0].1.get_next_leaf_num(), 7);
+
+        // clean pages are dropped silently
+        assert!(pool.insert(4, leaf(), false).is_empty());
+        assert!(!pool.contains(2));
+    }
+
+    #[test]
+    fn test_pinned_pages_are_not_evicted() {
+        let mut pool = BufferPool::new(2);
+        pool.insert(1, leaf(), false);
+        pool.pin(1);
+        pool.insert(2, leaf(), false);
+        pool.insert(3, leaf(), false);
+        assert!(pool.contains(1));
+        assert!(!pool.contains(2));
+
+        pool.pin(3);
+        pool.insert(4, leaf(), false);
+        assert_eq!(pool.len(), 3);
+
+        pool.unpin(1);
+        pool.insert(5, leaf(), false);
+        assert!(!pool.contains(1));
+        assert!(pool.contains(3));
+    }
+
+    #[test]
+    fn test_dirty_tracking() {
+        let mut pool = BufferPool::new(4);
+        pool.insert(1, leaf(), false);
+        pool.insert(2, leaf(), true);
+        pool.get(1);
+        assert_eq!(pool.dirty_pages(), vec![2]);
+
+        pool.get_mut(1);
+        assert_eq!(pool.dirty_pages(), vec![1, 2]);
+
+        pool.mark_clean(1);
+        pool.mark_clean(2);
+        assert!(pool.dirty_pages().is_empty());
+    }
+}
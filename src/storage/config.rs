@@ -0,0 +1,150 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::storage::constant::{DEFAULT_BUSY_TIMEOUT, DEFAULT_CACHE_SIZE, DEFAULT_PAGE_SIZE};
+use crate::storage::vfs::{StdVfs, Vfs};
+
+// per connection settings, passed to Table::open_db_connection_with_config
+#[derive(Clone, Debug)]
+pub struct ConnectionConfig {
+    cache_size: usize,
+    page_size: usize,
+    busy_timeout: Duration,
+    sync_mode: SyncMode,
+    vfs: Arc<dyn Vfs>,
+    mmap: bool,
+}
+
+// How hard commits are pushed to disk. A crash of the process alone never loses a commit, the modes
+// differ in what survives a crash of the operating system or a power loss
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncMode {
+    // nothing is synced, the database may be corrupted by a power loss
+    Off,
+    // the WAL and the database file are synced around checkpoints only. The latest commits may be lost,
+    // but the database stays consistent
+    Normal,
+    // the WAL is synced on every commit as well, a commit is durable once it returns
+    Full,
+}
+
+impl SyncMode {
+    // accepts the names and the numbers used by PRAGMA synchronous
+    pub fn parse(value: &str) -> anyhow::Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "off" | "0" => Ok(SyncMode::Off),
+            "normal" | "1" => Ok(SyncMode::Normal),
+            "full" | "2" => Ok(SyncMode::Full),
+            _ => anyhow::bail!("Unknown synchronous mode {}, expected off, normal or full", value),
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            SyncMode::Off => "off",
+            SyncMode::Normal => "normal",
+            SyncMode::Full => "full",
+        }
+    }
+}
+
+impl ConnectionConfig {
+    pub fn new() -> Self {
+        Self {
+            cache_size: DEFAULT_CACHE_SIZE,
+            page_size: DEFAULT_PAGE_SIZE,
+            busy_timeout: DEFAULT_BUSY_TIMEOUT,
+            sync_mode: SyncMode::Full,
+            vfs: Arc::new(StdVfs),
+            mmap: false,
+        }
+    }
+
+    // max number of pages kept in memory, pages pinned by cursors may exceed it
+    #[cfg(test)]
+    pub fn with_cache_size(mut self, cache_size: usize) -> Self {
+        self.cache_size = cache_size;
+        self
+    }
+
+    // page size of newly created databases, a power of two between 512 bytes and 64 KiB.
+    // Existing databases keep the page size they were created with
+    #[cfg(test)]
+    pub fn with_page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size;
+        self
+    }
+
+    // how long to wait for a lock held by another connection before failing with "database is locked"
+    #[cfg(test)]
+    pub fn with_busy_timeout(mut self, busy_timeout: Duration) -> Self {
+        self.busy_timeout = busy_timeout;
+        self
+    }
+
+    #[cfg(test)]
+    pub fn with_sync_mode(mut self, sync_mode: SyncMode) -> Self {
+        self.sync_mode = sync_mode;
+        self
+    }
+
+    // where the database and its WAL are stored, the file system unless set
+    pub fn with_vfs(mut self, vfs: Arc<dyn Vfs>) -> Self {
+        self.vfs = vfs;
+        self
+    }
+
+    // reads pages of the database file through a memory mapping instead of read calls. Writes always go
+    // through the vfs, backends that cannot be mapped are read as usual
+    #[cfg(test)]
+    pub fn with_mmap(mut self, mmap: bool) -> Self {
+        self.mmap = mmap;
+        self
+    }
+
+    pub fn get_cache_size(&self) -> usize {
+        self.cache_size
+    }
+
+    pub fn get_page_size(&self) -> usize {
+        self.page_size
+    }
+
+    pub fn get_busy_timeout(&self) -> Duration {
+        self.busy_timeout
+    }
+
+    pub fn get_sync_mode(&self) -> SyncMode {
+        self.sync_mode
+    }
+
+    pub fn get_vfs(&self) -> &Arc<dyn Vfs> {
+        &self.vfs
+    }
+
+    pub fn get_mmap(&self) -> bool {
+        self.mmap
+    }
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_sync_mode() {
+        assert_eq!(SyncMode::parse("off").unwrap(), SyncMode::Off);
+        assert_eq!(SyncMode::parse("NORMAL").unwrap(), SyncMode::Normal);
+        assert_eq!(SyncMode::parse("2").unwrap(), SyncMode::Full);
+        assert!(SyncMode::parse("extra").is_err());
+        for sync_mode in [SyncMode::Off, SyncMode::Normal, SyncMode::Full] {
+            assert_eq!(SyncMode::parse(sync_mode.get_name()).unwrap(), sync_mode);
+        }
+    }
+}
@@ -1,49 +1,88 @@
-// row constants
-pub const USER_NAME_SIZE: usize = 32;
-pub const EMAIL_SIZE: usize = 255;
-pub const ID_SIZE: usize = std::mem::size_of::<usize>();
-pub const ROW_SIZE: usize = USER_NAME_SIZE + EMAIL_SIZE + ID_SIZE;
+use std::time::Duration;
+
+// key constants
+// encoded keys are at most this long, internal nodes keep a slot of this size for every key
+pub const MAX_KEY_SIZE: usize = 64;
+pub(crate) const KEY_LEN_SIZE: usize = std::mem::size_of::<u16>();
 // table constants
-pub const PAGE_SIZE: usize = 4096;
+// the page size is chosen when a database is created, see storage::layout
+pub const DEFAULT_PAGE_SIZE: usize = 4096;
+pub const MIN_PAGE_SIZE: usize = 512;
+pub const MAX_PAGE_SIZE: usize = 64 * 1024;
 
-pub const TABLE_MAX_PAGES: usize = 100;
+// pages kept in memory per connection unless configured otherwise
+pub const DEFAULT_CACHE_SIZE: usize = 100;
+// how long a connection waits for the lock of another one, it fails right away by default
+pub const DEFAULT_BUSY_TIMEOUT: Duration = Duration::ZERO;
+// a commit leaving more frames than this in the WAL checkpoints it into the database file
+pub const WAL_AUTOCHECKPOINT_FRAMES: usize = 1000;
 
 // node constants
 
+pub(crate) const CHECKSUM_SIZE: usize = std::mem::size_of::<u32>();
+pub(crate) const CHECKSUM_OFFSET: usize = 0;
+
 pub const NODE_TYPE_SIZE: usize = std::mem::size_of::<u8>();
-pub(crate) const NODE_TYPE_OFFSET: usize = 0;
+pub(crate) const NODE_TYPE_OFFSET: usize = CHECKSUM_OFFSET + CHECKSUM_SIZE;
 
 pub(crate) const IS_ROOT_SIZE: usize = std::mem::size_of::<u8>();
 pub(crate) const IS_ROOT_OFFSET: usize = NODE_TYPE_OFFSET + NODE_TYPE_SIZE;
 
-pub(crate) const PARENT_PAGE_NUM_SIZE: usize = std::mem::size_of::<usize>();
+pub(crate) const PARENT_PAGE_NUM_SIZE: usize = std::mem::size_of::<u64>();
 pub(crate) const PARENT_PAGE_NUM_OFFSET: usize = IS_ROOT_OFFSET + IS_ROOT_SIZE;
 
-pub(crate) const SPACE_FOR_COMMON_HEADER: usize = NODE_TYPE_SIZE + IS_ROOT_SIZE + PARENT_PAGE_NUM_SIZE;
+pub(crate) const NEXT_FREE_PAGE_NUM_SIZE: usize = std::mem::size_of::<u64>();
+pub(crate) const NEXT_FREE_PAGE_NUM_OFFSET: usize = PARENT_PAGE_NUM_OFFSET + PARENT_PAGE_NUM_SIZE;
+
+pub(crate) const SPACE_FOR_COMMON_HEADER: usize = CHECKSUM_SIZE + NODE_TYPE_SIZE + IS_ROOT_SIZE + PARENT_PAGE_NUM_SIZE + NEXT_FREE_PAGE_NUM_SIZE;
 
 // leaf node constants
-pub(crate) const CELLS_COUNT_SIZE: usize = std::mem::size_of::<usize>();
+pub(crate) const CELLS_COUNT_SIZE: usize = std::mem::size_of::<u64>();
 pub(crate) const CELLS_COUNT_OFFSET: usize = SPACE_FOR_COMMON_HEADER;
-pub const LEAF_NEXT_LEAF_SIZE: usize = std::mem::size_of::<usize>();
+pub const LEAF_NEXT_LEAF_SIZE: usize = std::mem::size_of::<u64>();
 pub const LEAF_NEXT_LEAF_OFFSET: usize = CELLS_COUNT_OFFSET + CELLS_COUNT_SIZE;
+pub const LEAF_PREV_LEAF_SIZE: usize = std::mem::size_of::<u64>();
+pub const LEAF_PREV_LEAF_OFFSET: usize = LEAF_NEXT_LEAF_OFFSET + LEAF_NEXT_LEAF_SIZE;
 
-pub(crate) const LEAF_NODE_HEADER_SIZE: usize = CELLS_COUNT_SIZE + LEAF_NEXT_LEAF_SIZE;
+pub(crate) const LEAF_HEAP_START_SIZE: usize = std::mem::size_of::<u64>();
+pub(crate) const LEAF_HEAP_START_OFFSET: usize = LEAF_PREV_LEAF_OFFSET + LEAF_PREV_LEAF_SIZE;
+pub(crate) const LEAF_FRAGMENTED_BYTES_SIZE: usize = std::mem::size_of::<u64>();
+pub(crate) const LEAF_FRAGMENTED_BYTES_OFFSET: usize = LEAF_HEAP_START_OFFSET + LEAF_HEAP_START_SIZE;
+
+pub(crate) const LEAF_NODE_HEADER_SIZE: usize =
+    CELLS_COUNT_SIZE + LEAF_NEXT_LEAF_SIZE + LEAF_PREV_LEAF_SIZE + LEAF_HEAP_START_SIZE + LEAF_FRAGMENTED_BYTES_SIZE;
 
-pub(crate) const LEAF_NODE_CELLS_SPACE: usize = PAGE_SIZE - SPACE_FOR_COMMON_HEADER - LEAF_NODE_HEADER_SIZE;
 pub(crate) const LEAF_NODE_CELLS_OFFSET: usize = SPACE_FOR_COMMON_HEADER + LEAF_NODE_HEADER_SIZE;
 
-pub(crate) const LEAF_NODE_MAX_CELLS: usize = 3;
+// cell pointer of a slotted leaf page: u16 offset and u16 length of the cell
+pub(crate) const SLOT_SIZE: usize = 2 * std::mem::size_of::<u16>();
+// a cell starts with the length of the key, the key and the full length of the value
+pub(crate) const VALUE_LEN_SIZE: usize = std::mem::size_of::<u64>();
+pub(crate) const MIN_CELL_HEADER_SIZE: usize = KEY_LEN_SIZE + VALUE_LEN_SIZE;
+pub(crate) const MAX_CELL_HEADER_SIZE: usize = MIN_CELL_HEADER_SIZE + MAX_KEY_SIZE;
+// values are kept local only while this many cells still fit into a leaf, the rest spills into overflow pages
+pub(crate) const LEAF_MIN_CELLS: usize = 4;
 
-pub(crate) const CELL_SIZE: usize = ID_SIZE + ROW_SIZE;
+// overflow page constants
+pub(crate) const OVERFLOW_NEXT_PAGE_SIZE: usize = std::mem::size_of::<u64>();
+pub(crate) const OVERFLOW_NEXT_PAGE_OFFSET: usize = SPACE_FOR_COMMON_HEADER;
+pub(crate) const OVERFLOW_PAYLOAD_SIZE_SIZE: usize = std::mem::size_of::<u64>();
+pub(crate) const OVERFLOW_PAYLOAD_SIZE_OFFSET: usize = OVERFLOW_NEXT_PAGE_OFFSET + OVERFLOW_NEXT_PAGE_SIZE;
+pub(crate) const OVERFLOW_PAYLOAD_OFFSET: usize = OVERFLOW_PAYLOAD_SIZE_OFFSET + OVERFLOW_PAYLOAD_SIZE_SIZE;
 
 // internal node constants
-pub const INTERNAL_NODE_KEY_COUNT_SIZE: usize = std::mem::size_of::<usize>();
+pub const INTERNAL_NODE_KEY_COUNT_SIZE: usize = std::mem::size_of::<u64>();
 pub const INTERNAL_NODE_KEY_COUNT_OFFSET: usize = SPACE_FOR_COMMON_HEADER;
-pub const PAGE_NUM_SIZE: usize = std::mem::size_of::<usize>();
+pub const PAGE_NUM_SIZE: usize = std::mem::size_of::<u64>();
 pub const RIGHT_CHILD_OFFSET: usize = INTERNAL_NODE_KEY_COUNT_OFFSET + INTERNAL_NODE_KEY_COUNT_SIZE;
 pub const INTERNAL_NODE_HEADER_SIZE: usize = INTERNAL_NODE_KEY_COUNT_SIZE + PAGE_NUM_SIZE;
 
-pub const KEY_VALUE_SIZE: usize = ID_SIZE + PAGE_NUM_SIZE;
+// the length of the key, the key padded to MAX_KEY_SIZE and the child page num
+pub const KEY_VALUE_SIZE: usize = KEY_LEN_SIZE + MAX_KEY_SIZE + PAGE_NUM_SIZE;
 pub const KEY_VALUE_OFFSET: usize = SPACE_FOR_COMMON_HEADER + INTERNAL_NODE_HEADER_SIZE;
-pub const INTERNAL_NODE_MAX_CELLS: usize = 3;
-pub const INTERNAL_CELL_SIZE: usize = ID_SIZE + PAGE_NUM_SIZE;
+pub const INTERNAL_CELL_SIZE: usize = KEY_VALUE_SIZE;
+
+// the smallest page still has to hold LEAF_MIN_CELLS overflowing cells and enough keys for an internal node to split
+const _: () =
+    assert!(LEAF_NODE_CELLS_OFFSET + LEAF_MIN_CELLS * (SLOT_SIZE + MAX_CELL_HEADER_SIZE + PAGE_NUM_SIZE + 1) <= MIN_PAGE_SIZE);
+const _: () = assert!(KEY_VALUE_OFFSET + 3 * INTERNAL_CELL_SIZE <= MIN_PAGE_SIZE);
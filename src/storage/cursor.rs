@@ -1,205 +1,906 @@
-use crate::storage::node::NodeType;
+use std::ops::{Bound, RangeBounds};
+
+use anyhow::Result;
+
+use crate::storage::error::StorageError;
+use crate::storage::index::Index;
+use crate::storage::key::Key;
+use crate::storage::node::{Node, NodeType};
+use crate::storage::pager::Snapshot;
 use crate::storage::row::Row;
 use crate::storage::table::Table;
 
 pub struct Cursor<'a> {
     table: &'a mut Table,
+    // the table tree or the tree of an index
+    root_page_num: usize,
     page_num: usize,
     cell_num: usize,
     end_of_table: bool,
+    // stepped back from the first row, the cursor still points at it
+    start_of_table: bool,
 }
 
 impl<'a> Cursor<'a> {
-
-    pub fn to_table_start(&mut self) -> &mut Cursor<'a> {
-        // Then, get the pager as mutable which is separate from the above operation
-        let page_num = self.get_page_num();
-        let num_cells = {
-            let node =  self.table.get_pager_mut().get_node_mut(page_num);
-            node.get_cell_count()
-        };
-
-        // Use the num_cells value to update the cursor
-        self.end_of_table = num_cells == 0;
-        self
+    #[cfg(test)]
+    pub fn table_start(table: &'a mut Table) -> Result<Cursor<'a>> {
+        // the leftmost leaf holds the smallest key, an empty leftmost leaf means an empty table
+        Cursor::table_find(table, &Key::min())
     }
 
-    pub fn new(table: &'a mut Table, page_num: usize, cell_num: usize) -> Cursor {
+    // the page the cursor points at stays pinned in the buffer pool until the cursor moves or is dropped
+    pub fn new(table: &'a mut Table, page_num: usize, cell_num: usize) -> Result<Cursor<'a>> {
         let root_page_num = table.get_root_page_num();
-        let end_of_table = table.get_pager_mut().get_node_mut(root_page_num).get_cell_count() == cell_num;
-        Cursor {
+        Self::new_in_tree(table, root_page_num, page_num, cell_num)
+    }
+
+    fn new_in_tree(table: &'a mut Table, root_page_num: usize, page_num: usize, cell_num: usize) -> Result<Cursor<'a>> {
+        let end_of_table = is_end_of_tree(table.get_pager_mut().get_node(page_num)?, cell_num);
+        table.get_pager_mut().pin(page_num)?;
+        Ok(Cursor {
             table,
+            root_page_num,
             page_num,
             cell_num,
             end_of_table,
+            start_of_table: false,
+        })
+    }
+
+    // rows with keys in the range, in key order. Starts at the lower bound, leaves before it are not read
+    pub fn range(table: &'a mut Table, range: impl RangeBounds<Key>) -> Result<RangeCursor<'a>> {
+        let lower_bound = range.start_bound().cloned();
+        let mut cursor = Cursor::table_find(table, start_key(&lower_bound))?;
+        cursor.skip_leaf_end()?;
+        let mut rows = RangeCursor {
+            cursor,
+            lower_bound,
+            upper_bound: range.end_bound().cloned(),
+            forward: true,
+            done: false,
+        };
+        rows.skip_excluded_lower_bound()?;
+        Ok(rows)
+    }
+
+    pub fn table_find(table: &'a mut Table, key: &Key) -> Result<Cursor<'a>> {
+        // a new statement sees what other connections committed so far
+        table.get_pager_mut().refresh()?;
+        let root_page_num = table.get_root_page_num();
+        let (page_num, cell_num) = Cursor::find_position(table, root_page_num, key)?;
+        Self::new(table, page_num, cell_num)
+    }
+
+    // like table_find, in the tree of the index. The values of its entries are the keys of the rows.
+    // Only the table changes the entries, it keeps them in sync with the rows
+    pub fn index_find(table: &'a mut Table, index: &Index, key: &Key) -> Result<Cursor<'a>> {
+        table.get_pager_mut().refresh()?;
+        let (page_num, cell_num) = Cursor::find_position(table, index.get_root_page_num(), key)?;
+        Self::new_in_tree(table, index.get_root_page_num(), page_num, cell_num)
+    }
+
+    // entries of the index from the first one at or past the key on, for lookups
+    pub fn index_scan(table: &'a mut Table, index: &Index, key: &Key) -> Result<Cursor<'a>> {
+        let mut cursor = Cursor::index_find(table, index, key)?;
+        cursor.skip_leaf_end()?;
+        Ok(cursor)
+    }
+
+    // page and cell of the key, or of the cell it would be inserted at
+    fn find_position(table: &mut Table, root_page_num: usize, key: &Key) -> Result<(usize, usize)> {
+        let mut page_num = root_page_num;
+        loop {
+            let node = table.get_pager_mut().get_node(page_num)?;
+            match node.get_node_type() {
+                NodeType::Leaf => return Ok((page_num, node.leaf_find_cell_index_by_key(key))),
+                NodeType::Internal => page_num = node.internal_node_children(node.internal_find_child_index_by_key(key)),
+                node_type @ (NodeType::Free | NodeType::Overflow) => {
+                    return Err(StorageError::MisplacedPage { page_num, node_type }.into())
+                }
+            }
+        }
+    }
+
+    // past the last cell of the rightmost leaf
+    fn find_end_position(table: &mut Table, root_page_num: usize) -> Result<(usize, usize)> {
+        let mut page_num = root_page_num;
+        loop {
+            let node = table.get_pager_mut().get_node(page_num)?;
+            match node.get_node_type() {
+                NodeType::Leaf => return Ok((page_num, node.get_cell_count())),
+                NodeType::Internal => page_num = node.right_child_key,
+                node_type @ (NodeType::Free | NodeType::Overflow) => {
+                    return Err(StorageError::MisplacedPage { page_num, node_type }.into())
+                }
+            }
         }
     }
 
-    pub fn table_find(table: &mut Table, key: usize) -> Cursor {
+    pub fn get_page_num(&self) -> usize {
+        self.page_num
+    }
+
+    pub fn get_cell_num(&self) -> usize {
+        self.cell_num
+    }
+
+    #[cfg(test)]
+    pub fn is_end_of_table(&self) -> bool {
+        self.end_of_table
+    }
+
+    pub fn is_start_of_table(&self) -> bool {
+        self.start_of_table
+    }
+
+    // past the last cell of the rightmost leaf, retreat moves to the last row
+    #[cfg(test)]
+    pub fn table_end(table: &'a mut Table) -> Result<Cursor<'a>> {
+        table.get_pager_mut().refresh()?;
         let root_page_num = table.get_root_page_num();
-        let root_node = table.get_pager_mut().get_node_mut(root_page_num);
+        let (page_num, cell_num) = Cursor::find_end_position(table, root_page_num)?;
+        Self::new(table, page_num, cell_num)
+    }
+
+    pub fn advance(&mut self) -> Result<()> {
+        if self.start_of_table {
+            self.start_of_table = false;
+            return Ok(());
+        }
+        self.cell_num += 1;
+        let node = self.table.get_pager_mut().get_node(self.page_num)?;
+
+        if self.cell_num >= node.get_cell_count() {
+            let next_page_num = node.get_next_leaf_num();
+            if next_page_num == 0 {
+                // This was the rightmost leaf
+                self.end_of_table = true;
+            } else {
+                let pager = self.table.get_pager_mut();
+                pager.pin(next_page_num)?;
+                pager.unpin(self.page_num);
+                self.page_num = next_page_num;
+                self.cell_num = 0;
+            }
+        }
+        Ok(())
+    }
 
-        let root_node_type = root_node.get_node_type();
-        if root_node_type == NodeType::Leaf {
-            return Cursor::leaf_node_find(table, root_page_num, key);
+    // moves to the previous row, following the previous leaf links. Stepping back from the first row
+    // sets start_of_table, advancing from there goes back to the first row
+    pub fn retreat(&mut self) -> Result<()> {
+        if self.start_of_table {
+            return Ok(());
+        }
+        let node = self.table.get_pager_mut().get_node(self.page_num)?;
+        // advancing at the end of the table may have gone further than one past the last cell
+        let cell_num = self.cell_num.min(node.get_cell_count());
+        if cell_num > 0 {
+            self.cell_num = cell_num - 1;
+            self.end_of_table = false;
+            return Ok(());
+        }
+
+        let prev_page_num = node.get_prev_leaf_num();
+        if prev_page_num == 0 {
+            // This was the leftmost leaf
+            self.start_of_table = true;
         } else {
-            return Cursor::internal_node_find(table, root_page_num, key);
+            let cell_count = self.table.get_pager_mut().get_node(prev_page_num)?.get_cell_count();
+            self.move_to(prev_page_num, cell_count - 1)?;
         }
+        Ok(())
     }
 
-    fn internal_node_find(table: &'a mut Table, page_num: usize, key: usize) -> Cursor {
-        let node = table.get_pager_mut().get_node_mut(page_num);
-        let keys_num = node.get_key_count();
+    // points the cursor at the key like table_find does, within the same statement
+    fn seek(&mut self, key: &Key) -> Result<()> {
+        let (page_num, cell_num) = Cursor::find_position(self.table, self.root_page_num, key)?;
+        self.move_to(page_num, cell_num)
+    }
 
-        let mut min_index = 0;
-        let mut max_index = keys_num;
+    // a search past the last cell of a leaf that is not the rightmost one goes on at the first cell of the
+    // next leaf, which holds the next key when the separator in front of it is larger than the max key of
+    // the leaf. Only for reading, an insert belongs into the leaf the search found
+    fn skip_leaf_end(&mut self) -> Result<()> {
+        let node = self.table.get_pager_mut().get_node(self.page_num)?;
+        let next_page_num = node.get_next_leaf_num();
+        if self.cell_num >= node.get_cell_count() && next_page_num != 0 {
+            self.move_to(next_page_num, 0)?;
+        }
+        Ok(())
+    }
 
-        while min_index != max_index {
-            let index = (min_index + max_index) / 2;
-            let key_at_index = node.internal_get_key(index);
+    fn seek_end(&mut self) -> Result<()> {
+        let (page_num, cell_num) = Cursor::find_end_position(self.table, self.root_page_num)?;
+        self.move_to(page_num, cell_num)
+    }
 
-            if key_at_index >= key {
-                max_index = index;
-            } else {
-                min_index = index + 1;
+    fn move_to(&mut self, page_num: usize, cell_num: usize) -> Result<()> {
+        let pager = self.table.get_pager_mut();
+        let end_of_table = is_end_of_tree(pager.get_node(page_num)?, cell_num);
+        pager.pin(page_num)?;
+        pager.unpin(self.page_num);
+        self.page_num = page_num;
+        self.cell_num = cell_num;
+        self.end_of_table = end_of_table;
+        self.start_of_table = false;
+        Ok(())
+    }
+
+    pub fn select(&mut self) -> Result<Vec<u8>> {
+        self.table.select(self.page_num, self.cell_num)
+    }
+
+    // key of the cell the cursor points at, None past the last cell of the page
+    pub fn get_key(&mut self) -> Result<Option<Key>> {
+        let node = self.table.get_pager_mut().get_node(self.page_num)?;
+        if self.cell_num < node.get_cell_count() {
+            Ok(Some(node.get_key(self.cell_num)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    //todo: cursor should probably not know about row
+    pub fn insert(&mut self, row: &Row) -> Result<()> {
+        self.table.insert(self.page_num, self.cell_num, row)
+    }
+
+    // the cursor has to be at the key, see table_find
+    #[cfg(test)]
+    pub fn insert_key_value(&mut self, key: &Key, value: &[u8]) -> Result<()> {
+        self.table.insert_key_value(self.page_num, self.cell_num, key, value)
+    }
+
+    // the tree may be rebalanced, so the cursor should not be used after deleting
+    pub fn delete(&mut self) -> Result<()> {
+        self.table.delete(self.page_num, self.cell_num)
+    }
+
+    // replaces the row the cursor points at with one of the same id, the cursor should not be used afterwards
+    pub fn update(&mut self, row: &Row) -> Result<()> {
+        self.table.update(self.page_num, self.cell_num, row)
+    }
+}
+
+impl Drop for Cursor<'_> {
+    fn drop(&mut self) {
+        self.table.get_pager_mut().unpin(self.page_num);
+    }
+}
+
+// Iterator over the rows of a key range, see Cursor::range. Iterates from both ends: the bounds shrink
+// to the keys not handed out yet, and the cursor seeks to the other end when the direction changes.
+// Stops after the first error
+pub(crate) struct RangeCursor<'a> {
+    cursor: Cursor<'a>,
+    lower_bound: Bound<Key>,
+    upper_bound: Bound<Key>,
+    // whether the cursor is at the front end or at the back end
+    forward: bool,
+    done: bool,
+}
+
+// only past the last cell of the rightmost leaf, the end of any other leaf is followed by the next one
+fn is_end_of_tree(node: &Node, cell_num: usize) -> bool {
+    cell_num >= node.get_cell_count() && node.get_next_leaf_num() == 0
+}
+
+// where a search for the first key of the range starts
+fn start_key(lower_bound: &Bound<Key>) -> &Key {
+    match lower_bound {
+        Bound::Included(key) | Bound::Excluded(key) => key,
+        Bound::Unbounded => const { &Key::min() },
+    }
+}
+
+impl RangeCursor<'_> {
+    // the search lands on an excluded lower bound if the key is in the table
+    fn skip_excluded_lower_bound(&mut self) -> Result<()> {
+        if let Bound::Excluded(key) = &self.lower_bound {
+            if self.cursor.get_key()?.as_ref() == Some(key) {
+                self.cursor.advance()?;
             }
         }
+        Ok(())
+    }
 
-        let child_page_num = node.internal_node_children(min_index);
-        let child_node = table.get_pager_mut().get_node_mut(child_page_num);
-        let child_node_type = child_node.get_node_type();
+    fn next_row(&mut self) -> Result<Option<Row>> {
+        if !self.forward {
+            self.cursor.seek(start_key(&self.lower_bound))?;
+            self.cursor.skip_leaf_end()?;
+            self.skip_excluded_lower_bound()?;
+            self.forward = true;
+        }
+        let Some((key, row)) = self.select_in_range()? else {
+            return Ok(None);
+        };
+        self.lower_bound = Bound::Excluded(key);
+        self.cursor.advance()?;
+        Ok(Some(row))
+    }
 
-        return match child_node_type {
-            NodeType::Leaf => {
-                Cursor::leaf_node_find(table, child_page_num, key)
+    fn next_back_row(&mut self) -> Result<Option<Row>> {
+        if self.forward {
+            // the cursor lands on the first key at or past the bound
+            match &self.upper_bound {
+                Bound::Included(key) | Bound::Excluded(key) => self.cursor.seek(key)?,
+                Bound::Unbounded => self.cursor.seek_end()?,
             }
-            NodeType::Internal => {
-                Cursor::internal_node_find(table, child_page_num, key)
+            self.forward = false;
+            if !self.cursor.get_key()?.is_some_and(|key| self.contains(&key)) {
+                self.cursor.retreat()?;
             }
+        }
+        if self.cursor.is_start_of_table() {
+            return Ok(None);
+        }
+        let Some((key, row)) = self.select_in_range()? else {
+            return Ok(None);
         };
+        self.upper_bound = Bound::Excluded(key);
+        self.cursor.retreat()?;
+        Ok(Some(row))
     }
 
-    fn leaf_node_find(table: &'a mut Table, page_num: usize, key: usize) -> Cursor {
-        let root_node = table.get_pager_mut().get_node_mut(page_num);
+    fn select_in_range(&mut self) -> Result<Option<(Key, Row)>> {
+        match self.cursor.get_key()? {
+            Some(key) if self.contains(&key) => {
+                let row = Row::deserialize(&self.cursor.select()?)?;
+                Ok(Some((key, row)))
+            }
+            _ => Ok(None),
+        }
+    }
 
-        let mut min_index = 0;
-        let mut one_past_max_index = root_node.get_cell_count();
+    fn contains(&self, key: &Key) -> bool {
+        (self.lower_bound.as_ref(), self.upper_bound.as_ref()).contains(key)
+    }
 
-        while min_index != one_past_max_index {
-            let index = (min_index + one_past_max_index) / 2;
-            let key_at_index = root_node.get_key(index);
+    fn step(&mut self, row: Option<Result<Row>>) -> Option<Result<Row>> {
+        self.done = !matches!(row, Some(Ok(_)));
+        row
+    }
+}
 
-            if key_at_index == key {
-                return Self::new(table, page_num, index);
-            }
+impl Iterator for RangeCursor<'_> {
+    type Item = Result<Row>;
 
-            if key_at_index > key {
-                one_past_max_index = index;
-            } else {
-                min_index = index + 1;
-            }
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
         }
+        let row = self.next_row().transpose();
+        self.step(row)
+    }
+}
 
-        return Self::new(table, page_num, min_index);
+// once one end runs out of rows the ends have met, so both ends are done
+impl DoubleEndedIterator for RangeCursor<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let row = self.next_back_row().transpose();
+        self.step(row)
     }
+}
 
-    pub fn get_page_num(&self) -> usize {
-        self.page_num
+// Read only cursor over the tree as of a snapshot. It does not borrow the table between calls, so
+// writers can keep changing it while a long scan is in progress. Internal, for tests of snapshots on
+// a single connection, readers go through Database::read_cursor
+#[cfg(test)]
+pub struct SnapshotCursor<'s> {
+    snapshot: &'s Snapshot,
+    position: SnapshotPosition,
+}
+
+#[cfg(test)]
+impl<'s> SnapshotCursor<'s> {
+    pub fn table_start(table: &Table, snapshot: &'s Snapshot) -> Result<SnapshotCursor<'s>> {
+        SnapshotCursor::table_find(table, snapshot, &Key::min())
     }
 
-    pub fn get_cell_num(&self) -> usize {
-        self.cell_num
+    pub fn table_find(table: &Table, snapshot: &'s Snapshot, key: &Key) -> Result<SnapshotCursor<'s>> {
+        Ok(SnapshotCursor {
+            snapshot,
+            position: SnapshotPosition::find(table, snapshot, key)?,
+        })
     }
 
     pub fn is_end_of_table(&self) -> bool {
-        self.end_of_table
+        self.position.is_end_of_table()
     }
 
-    pub fn table_end(table: &'a mut Table) -> Cursor {
-        let page_num = table.get_root_page_num();
-        let root_node = table.get_pager_mut().get_node_mut(0);
-        let cell_count = root_node.get_cell_count();
-        Cursor {
-            table,
-            page_num,
-            cell_num: cell_count,
-            end_of_table: true,
+    pub fn advance(&mut self, table: &Table) -> Result<()> {
+        self.position.advance(table, self.snapshot)
+    }
+
+    pub fn get_key(&self) -> Option<Key> {
+        self.position.get_key()
+    }
+
+    pub fn select(&self, table: &Table) -> Result<Vec<u8>> {
+        self.position.select(table, self.snapshot)
+    }
+}
+
+// where a snapshot cursor points, only reads the table through shared references
+pub(crate) struct SnapshotPosition {
+    page_num: usize,
+    // the leaf the cursor points at, snapshot pages never change
+    node: Node,
+    cell_num: usize,
+    end_of_table: bool,
+}
+
+impl SnapshotPosition {
+    pub fn find(table: &Table, snapshot: &Snapshot, key: &Key) -> Result<Self> {
+        let mut page_num = table.get_root_page_num();
+        let pager = table.get_pager();
+        let mut node = pager.get_node_at(page_num, snapshot)?;
+        loop {
+            match node.get_node_type() {
+                NodeType::Leaf => break,
+                NodeType::Internal => {
+                    page_num = node.internal_node_children(node.internal_find_child_index_by_key(key));
+                    node = pager.get_node_at(page_num, snapshot)?;
+                }
+                node_type @ (NodeType::Free | NodeType::Overflow) => {
+                    return Err(StorageError::MisplacedPage { page_num, node_type }.into())
+                }
+            }
+        }
+
+        let mut cell_num = node.leaf_find_cell_index_by_key(key);
+        // the key may be in the next leaf, see Cursor::skip_leaf_end
+        if cell_num == node.get_cell_count() && node.get_next_leaf_num() != 0 {
+            page_num = node.get_next_leaf_num();
+            node = pager.get_node_at(page_num, snapshot)?;
+            cell_num = 0;
         }
+        Ok(Self {
+            page_num,
+            end_of_table: is_end_of_tree(&node, cell_num),
+            node,
+            cell_num,
+        })
     }
 
-    pub fn advance(&mut self) {
-        self.cell_num += 1;
-        let node = self.table.get_pager_mut().get_node_mut(self.page_num);
+    pub fn is_end_of_table(&self) -> bool {
+        self.end_of_table
+    }
 
-        if self.cell_num >= node.get_cell_count() {
-            let next_page_num = node.get_next_leaf_num();
+    pub fn advance(&mut self, table: &Table, snapshot: &Snapshot) -> Result<()> {
+        self.cell_num += 1;
+        if self.cell_num >= self.node.get_cell_count() {
+            let next_page_num = self.node.get_next_leaf_num();
             if next_page_num == 0 {
-                // This was the rightmost leaf
                 self.end_of_table = true;
             } else {
+                self.node = table.get_pager().get_node_at(next_page_num, snapshot)?;
                 self.page_num = next_page_num;
                 self.cell_num = 0;
             }
         }
+        Ok(())
     }
 
-    pub fn select(&mut self) -> &[u8] {
-        self.table.select(self.page_num, self.cell_num)
+    #[cfg(test)]
+    pub fn get_key(&self) -> Option<Key> {
+        (self.cell_num < self.node.get_cell_count()).then(|| self.node.get_key(self.cell_num))
     }
 
-    //todo: cursor should probably not know about row
-    pub fn insert(&mut self, row: &Row) {
-        self.table.insert(self.page_num, self.cell_num, row);
+    pub fn select(&self, table: &Table, snapshot: &Snapshot) -> Result<Vec<u8>> {
+        let cell = self.node.get_cell(self.cell_num);
+        Ok(table.get_pager().select_at(self.page_num, cell, snapshot)?)
     }
 }
 
 #[cfg(test)]
 mod test {
-    use std::{fs, panic};
-    use crate::storage::pager::Pager;
+    use std::fs;
+
+    use crate::storage::config::ConnectionConfig;
+    use crate::storage::test_util::{delete_rows, insert_rows, open_test_table, open_test_table_with_config};
 
     use super::*;
 
+    // small xorshift, good enough to shuffle test keys without pulling in a dependency
+    fn shuffle(keys: &mut [u32], mut seed: u64) {
+        for i in (1..keys.len()).rev() {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            keys.swap(i, (seed % (i as u64 + 1)) as usize);
+        }
+    }
+
+    fn insert_and_scan(test_db_path: &str, keys: &[u32]) {
+        let mut table = open_test_table(test_db_path);
+        insert_rows(&mut table, keys.iter().copied());
+
+        let mut expected_keys = keys.to_vec();
+        expected_keys.sort();
+
+        let mut scanned_keys = Vec::new();
+        let mut cursor = Cursor::table_start(&mut table).unwrap();
+        while !cursor.is_end_of_table() {
+            let row = Row::deserialize(&cursor.select().unwrap()).unwrap();
+            assert_eq!(row.get_user_name(), format!("user{}", row.get_id()));
+            scanned_keys.push(row.get_id());
+            cursor.advance().unwrap();
+        }
+        drop(cursor);
+        assert_eq!(scanned_keys, expected_keys);
+
+        for &key in keys {
+            let mut cursor = Cursor::table_find(&mut table, &Key::int(key.into())).unwrap();
+            assert_eq!(Row::deserialize(&cursor.select().unwrap()).unwrap().get_id(), key);
+        }
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
     #[test]
     fn test_cursor() {
-        let test_db_path = "test.db";
-        let mut table = Table::open_db_connection(test_db_path).unwrap();
+        let test_db_path = "test_cursor.db";
+        let mut table = open_test_table(test_db_path);
+        let root_page_num = table.get_root_page_num();
         let row = Row::new(1, "test".to_string(), "test".to_string());
-        table.insert(0, 0, &row);
+        table.insert(root_page_num, 0, &row).unwrap();
         table.flush().unwrap();
 
-        let mut cursor = Cursor::table_start(&mut table);
-        assert_eq!(cursor.get_page_num(), 0);
-        assert_eq!(cursor.is_end_of_table(), false);
-        cursor.advance();
-        assert_eq!(cursor.is_end_of_table(), true);
+        let mut cursor = Cursor::table_start(&mut table).unwrap();
+        assert_eq!(cursor.get_page_num(), root_page_num);
+        assert!(!cursor.is_end_of_table());
+        cursor.advance().unwrap();
+        assert!(cursor.is_end_of_table());
+        drop(cursor);
+
+        let mut cursor = Cursor::table_end(&mut table).unwrap();
+        assert_eq!(cursor.get_page_num(), root_page_num);
+        assert!(cursor.is_end_of_table());
+        cursor.advance().unwrap();
+        assert!(cursor.is_end_of_table());
 
-        let mut cursor = Cursor::table_end(&mut table);
-        assert_eq!(cursor.get_page_num(), 0);
-        assert_eq!(cursor.is_end_of_table(), true);
-        cursor.advance();
-        assert_eq!(cursor.is_end_of_table(), true);
+        fs::remove_file(test_db_path).unwrap();
     }
 
     #[test]
     fn test_cursor_find() {
-        let test_db_path = "test.db";
-        let mut table = Table::open_db_connection(test_db_path).unwrap();
+        let test_db_path = "test_cursor_find.db";
+        let mut table = open_test_table(test_db_path);
+        let root_page_num = table.get_root_page_num();
         let row = Row::new(1, "test".to_string(), "test".to_string());
-        table.insert(0, 0, &row);
+        table.insert(root_page_num, 0, &row).unwrap();
         let row = Row::new(2, "test".to_string(), "test".to_string());
-        table.insert(0, 1, &row);
+        table.insert(root_page_num, 1, &row).unwrap();
         let row = Row::new(3, "test".to_string(), "test".to_string());
-        table.insert(0, 2, &row);
+        table.insert(root_page_num, 2, &row).unwrap();
         table.flush().unwrap();
 
-        let mut cursor = Cursor::table_find(&mut table, 2);
-        assert_eq!(cursor.get_page_num(), 0);
+        let mut cursor = Cursor::table_find(&mut table, &Key::int(2)).unwrap();
+        assert_eq!(cursor.get_page_num(), root_page_num);
         assert_eq!(cursor.get_cell_num(), 1);
-        assert_eq!(cursor.is_end_of_table(), false);
-        cursor.advance();
-        assert_eq!(cursor.is_end_of_table(), true);
+        assert!(!cursor.is_end_of_table());
+        cursor.advance().unwrap();
+        assert!(!cursor.is_end_of_table());
+        cursor.advance().unwrap();
+        assert!(cursor.is_end_of_table());
 
         fs::remove_file(test_db_path).expect("Failed to remove test database file");
     }
+
+    #[test]
+    fn test_cursor_scan_ascending_inserts() {
+        let keys: Vec<u32> = (1..=20_000).collect();
+        insert_and_scan("test_cursor_scan_ascending_inserts.db", &keys);
+    }
+
+    #[test]
+    fn test_cursor_scan_descending_inserts() {
+        let keys: Vec<u32> = (1..=20_000).rev().collect();
+        insert_and_scan("test_cursor_scan_descending_inserts.db", &keys);
+    }
+
+    #[test]
+    fn test_cursor_scan_random_inserts() {
+        let mut keys: Vec<u32> = (1..=20_000).collect();
+        shuffle(&mut keys, 0x2545_f491_4f6c_dd1d);
+        insert_and_scan("test_cursor_scan_random_inserts.db", &keys);
+    }
+
+    #[test]
+    fn test_cursor_delete_random() {
+        let test_db_path = "test_cursor_delete_random.db";
+        let mut table = open_test_table(test_db_path);
+        let mut keys: Vec<u32> = (1..=5_000).collect();
+        shuffle(&mut keys, 0x9e37_79b9_7f4a_7c15);
+        insert_rows(&mut table, keys.iter().copied());
+
+        let (deleted_keys, kept_keys) = keys.split_at(keys.len() / 2);
+        for &key in deleted_keys {
+            let mut cursor = Cursor::table_find(&mut table, &Key::int(key.into())).unwrap();
+            assert_eq!(cursor.get_key().unwrap(), Some(Key::int(key.into())));
+            cursor.delete().unwrap();
+        }
+
+        let mut expected_keys = kept_keys.to_vec();
+        expected_keys.sort();
+        let mut scanned_keys = Vec::new();
+        let mut cursor = Cursor::table_start(&mut table).unwrap();
+        while !cursor.is_end_of_table() {
+            scanned_keys.push(Row::deserialize(&cursor.select().unwrap()).unwrap().get_id());
+            cursor.advance().unwrap();
+        }
+        drop(cursor);
+        assert_eq!(scanned_keys, expected_keys);
+
+        for &key in deleted_keys {
+            assert_ne!(Cursor::table_find(&mut table, &Key::int(key.into())).unwrap().get_key().unwrap(), Some(Key::int(key.into())));
+        }
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    // the bounds of an id range as keys
+    fn key_range(range: impl RangeBounds<i64>) -> (Bound<Key>, Bound<Key>) {
+        (range.start_bound().map(|&id| Key::int(id)), range.end_bound().map(|&id| Key::int(id)))
+    }
+
+    fn range_ids(table: &mut Table, range: impl RangeBounds<i64>) -> Vec<u32> {
+        Cursor::range(table, key_range(range)).unwrap().map(|row| row.unwrap().get_id()).collect()
+    }
+
+    #[test]
+    fn test_range_scan() {
+        let test_db_path = "test_range_scan.db";
+        let mut table = open_test_table(test_db_path);
+        // even keys only, bounds can fall between two keys
+        insert_rows(&mut table, (1..=1_000).map(|key| key * 2));
+        // gaps left by deletes, some of the ranges start in one
+        delete_rows(&mut table, (1..=1_000).filter(|key| key % 7 == 0).map(|key| key * 2));
+        let ids: Vec<u32> = (1..=1_000).filter(|key| key % 7 != 0).map(|key| key * 2).collect();
+        let expected = |range: &dyn Fn(u32) -> bool| ids.iter().copied().filter(|&id| range(id)).collect::<Vec<u32>>();
+
+        assert_eq!(range_ids(&mut table, ..), ids);
+        assert_eq!(range_ids(&mut table, 100..=200), expected(&|id| (100..=200).contains(&id)));
+        assert_eq!(range_ids(&mut table, 101..199), expected(&|id| (101..199).contains(&id)));
+        assert_eq!(range_ids(&mut table, (Bound::Excluded(100), Bound::Excluded(200))), expected(&|id| id > 100 && id < 200));
+        assert_eq!(range_ids(&mut table, ..=50), expected(&|id| id <= 50));
+        assert_eq!(range_ids(&mut table, 1_990..), expected(&|id| id >= 1_990));
+        for key in [14, 28, 140, 1_400] {
+            // ranges starting at a deleted key
+            assert_eq!(range_ids(&mut table, key..key + 10), expected(&|id| (key..key + 10).contains(&(id as i64))));
+        }
+        assert_eq!(range_ids(&mut table, 500..=500), vec![500]);
+        assert!(range_ids(&mut table, 501..=501).is_empty());
+        assert!(range_ids(&mut table, 2_001..).is_empty());
+        assert!(range_ids(&mut table, (Bound::Excluded(i64::MAX), Bound::Unbounded)).is_empty());
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_cursor_retreat() {
+        let test_db_path = "test_cursor_retreat.db";
+        let mut table = open_test_table(test_db_path);
+        // enough rows for several levels, with leaves merged away by the deletes
+        insert_rows(&mut table, 1..=5_000);
+        delete_rows(&mut table, (1..=5_000).filter(|key| key % 3 != 0));
+
+        let mut scanned_keys = Vec::new();
+        let mut cursor = Cursor::table_end(&mut table).unwrap();
+        assert!(cursor.is_end_of_table());
+        cursor.retreat().unwrap();
+        while !cursor.is_start_of_table() {
+            scanned_keys.push(cursor.get_key().unwrap().unwrap());
+            cursor.retreat().unwrap();
+        }
+        assert_eq!(scanned_keys, (1..=5_000).rev().filter(|key| key % 3 == 0).map(Key::int).collect::<Vec<_>>());
+
+        // back at the first row, from where the cursor goes forward again
+        cursor.advance().unwrap();
+        assert_eq!(cursor.get_key().unwrap(), Some(Key::int(3)));
+        cursor.advance().unwrap();
+        assert_eq!(cursor.get_key().unwrap(), Some(Key::int(6)));
+        cursor.retreat().unwrap();
+        assert_eq!(cursor.get_key().unwrap(), Some(Key::int(3)));
+        drop(cursor);
+
+        let mut cursor = Cursor::table_find(&mut table, &Key::int(3_000)).unwrap();
+        cursor.retreat().unwrap();
+        assert_eq!(cursor.get_key().unwrap(), Some(Key::int(2_997)));
+        drop(cursor);
+
+        delete_rows(&mut table, (1..=5_000).filter(|key| key % 3 == 0));
+        let mut cursor = Cursor::table_end(&mut table).unwrap();
+        cursor.retreat().unwrap();
+        assert!(cursor.is_start_of_table());
+        assert!(cursor.is_end_of_table());
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_range_scan_backwards() {
+        let test_db_path = "test_range_scan_backwards.db";
+        let mut table = open_test_table(test_db_path);
+        insert_rows(&mut table, (1..=1_000).map(|key| key * 2));
+        let rev_ids = |table: &mut Table, range: (Bound<i64>, Bound<i64>)| -> Vec<u32> {
+            Cursor::range(table, key_range(range)).unwrap().rev().map(|row| row.unwrap().get_id()).collect()
+        };
+
+        assert_eq!(rev_ids(&mut table, (Bound::Unbounded, Bound::Unbounded)), (1..=1_000).rev().map(|key| key * 2).collect::<Vec<_>>());
+        assert_eq!(rev_ids(&mut table, (Bound::Included(100), Bound::Included(110))), vec![110, 108, 106, 104, 102, 100]);
+        assert_eq!(rev_ids(&mut table, (Bound::Excluded(100), Bound::Excluded(110))), vec![108, 106, 104, 102]);
+        assert_eq!(rev_ids(&mut table, (Bound::Included(101), Bound::Included(109))), vec![108, 106, 104, 102]);
+        assert_eq!(rev_ids(&mut table, (Bound::Unbounded, Bound::Excluded(7))), vec![6, 4, 2]);
+        assert_eq!(rev_ids(&mut table, (Bound::Included(1_995), Bound::Unbounded)), vec![2_000, 1_998, 1_996]);
+        assert!(rev_ids(&mut table, (Bound::Included(501), Bound::Included(501))).is_empty());
+        assert!(rev_ids(&mut table, (Bound::Unbounded, Bound::Excluded(2))).is_empty());
+
+        // the two ends meet in the middle and every row is handed out once
+        let mut rows = Cursor::range(&mut table, key_range(100..=120)).unwrap();
+        let mut ids = Vec::new();
+        while let Some(row) = rows.next() {
+            ids.push(row.unwrap().get_id());
+            if let Some(row) = rows.next_back() {
+                ids.push(row.unwrap().get_id());
+            }
+        }
+        assert_eq!(ids, vec![100, 120, 102, 118, 104, 116, 106, 114, 108, 112, 110]);
+        assert!(rows.next_back().is_none());
+        drop(rows);
+
+        let mut rows = Cursor::range(&mut table, key_range(..)).unwrap();
+        assert_eq!(rows.next_back().unwrap().unwrap().get_id(), 2_000);
+        assert_eq!(rows.next().unwrap().unwrap().get_id(), 2);
+        assert_eq!(rows.nth_back(1).unwrap().unwrap().get_id(), 1_996);
+        assert_eq!(rows.count(), 996);
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    // the separator in front of the leaf after the key, on the path of the key
+    fn separator_after(table: &mut Table, key: &Key) -> Option<(usize, usize)> {
+        let mut page_num = table.get_root_page_num();
+        loop {
+            let node = table.get_pager_mut().get_node(page_num).unwrap();
+            if node.get_node_type() != NodeType::Internal {
+                return None;
+            }
+            let index = node.internal_find_child_index_by_key(key);
+            if index < node.get_key_count() && node.internal_get_key(index) == *key {
+                return Some((page_num, index));
+            }
+            page_num = node.internal_node_children(index);
+        }
+    }
+
+    #[test]
+    fn test_range_scan_from_deleted_max_key() {
+        let test_db_path = "test_range_scan_from_deleted_max_key.db";
+        let config = ConnectionConfig::new().with_page_size(1024);
+        let mut table = open_test_table_with_config(test_db_path, &config);
+        insert_rows(&mut table, 1..=3_000);
+        // max keys of leaves, the last ones of rightmost children of their parents
+        for key in [50, 98, 146, 194] {
+            delete_rows(&mut table, std::iter::once(key));
+            let start = i64::from(key);
+            assert_eq!(range_ids(&mut table, start..start + 3), vec![key + 1, key + 2]);
+            assert_eq!(range_ids(&mut table, start..).len(), 3_000 - key as usize);
+        }
+
+        // a separator larger than the max key of its leaf, as older versions left behind on deletes
+        let (page_num, index) = separator_after(&mut table, &Key::int(49)).unwrap();
+        table.get_pager_mut().get_node_mut(page_num).unwrap().internal_set_key(index, &Key::int(50));
+        assert_eq!(range_ids(&mut table, 50..53), vec![51, 52]);
+        assert_eq!(range_ids(&mut table, (Bound::Excluded(49), Bound::Unbounded)).len(), 2_947);
+        assert_eq!(Cursor::range(&mut table, key_range(50..53)).unwrap().rev().count(), 2);
+        table.flush().unwrap();
+        let snapshot = table.pin_snapshot();
+        let cursor = SnapshotCursor::table_find(&table, &snapshot, &Key::int(50)).unwrap();
+        assert_eq!(cursor.get_key(), Some(Key::int(51)));
+        drop(snapshot);
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    fn snapshot_scan(table: &Table, snapshot: &Snapshot) -> Vec<u32> {
+        let mut keys = Vec::new();
+        let mut cursor = SnapshotCursor::table_start(table, snapshot).unwrap();
+        while !cursor.is_end_of_table() {
+            keys.push(Row::deserialize(&cursor.select(table).unwrap()).unwrap().get_id());
+            cursor.advance(table).unwrap();
+        }
+        keys
+    }
+
+    #[test]
+    fn test_snapshot_ignores_later_changes() {
+        let test_db_path = "test_snapshot_ignores_later_changes.db";
+        let mut table = open_test_table(test_db_path);
+        insert_rows(&mut table, 1..=2_000);
+        table.flush().unwrap();
+
+        let snapshot = table.begin_snapshot().unwrap();
+        // committed changes, splits and merges included
+        insert_rows(&mut table, 2_001..=4_000);
+        delete_rows(&mut table, 1..=1_000);
+        table.flush().unwrap();
+        // and uncommitted ones
+        insert_rows(&mut table, 4_001..=4_100);
+        assert_eq!(snapshot_scan(&table, &snapshot), (1..=2_000).collect::<Vec<_>>());
+
+        let cursor = SnapshotCursor::table_find(&table, &snapshot, &Key::int(1_500)).unwrap();
+        assert_eq!(cursor.get_key(), Some(Key::int(1_500)));
+        table.end_snapshot(snapshot);
+
+        table.flush().unwrap();
+        let snapshot = table.begin_snapshot().unwrap();
+        assert_eq!(snapshot_scan(&table, &snapshot), (1_001..=4_100).collect::<Vec<_>>());
+        table.end_snapshot(snapshot);
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_scan_interleaved_with_writes() {
+        let test_db_path = "test_snapshot_scan_interleaved_with_writes.db";
+        let mut table = open_test_table(test_db_path);
+        insert_rows(&mut table, (2..=6_000).step_by(2));
+        table.flush().unwrap();
+
+        let snapshot = table.begin_snapshot().unwrap();
+        let mut cursor = SnapshotCursor::table_start(&table, &snapshot).unwrap();
+        let mut scanned_keys = Vec::new();
+        let mut next_key = 1;
+        while !cursor.is_end_of_table() {
+            scanned_keys.push(Row::deserialize(&cursor.select(&table).unwrap()).unwrap().get_id());
+            cursor.advance(&table).unwrap();
+
+            // the writer fills the gaps and drops rows ahead of the reader, committing as it goes
+            insert_rows(&mut table, next_key..next_key + 1);
+            next_key += 2;
+            if next_key % 20 == 1 && next_key + 1_001 <= 6_000 {
+                delete_rows(&mut table, next_key + 1_001..next_key + 1_002);
+                table.flush().unwrap();
+            }
+        }
+        assert_eq!(scanned_keys, (2..=6_000).step_by(2).collect::<Vec<_>>());
+        table.end_snapshot(snapshot);
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_holds_back_checkpoint() {
+        let test_db_path = "test_snapshot_holds_back_checkpoint.db";
+        let mut table = open_test_table(test_db_path);
+        insert_rows(&mut table, 1..=500);
+        table.flush().unwrap();
+
+        let snapshot = table.begin_snapshot().unwrap();
+        // enough commits to pass the auto checkpoint threshold several times over
+        for key in 501..=3_000 {
+            insert_rows(&mut table, key..key + 1);
+            table.flush().unwrap();
+        }
+        assert_eq!(snapshot_scan(&table, &snapshot), (1..=500).collect::<Vec<_>>());
+        table.checkpoint().unwrap();
+        assert!(table.get_pager().get_wal_frame_count() > 0);
+        assert_eq!(snapshot_scan(&table, &snapshot), (1..=500).collect::<Vec<_>>());
+
+        // once the reader is gone the whole log can be copied back
+        table.end_snapshot(snapshot);
+        table.checkpoint().unwrap();
+        assert_eq!(table.get_pager().get_wal_frame_count(), 0);
+        let snapshot = table.begin_snapshot().unwrap();
+        assert_eq!(snapshot_scan(&table, &snapshot), (1..=3_000).collect::<Vec<_>>());
+        table.end_snapshot(snapshot);
+
+        fs::remove_file(test_db_path).unwrap();
+    }
 }
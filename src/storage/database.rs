@@ -0,0 +1,207 @@
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use anyhow::Result;
+
+use crate::storage::config::ConnectionConfig;
+use crate::storage::cursor::SnapshotPosition;
+use crate::storage::key::Key;
+use crate::storage::pager::Snapshot;
+use crate::storage::table::Table;
+
+// Shared handle to a database, cheap to clone and safe to send to other threads.
+// Writers lock the whole table through write. Read cursors scan a snapshot and only hold the read lock
+// while they load a page, so any number of them can be open next to a writer
+#[derive(Clone)]
+pub struct Database {
+    table: Arc<RwLock<Table>>,
+}
+
+impl Database {
+    #[cfg(test)]
+    pub fn open(file_path: &str) -> Result<Self> {
+        Self::open_with_config(file_path, &ConnectionConfig::default())
+    }
+
+    pub fn open_with_config(file_path: &str, config: &ConnectionConfig) -> Result<Self> {
+        Ok(Self {
+            table: Arc::new(RwLock::new(Table::open_db_connection_with_config(file_path, config)?)),
+        })
+    }
+
+    // exclusive access, waits for the other writer and for readers in the middle of a page load
+    pub fn write(&self) -> Result<RwLockWriteGuard<'_, Table>> {
+        self.table.write().map_err(|_| anyhow::anyhow!("Database lock is poisoned"))
+    }
+
+    fn read(&self) -> Result<RwLockReadGuard<'_, Table>> {
+        self.table.read().map_err(|_| anyhow::anyhow!("Database lock is poisoned"))
+    }
+
+    // cursor over the last committed version of the table
+    pub fn read_cursor(&self) -> Result<ReadCursor> {
+        self.read_cursor_at(&Key::min())
+    }
+
+    // cursor at the first key not less than the given one. Catches up with the commits of other connections
+    // first, unless a writer holds the table: the reader does not wait for it and sees the last commit instead
+    pub fn read_cursor_at(&self, key: &Key) -> Result<ReadCursor> {
+        if let Ok(mut table) = self.table.try_write() {
+            table.get_pager_mut().refresh()?;
+        }
+        let table = self.read()?;
+        let snapshot = table.pin_snapshot();
+        let position = SnapshotPosition::find(&table, &snapshot, key)?;
+        drop(table);
+        Ok(ReadCursor {
+            database: self.clone(),
+            snapshot,
+            position,
+        })
+    }
+}
+
+// Owned read cursor of a Database, its snapshot ends when it is dropped. Dropping it takes no lock
+pub struct ReadCursor {
+    database: Database,
+    snapshot: Snapshot,
+    position: SnapshotPosition,
+}
+
+impl ReadCursor {
+    pub fn is_end_of_table(&self) -> bool {
+        self.position.is_end_of_table()
+    }
+
+    pub fn advance(&mut self) -> Result<()> {
+        let table = self.database.read()?;
+        self.position.advance(&table, &self.snapshot)
+    }
+
+    #[cfg(test)]
+    pub fn get_key(&self) -> Option<Key> {
+        self.position.get_key()
+    }
+
+    pub fn select(&self) -> Result<Vec<u8>> {
+        let table = self.database.read()?;
+        self.position.select(&table, &self.snapshot)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::thread;
+
+    use crate::storage::cursor::Cursor;
+    use crate::storage::row::Row;
+    use crate::storage::test_util;
+
+    use super::*;
+
+    fn insert_rows(database: &Database, keys: impl Iterator<Item = u32>) {
+        let mut table = database.write().unwrap();
+        test_util::insert_rows(&mut table, keys);
+        table.flush().unwrap();
+    }
+
+    fn scan_ids(mut cursor: ReadCursor) -> Vec<u32> {
+        let mut ids = Vec::new();
+        while !cursor.is_end_of_table() {
+            ids.push(Row::deserialize(&cursor.select().unwrap()).unwrap().get_id());
+            cursor.advance().unwrap();
+        }
+        ids
+    }
+
+    #[test]
+    fn test_database_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Database>();
+        assert_send_sync::<ReadCursor>();
+    }
+
+    #[test]
+    fn test_many_open_read_cursors() {
+        let test_db_path = "test_many_open_read_cursors.db";
+        let _ = fs::remove_file(test_db_path);
+        let database = Database::open(test_db_path).unwrap();
+        insert_rows(&database, 1..=1_000);
+
+        let mut first = database.read_cursor().unwrap();
+        let second = database.read_cursor_at(&Key::int(500)).unwrap();
+        insert_rows(&database, 1_001..=2_000);
+        let third = database.read_cursor().unwrap();
+
+        first.advance().unwrap();
+        assert_eq!(first.get_key(), Some(Key::int(2)));
+        assert_eq!(second.get_key(), Some(Key::int(500)));
+        assert_eq!(scan_ids(second), (500..=1_000).collect::<Vec<_>>());
+        assert_eq!(scan_ids(third), (1..=2_000).collect::<Vec<_>>());
+        assert_eq!(scan_ids(first), (2..=1_000).collect::<Vec<_>>());
+
+        // every snapshot has ended, the log can be emptied
+        database.write().unwrap().checkpoint().unwrap();
+        assert_eq!(database.write().unwrap().get_pager().get_wal_frame_count(), 0);
+
+        drop(database);
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_read_cursor_dropped_by_writer() {
+        let test_db_path = "test_read_cursor_dropped_by_writer.db";
+        let _ = fs::remove_file(test_db_path);
+        let database = Database::open(test_db_path).unwrap();
+        insert_rows(&database, 1..=100);
+
+        let cursor = database.read_cursor().unwrap();
+        let mut table = database.write().unwrap();
+        let row = Row::new(101, "user101".to_string(), "user101@mail.com".to_string());
+        Cursor::table_find(&mut table, &Key::int(101)).unwrap().insert(&row).unwrap();
+        // ends the snapshot without waiting for the write lock this thread holds
+        drop(cursor);
+        table.flush().unwrap();
+        table.checkpoint().unwrap();
+        assert_eq!(table.get_pager().get_wal_frame_count(), 0);
+        drop(table);
+
+        assert_eq!(scan_ids(database.read_cursor().unwrap()), (1..=101).collect::<Vec<_>>());
+
+        drop(database);
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_readers_on_other_threads() {
+        let test_db_path = "test_readers_on_other_threads.db";
+        let _ = fs::remove_file(test_db_path);
+        let database = Database::open(test_db_path).unwrap();
+        insert_rows(&database, 1..=100);
+
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let database = database.clone();
+                thread::spawn(move || {
+                    for _ in 0..20 {
+                        // writes are committed in batches of whole hundreds, a snapshot never sees a partial one
+                        let ids = scan_ids(database.read_cursor().unwrap());
+                        assert_eq!(ids.len() % 100, 0);
+                        assert_eq!(ids, (1..=ids.len() as u32).collect::<Vec<_>>());
+                    }
+                })
+            })
+            .collect();
+
+        for batch in 1..30 {
+            insert_rows(&database, batch * 100 + 1..=(batch + 1) * 100);
+        }
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        assert_eq!(scan_ids(database.read_cursor().unwrap()), (1..=3_000).collect::<Vec<_>>());
+
+        drop(database);
+        fs::remove_file(test_db_path).unwrap();
+    }
+}
@@ -0,0 +1,105 @@
+use std::fmt::{Display, Formatter};
+use std::io;
+
+use crate::storage::node::NodeType;
+
+// Errors raised while reading pages back from the database file. Every variant except Io, Locked and
+// ConstraintViolation means the file is corrupted, they carry the page num so the damaged page can be located
+#[derive(Debug)]
+pub enum StorageError {
+    // the checksum stored in the page does not match its content
+    ChecksumMismatch { page_num: usize, stored: u32, computed: u32 },
+    // the file ends in the middle of the page, only len bytes could be read
+    TornPage { page_num: usize, len: usize, page_size: usize },
+    InvalidNodeType { page_num: usize, node_type: u8 },
+    CellCountOutOfRange { page_num: usize, cell_count: usize, max_cell_count: usize },
+    // a slot of a leaf points outside of the cell heap
+    InvalidCellPointer { page_num: usize },
+    // a key of the page is longer than MAX_KEY_SIZE
+    InvalidKey { page_num: usize },
+    // the overflow chain of a cell in the page ends early or runs into a page that is not an overflow page
+    InvalidOverflowChain { page_num: usize },
+    // a child, leaf link or free list pointer to the header page or past the end of the file
+    PageOutOfRange { page_num: usize, page_count: usize },
+    // a free or overflow page is reached through a child or leaf link
+    MisplacedPage { page_num: usize, node_type: NodeType },
+    // another connection holds a conflicting lock and the busy timeout ran out
+    Locked,
+    // a statement would store a value twice in a column that has to be unique, nothing was changed
+    ConstraintViolation { column: String, value: String },
+    Io(io::Error),
+}
+
+impl Display for StorageError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageError::ChecksumMismatch { page_num, stored, computed } => write!(
+                f,
+                "Page {} is corrupted: stored checksum {:#010x} does not match computed checksum {:#010x}",
+                page_num, stored, computed
+            ),
+            StorageError::TornPage { page_num, len, page_size } => write!(
+                f,
+                "Page {} is torn: read {} of {} bytes",
+                page_num, len, page_size
+            ),
+            StorageError::InvalidNodeType { page_num, node_type } => write!(
+                f,
+                "Page {} is corrupted: invalid node type {}",
+                page_num, node_type
+            ),
+            StorageError::CellCountOutOfRange { page_num, cell_count, max_cell_count } => write!(
+                f,
+                "Page {} is corrupted: cell count {} is larger than {}",
+                page_num, cell_count, max_cell_count
+            ),
+            StorageError::InvalidCellPointer { page_num } => write!(
+                f,
+                "Page {} is corrupted: cell pointers do not match the cell heap",
+                page_num
+            ),
+            StorageError::InvalidKey { page_num } => write!(
+                f,
+                "Page {} is corrupted: key length out of range",
+                page_num
+            ),
+            StorageError::InvalidOverflowChain { page_num } => write!(
+                f,
+                "Page {} is corrupted: overflow chain does not match the length of its value",
+                page_num
+            ),
+            StorageError::PageOutOfRange { page_num, page_count } => write!(
+                f,
+                "Page {} is corrupted: page num out of range of {} pages",
+                page_num, page_count
+            ),
+            StorageError::MisplacedPage { page_num, node_type } => write!(
+                f,
+                "{:?} page {} is linked into the tree",
+                node_type, page_num
+            ),
+            StorageError::Locked => write!(f, "Database is locked"),
+            StorageError::ConstraintViolation { column, value } => write!(
+                f,
+                "UNIQUE constraint failed: {} = {} already exists",
+                column, value
+            ),
+            StorageError::Io(err) => write!(f, "I/O error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StorageError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for StorageError {
+    fn from(err: io::Error) -> Self {
+        StorageError::Io(err)
+    }
+}
@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+use std::fs::TryLockError;
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::storage::vfs::{read_up_to, LockKind, MemoryVfs, Vfs, VfsFile};
+
+// In-memory backend for crash tests. Every write, truncate and sync is an I/O step, the step set by
+// fail_at_step fails and takes the backend down with it: nothing works anymore, like in a process that
+// died. restart hands out the files as they would be found after the crash, with the changes that were
+// not synced yet kept, dropped or torn. Creating and removing files is durable right away
+#[derive(Clone, Debug, Default)]
+pub(crate) struct FaultVfs {
+    files: MemoryVfs,
+    state: Arc<Mutex<FaultState>>,
+}
+
+// what is left of the writes that were not synced when the crash hit
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Crash {
+    // only the process died, the operating system still wrote them back
+    Process,
+    // power loss, none of them made it to disk
+    PowerLoss,
+    // power loss while writing them back, every write only got its first half to disk
+    TornWrites,
+}
+
+#[derive(Debug, Default)]
+struct FaultState {
+    // content of every file as of its last sync
+    durable: HashMap<String, Vec<u8>>,
+    // writes and truncates since the last sync of the file, oldest first
+    unsynced: HashMap<String, Vec<Change>>,
+    step_count: usize,
+    fail_at: Option<usize>,
+    failed: bool,
+    short_reads: bool,
+}
+
+#[derive(Debug)]
+enum Change {
+    Write(u64, Vec<u8>),
+    Truncate(u64),
+}
+
+impl FaultState {
+    fn check_alive(&self) -> io::Result<()> {
+        match self.failed {
+            true => Err(io::Error::other("Storage is gone after an injected failure")),
+            false => Ok(()),
+        }
+    }
+
+    fn next_step(&mut self) -> io::Result<()> {
+        self.check_alive()?;
+        let step = self.step_count;
+        self.step_count += 1;
+        if self.fail_at == Some(step) {
+            self.failed = true;
+            return Err(io::Error::other(format!("Injected failure of I/O step {}", step)));
+        }
+        Ok(())
+    }
+}
+
+impl FaultVfs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // reads return at most half of what was asked for, callers have to keep reading
+    pub fn with_short_reads(self) -> Self {
+        self.get_state().short_reads = true;
+        self
+    }
+
+    // the step with this index, counted from 0 since the backend was created, fails
+    pub fn fail_at_step(&self, step: usize) {
+        self.get_state().fail_at = Some(step);
+    }
+
+    pub fn get_step_count(&self) -> usize {
+        self.get_state().step_count
+    }
+
+    pub fn has_failed(&self) -> bool {
+        self.get_state().failed
+    }
+
+    // crashes this backend if it is still up and returns a fresh one with the files left on disk
+    pub fn restart(&self, crash: Crash) -> FaultVfs {
+        let mut state = self.get_state();
+        state.failed = true;
+        let restarted = FaultVfs::new();
+        for (path, durable) in &state.durable {
+            let mut content = durable.clone();
+            for change in state.unsynced.get(path).into_iter().flatten() {
+                match (crash, change) {
+                    (Crash::PowerLoss, _) => break,
+                    (_, Change::Truncate(len)) => content.resize(*len as usize, 0),
+                    (Crash::Process, Change::Write(offset, bytes)) => write_into(&mut content, *offset, bytes),
+                    (Crash::TornWrites, Change::Write(offset, bytes)) => {
+                        write_into(&mut content, *offset, &bytes[..bytes.len() / 2])
+                    }
+                }
+            }
+            restarted.files.open(path).unwrap().write_at(&content, 0).unwrap();
+            restarted.get_state().durable.insert(path.clone(), content);
+        }
+        restarted
+    }
+
+    fn get_state(&self) -> MutexGuard<'_, FaultState> {
+        lock_state(&self.state)
+    }
+}
+
+fn lock_state(state: &Mutex<FaultState>) -> MutexGuard<'_, FaultState> {
+    state.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+fn write_into(content: &mut Vec<u8>, offset: u64, bytes: &[u8]) {
+    let end = offset as usize + bytes.len();
+    if content.len() < end {
+        content.resize(end, 0);
+    }
+    content[offset as usize..end].copy_from_slice(bytes);
+}
+
+impl Vfs for FaultVfs {
+    fn open(&self, path: &str) -> io::Result<Box<dyn VfsFile>> {
+        let mut state = self.get_state();
+        state.check_alive()?;
+        let file = self.files.open(path)?;
+        state.durable.entry(path.to_string()).or_default();
+        Ok(Box::new(FaultFile {
+            file,
+            path: path.to_string(),
+            state: self.state.clone(),
+        }))
+    }
+
+    fn delete(&self, path: &str) -> io::Result<()> {
+        let mut state = self.get_state();
+        state.check_alive()?;
+        self.files.delete(path)?;
+        state.durable.remove(path);
+        state.unsynced.remove(path);
+        Ok(())
+    }
+
+    fn exists(&self, path: &str) -> io::Result<bool> {
+        self.get_state().check_alive()?;
+        self.files.exists(path)
+    }
+
+    fn sync_dir(&self, _path: &str) -> io::Result<()> {
+        self.get_state().next_step()
+    }
+}
+
+struct FaultFile {
+    file: Box<dyn VfsFile>,
+    path: String,
+    state: Arc<Mutex<FaultState>>,
+}
+
+impl VfsFile for FaultFile {
+    fn read_at(&self, buffer: &mut [u8], offset: u64) -> io::Result<usize> {
+        let state = lock_state(&self.state);
+        state.check_alive()?;
+        let len = match state.short_reads {
+            true => buffer.len().div_ceil(2),
+            false => buffer.len(),
+        };
+        self.file.read_at(&mut buffer[..len], offset)
+    }
+
+    fn write_at(&self, buffer: &[u8], offset: u64) -> io::Result<()> {
+        let mut state = lock_state(&self.state);
+        state.next_step()?;
+        self.file.write_at(buffer, offset)?;
+        state.unsynced.entry(self.path.clone()).or_default().push(Change::Write(offset, buffer.to_vec()));
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        lock_state(&self.state).check_alive()?;
+        self.file.size()
+    }
+
+    fn truncate(&self, len: u64) -> io::Result<()> {
+        let mut state = lock_state(&self.state);
+        state.next_step()?;
+        self.file.truncate(len)?;
+        state.unsynced.entry(self.path.clone()).or_default().push(Change::Truncate(len));
+        Ok(())
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        let mut state = lock_state(&self.state);
+        state.next_step()?;
+        let content = read_up_to(&*self.file, 0, self.file.size()? as usize)?;
+        state.durable.insert(self.path.clone(), content);
+        state.unsynced.remove(&self.path);
+        Ok(())
+    }
+
+    fn try_lock(&self, kind: LockKind) -> Result<(), TryLockError> {
+        lock_state(&self.state).check_alive().map_err(TryLockError::Error)?;
+        self.file.try_lock(kind)
+    }
+
+    fn unlock(&self) -> io::Result<()> {
+        lock_state(&self.state).check_alive()?;
+        self.file.unlock()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn content(vfs: &FaultVfs, path: &str) -> Vec<u8> {
+        let file = vfs.open(path).unwrap();
+        read_up_to(&*file, 0, file.size().unwrap() as usize).unwrap()
+    }
+
+    #[test]
+    fn test_unsynced_writes_on_restart() {
+        let vfs = FaultVfs::new();
+        let file = vfs.open("file").unwrap();
+        file.write_at(b"synced", 0).unwrap();
+        file.sync().unwrap();
+        file.write_at(b"unsynced", 6).unwrap();
+        file.truncate(10).unwrap();
+
+        assert_eq!(content(&vfs.restart(Crash::Process), "file"), b"syncedunsy");
+        assert_eq!(content(&vfs.restart(Crash::PowerLoss), "file"), b"synced");
+        assert_eq!(content(&vfs.restart(Crash::TornWrites), "file"), b"syncedunsy");
+        // the crashed backend is gone
+        assert!(file.size().is_err());
+        assert!(vfs.open("file").is_err());
+    }
+
+    #[test]
+    fn test_torn_write_keeps_its_first_half() {
+        let vfs = FaultVfs::new();
+        vfs.open("file").unwrap().write_at(b"abcdef", 2).unwrap();
+        assert_eq!(content(&vfs.restart(Crash::TornWrites), "file"), b"\0\0abc");
+    }
+
+    #[test]
+    fn test_failed_step_takes_the_backend_down() {
+        let vfs = FaultVfs::new().with_short_reads();
+        vfs.fail_at_step(2);
+        let file = vfs.open("file").unwrap();
+        file.write_at(b"first", 0).unwrap();
+        file.sync().unwrap();
+        assert!(!vfs.has_failed());
+        let mut buffer = [0; 5];
+        assert_eq!(file.read_at(&mut buffer, 0).unwrap(), 3);
+        assert_eq!(read_up_to(&*file, 0, 5).unwrap(), b"first");
+
+        assert!(file.write_at(b"second", 5).is_err());
+        assert!(vfs.has_failed());
+        assert!(file.sync().is_err());
+        assert_eq!(vfs.get_step_count(), 3);
+
+        assert_eq!(content(&vfs.restart(Crash::Process), "file"), b"first");
+    }
+}
@@ -0,0 +1,251 @@
+use anyhow::{bail, Result};
+
+use crate::storage::index::{Index, IndexedColumn};
+use crate::storage::layout::is_valid_page_size;
+
+// Database header, stored in the first page of the file. Integers are little endian.
+//
+//   0..16    magic string, identifies a simple_db file
+//   16..20   format version, u32
+//   20..24   page size in bytes, u32. Fixed when the database is created
+//   24..32   root page num, u64
+//   32..40   page count including the header page, u64
+//   40..48   first page of the free list, 0 if the list is empty, u64
+//   48..56   schema version, bumped on every schema change, u64
+//   56..58   index count, u16
+//   58..     the indexes, 10 bytes each: indexed column, u8, flags, u8, and root page num, u64.
+//            Flag bit 0 marks a unique index, the other bits are reserved and zero
+//
+// The rest of the page is zero padding.
+pub const MAGIC: &[u8; MAGIC_SIZE] = b"simple_db file\0\0";
+// 2 added the previous leaf link to leaf nodes, 3 stores keys as encoded byte strings, 4 added indexes
+pub const FORMAT_VERSION: u32 = 4;
+pub const HEADER_PAGE_NUM: usize = 0;
+
+const MAGIC_SIZE: usize = 16;
+const MAGIC_OFFSET: usize = 0;
+const FORMAT_VERSION_OFFSET: usize = MAGIC_OFFSET + MAGIC_SIZE;
+const PAGE_SIZE_OFFSET: usize = FORMAT_VERSION_OFFSET + std::mem::size_of::<u32>();
+const ROOT_PAGE_NUM_OFFSET: usize = PAGE_SIZE_OFFSET + std::mem::size_of::<u32>();
+const PAGE_COUNT_OFFSET: usize = ROOT_PAGE_NUM_OFFSET + std::mem::size_of::<u64>();
+const FREE_LIST_HEAD_OFFSET: usize = PAGE_COUNT_OFFSET + std::mem::size_of::<u64>();
+const SCHEMA_VERSION_OFFSET: usize = FREE_LIST_HEAD_OFFSET + std::mem::size_of::<u64>();
+const INDEX_COUNT_OFFSET: usize = SCHEMA_VERSION_OFFSET + std::mem::size_of::<u64>();
+const INDEXES_OFFSET: usize = INDEX_COUNT_OFFSET + std::mem::size_of::<u16>();
+const INDEX_SIZE: usize = 2 * std::mem::size_of::<u8>() + std::mem::size_of::<u64>();
+const UNIQUE_INDEX_FLAG: u8 = 1;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DatabaseHeader {
+    pub(crate) page_size: usize,
+    pub(crate) root_page_num: usize,
+    pub(crate) page_count: usize,
+    pub(crate) free_list_head: usize,
+    pub(crate) schema_version: u64,
+    pub(crate) indexes: Vec<Index>,
+}
+
+impl DatabaseHeader {
+    // header of a fresh database: the header page followed by an empty root leaf
+    pub fn new(page_size: usize) -> Self {
+        Self {
+            page_size,
+            root_page_num: HEADER_PAGE_NUM + 1,
+            page_count: HEADER_PAGE_NUM + 2,
+            free_list_head: 0,
+            schema_version: 0,
+            indexes: Vec::new(),
+        }
+    }
+
+    pub fn get_root_page_num(&self) -> usize {
+        self.root_page_num
+    }
+
+    pub fn get_page_size(&self) -> usize {
+        self.page_size
+    }
+
+    pub fn get_page_count(&self) -> usize {
+        self.page_count
+    }
+
+    #[cfg(test)]
+    pub fn get_schema_version(&self) -> u64 {
+        self.schema_version
+    }
+
+    pub fn get_indexes(&self) -> &[Index] {
+        &self.indexes
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = vec![0; self.page_size];
+        bytes[MAGIC_OFFSET..MAGIC_OFFSET + MAGIC_SIZE].copy_from_slice(MAGIC);
+        write_u32(&mut bytes, FORMAT_VERSION_OFFSET, FORMAT_VERSION);
+        write_u32(&mut bytes, PAGE_SIZE_OFFSET, self.page_size as u32);
+        write_u64(&mut bytes, ROOT_PAGE_NUM_OFFSET, self.root_page_num as u64);
+        write_u64(&mut bytes, PAGE_COUNT_OFFSET, self.page_count as u64);
+        write_u64(&mut bytes, FREE_LIST_HEAD_OFFSET, self.free_list_head as u64);
+        write_u64(&mut bytes, SCHEMA_VERSION_OFFSET, self.schema_version);
+        bytes[INDEX_COUNT_OFFSET..INDEXES_OFFSET].copy_from_slice(&(self.indexes.len() as u16).to_le_bytes());
+        for (i, index) in self.indexes.iter().enumerate() {
+            let offset = INDEXES_OFFSET + i * INDEX_SIZE;
+            bytes[offset] = index.get_column().get_id();
+            bytes[offset + 1] = if index.is_unique() { UNIQUE_INDEX_FLAG } else { 0 };
+            write_u64(&mut bytes, offset + 2, index.get_root_page_num() as u64);
+        }
+        bytes
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < INDEXES_OFFSET
+            || &bytes[MAGIC_OFFSET..MAGIC_OFFSET + MAGIC_SIZE] != MAGIC
+        {
+            bail!("File is not a simple_db database");
+        }
+
+        let format_version = read_u32(bytes, FORMAT_VERSION_OFFSET);
+        if format_version > FORMAT_VERSION {
+            bail!(
+                "Database file format version {} is newer than the supported version {}",
+                format_version,
+                FORMAT_VERSION
+            );
+        }
+        if format_version < FORMAT_VERSION {
+            bail!(
+                "Database file format version {} is older than the supported version {}",
+                format_version,
+                FORMAT_VERSION
+            );
+        }
+
+        let mut header = Self {
+            page_size: read_u32(bytes, PAGE_SIZE_OFFSET) as usize,
+            root_page_num: read_u64(bytes, ROOT_PAGE_NUM_OFFSET) as usize,
+            page_count: read_u64(bytes, PAGE_COUNT_OFFSET) as usize,
+            free_list_head: read_u64(bytes, FREE_LIST_HEAD_OFFSET) as usize,
+            schema_version: read_u64(bytes, SCHEMA_VERSION_OFFSET),
+            indexes: Vec::new(),
+        };
+
+        if !is_valid_page_size(header.page_size) {
+            bail!("Database page size {} is not supported", header.page_size);
+        }
+        if header.root_page_num == HEADER_PAGE_NUM || header.root_page_num >= header.page_count {
+            bail!("Database header is corrupted: root page {} out of {} pages", header.root_page_num, header.page_count);
+        }
+        if header.free_list_head >= header.page_count {
+            bail!("Database header is corrupted: free list head {} out of {} pages", header.free_list_head, header.page_count);
+        }
+
+        let index_count = u16::from_le_bytes(bytes[INDEX_COUNT_OFFSET..INDEXES_OFFSET].try_into().unwrap()) as usize;
+        if INDEXES_OFFSET + index_count * INDEX_SIZE > bytes.len() {
+            bail!("Database header is corrupted: {} indexes do not fit into the header", index_count);
+        }
+        for i in 0..index_count {
+            let offset = INDEXES_OFFSET + i * INDEX_SIZE;
+            let Some(column) = IndexedColumn::from_id(bytes[offset]) else {
+                bail!("Database header is corrupted: unknown indexed column {}", bytes[offset]);
+            };
+            let root_page_num = read_u64(bytes, offset + 2) as usize;
+            if root_page_num == HEADER_PAGE_NUM || root_page_num >= header.page_count {
+                bail!("Database header is corrupted: index root page {} out of {} pages", root_page_num, header.page_count);
+            }
+            header.indexes.push(Index::new(column, root_page_num, bytes[offset + 1] & UNIQUE_INDEX_FLAG != 0));
+        }
+
+        Ok(header)
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fn write_u64(bytes: &mut [u8], offset: usize, value: u64) {
+    bytes[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod test {
+    use crate::storage::constant::DEFAULT_PAGE_SIZE;
+
+    use super::*;
+
+    #[test]
+    fn test_header_round_trip() {
+        let header = DatabaseHeader {
+            page_size: 8192,
+            root_page_num: 1,
+            page_count: 42,
+            free_list_head: 17,
+            schema_version: 3,
+            indexes: vec![Index::new(IndexedColumn::Email, 5, true), Index::new(IndexedColumn::UserName, 40, false)],
+        };
+        assert_eq!(DatabaseHeader::deserialize(&header.serialize()).unwrap(), header);
+    }
+
+    #[test]
+    fn test_foreign_file_is_rejected() {
+        let mut bytes = DatabaseHeader::new(DEFAULT_PAGE_SIZE).serialize();
+        bytes[..4].copy_from_slice(b"SQLi");
+        let err = DatabaseHeader::deserialize(&bytes).unwrap_err();
+        assert!(err.to_string().contains("not a simple_db database"));
+
+        assert!(DatabaseHeader::deserialize(&[0; 8]).is_err());
+    }
+
+    #[test]
+    fn test_newer_format_version_is_rejected() {
+        let mut bytes = DatabaseHeader::new(DEFAULT_PAGE_SIZE).serialize();
+        write_u32(&mut bytes, FORMAT_VERSION_OFFSET, FORMAT_VERSION + 1);
+        let err = DatabaseHeader::deserialize(&bytes).unwrap_err();
+        assert!(err.to_string().contains("newer than the supported version"));
+    }
+
+    #[test]
+    fn test_older_format_version_is_rejected() {
+        let mut bytes = DatabaseHeader::new(DEFAULT_PAGE_SIZE).serialize();
+        write_u32(&mut bytes, FORMAT_VERSION_OFFSET, FORMAT_VERSION - 1);
+        let err = DatabaseHeader::deserialize(&bytes).unwrap_err();
+        assert!(err.to_string().contains("older than the supported version"));
+    }
+
+    #[test]
+    fn test_corrupted_root_page_num_is_rejected() {
+        let mut header = DatabaseHeader::new(DEFAULT_PAGE_SIZE);
+        header.root_page_num = header.page_count;
+        assert!(DatabaseHeader::deserialize(&header.serialize()).is_err());
+    }
+
+    #[test]
+    fn test_corrupted_index_is_rejected() {
+        let mut header = DatabaseHeader::new(DEFAULT_PAGE_SIZE);
+        header.indexes.push(Index::new(IndexedColumn::Email, header.page_count, false));
+        let err = DatabaseHeader::deserialize(&header.serialize()).unwrap_err();
+        assert!(err.to_string().contains("index root page"));
+
+        let mut bytes = DatabaseHeader::new(DEFAULT_PAGE_SIZE).serialize();
+        bytes[INDEX_COUNT_OFFSET] = 1;
+        let err = DatabaseHeader::deserialize(&bytes).unwrap_err();
+        assert!(err.to_string().contains("unknown indexed column 0"));
+    }
+
+    #[test]
+    fn test_unsupported_page_size_is_rejected() {
+        let mut header = DatabaseHeader::new(DEFAULT_PAGE_SIZE);
+        header.page_size = 1000;
+        let err = DatabaseHeader::deserialize(&header.serialize()).unwrap_err();
+        assert!(err.to_string().contains("page size 1000 is not supported"));
+    }
+}
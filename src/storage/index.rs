@@ -0,0 +1,151 @@
+use std::ops::RangeInclusive;
+
+use anyhow::Result;
+
+use crate::storage::constant::MAX_KEY_SIZE;
+use crate::storage::key::{Key, KeyValue};
+use crate::storage::row::Row;
+
+// the tag and the length of the text column and the tagged id column take the rest of an entry key
+const MAX_INDEXED_VALUE_SIZE: usize = MAX_KEY_SIZE - 3 - 9;
+
+// A column of the rows that can be indexed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexedColumn {
+    UserName,
+    Email,
+}
+
+impl IndexedColumn {
+    pub fn parse(name: &str) -> Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "user_name" => Ok(IndexedColumn::UserName),
+            "email" => Ok(IndexedColumn::Email),
+            _ => anyhow::bail!("Unknown column {}, expected user_name or email", name),
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            IndexedColumn::UserName => "user_name",
+            IndexedColumn::Email => "email",
+        }
+    }
+
+    pub fn get_value<'r>(&self, row: &'r Row) -> &'r str {
+        match self {
+            IndexedColumn::UserName => row.get_user_name(),
+            IndexedColumn::Email => row.get_email(),
+        }
+    }
+
+    // how the column is stored in the database header
+    pub(crate) fn get_id(&self) -> u8 {
+        match self {
+            IndexedColumn::UserName => 1,
+            IndexedColumn::Email => 2,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(IndexedColumn::UserName),
+            2 => Some(IndexedColumn::Email),
+            _ => None,
+        }
+    }
+}
+
+// Secondary index on a column, a tree of its own next to the table. Its entries are keyed by the value
+// of the column followed by the id, so equal values are kept apart, and hold the key of the row.
+// Values longer than an entry key allows are cut short, a lookup compares the rows it finds.
+// A unique index makes the table reject a row whose value another row holds already
+#[derive(Clone, Debug, PartialEq)]
+pub struct Index {
+    column: IndexedColumn,
+    root_page_num: usize,
+    unique: bool,
+}
+
+impl Index {
+    pub fn new(column: IndexedColumn, root_page_num: usize, unique: bool) -> Self {
+        Self {
+            column,
+            root_page_num,
+            unique,
+        }
+    }
+
+    pub fn is_unique(&self) -> bool {
+        self.unique
+    }
+
+    pub fn get_column(&self) -> IndexedColumn {
+        self.column
+    }
+
+    pub fn get_root_page_num(&self) -> usize {
+        self.root_page_num
+    }
+
+    // key of the entry of the row
+    pub fn get_entry_key(&self, row: &Row) -> Key {
+        let value = indexed_prefix(self.column.get_value(row));
+        Key::new(&[KeyValue::Text(value.to_string()), KeyValue::Int(row.get_id().into())]).unwrap()
+    }
+
+    // keys of the entries that may belong to rows holding the value
+    pub fn get_lookup_range(&self, value: &str) -> RangeInclusive<Key> {
+        let value = indexed_prefix(value).to_string();
+        let lower = Key::new(&[KeyValue::Text(value.clone())]).unwrap();
+        let upper = Key::new(&[KeyValue::Text(value), KeyValue::Int(i64::MAX)]).unwrap();
+        lower..=upper
+    }
+}
+
+// the longest prefix of the value that fits into an entry key, cut at a char boundary
+fn indexed_prefix(value: &str) -> &str {
+    let mut len = value.len().min(MAX_INDEXED_VALUE_SIZE);
+    while !value.is_char_boundary(len) {
+        len -= 1;
+    }
+    &value[..len]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_entry_keys_are_ordered_by_value_then_id() {
+        let index = Index::new(IndexedColumn::Email, 2, false);
+        let entry = |id: u32, email: &str| index.get_entry_key(&Row::new(id, "user".to_string(), email.to_string()));
+        assert!(entry(9, "a@mail.com") < entry(1, "b@mail.com"));
+        assert!(entry(1, "a@mail.com") < entry(2, "a@mail.com"));
+
+        let range = index.get_lookup_range("a@mail.com");
+        assert!(range.contains(&entry(1, "a@mail.com")));
+        assert!(range.contains(&entry(u32::MAX, "a@mail.com")));
+        assert!(!range.contains(&entry(1, "a@mail.co")));
+        assert!(!range.contains(&entry(1, "a@mail.comm")));
+    }
+
+    #[test]
+    fn test_long_values_share_their_prefix() {
+        let index = Index::new(IndexedColumn::UserName, 2, true);
+        let long = "é".repeat(MAX_INDEXED_VALUE_SIZE);
+        let row = Row::new(1, long.clone(), "email".to_string());
+        assert!(index.get_entry_key(&row).as_bytes().len() <= MAX_KEY_SIZE);
+        // the lookup of another value with the same prefix finds the entry too
+        assert!(index.get_lookup_range(&format!("{}x", long)).contains(&index.get_entry_key(&row)));
+    }
+
+    #[test]
+    fn test_column_names() {
+        assert_eq!(IndexedColumn::parse("EMAIL").unwrap(), IndexedColumn::Email);
+        assert_eq!(IndexedColumn::parse("user_name").unwrap().get_name(), "user_name");
+        assert!(IndexedColumn::parse("id").is_err());
+        assert_eq!(IndexedColumn::from_id(IndexedColumn::Email.get_id()), Some(IndexedColumn::Email));
+        assert_eq!(IndexedColumn::from_id(0), None);
+    }
+}
@@ -0,0 +1,191 @@
+use std::cmp::Ordering;
+use std::fmt::{Debug, Formatter};
+
+use anyhow::Result;
+
+use crate::storage::constant::MAX_KEY_SIZE;
+
+// type tags of the encoded columns, ints sort before texts
+const INT_TAG: u8 = 1;
+const TEXT_TAG: u8 = 2;
+const TEXT_LEN_SIZE: usize = std::mem::size_of::<u16>();
+
+// A column of a key
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KeyValue {
+    Int(i64),
+    Text(String),
+}
+
+// Key of a tree, one or more columns encoded one after the other: a type tag followed by the value, a big
+// endian i64 for ints and a u16 length followed by the utf-8 bytes for texts. Keys are ordered by compare,
+// column by column, so a key sorts right after every key it is a prefix of. At most MAX_KEY_SIZE bytes.
+// The table tree is keyed on the row id alone, text and composite keys are only built by the index trees
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Key(Vec<u8>);
+
+impl Key {
+    pub fn new(values: &[KeyValue]) -> Result<Self> {
+        let mut bytes = Vec::new();
+        for value in values {
+            match value {
+                KeyValue::Int(value) => {
+                    bytes.push(INT_TAG);
+                    bytes.extend_from_slice(&value.to_be_bytes());
+                }
+                KeyValue::Text(value) => {
+                    bytes.push(TEXT_TAG);
+                    bytes.extend_from_slice(&(value.len() as u16).to_le_bytes());
+                    bytes.extend_from_slice(value.as_bytes());
+                }
+            }
+        }
+        anyhow::ensure!(
+            bytes.len() <= MAX_KEY_SIZE,
+            "Key is {} bytes long, keys are at most {} bytes",
+            bytes.len(),
+            MAX_KEY_SIZE
+        );
+        Ok(Key(bytes))
+    }
+
+    // a single int column always fits
+    pub fn int(value: i64) -> Self {
+        Key::new(&[KeyValue::Int(value)]).unwrap()
+    }
+
+    #[cfg(test)]
+    pub fn text(value: &str) -> Result<Self> {
+        Key::new(&[KeyValue::Text(value.to_string())])
+    }
+
+    // the key without columns, it sorts before every other key
+    pub const fn min() -> Self {
+        Key(Vec::new())
+    }
+
+    // an encoded key as stored in a page
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Self {
+        Key(bytes.to_vec())
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    // the columns of the key, decoding stops at the first malformed one
+    pub fn values(&self) -> Vec<KeyValue> {
+        let mut values = Vec::new();
+        let mut bytes = &self.0[..];
+        while let Some((column, rest)) = split_column(bytes) {
+            values.push(match column {
+                Column::Int(value) => KeyValue::Int(value),
+                Column::Text(value) => KeyValue::Text(String::from_utf8_lossy(value).into_owned()),
+            });
+            bytes = rest;
+        }
+        values
+    }
+}
+
+impl Ord for Key {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare(&self.0, &other.0)
+    }
+}
+
+impl PartialOrd for Key {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Debug for Key {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Key").field(&self.values()).finish()
+    }
+}
+
+// a column decoded in place
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum Column<'a> {
+    Int(i64),
+    Text(&'a [u8]),
+}
+
+// the first column and the bytes after it, None at the end of the key or for a malformed column
+fn split_column(bytes: &[u8]) -> Option<(Column<'_>, &[u8])> {
+    let (&tag, rest) = bytes.split_first()?;
+    match tag {
+        INT_TAG => {
+            let (value, rest) = rest.split_first_chunk::<8>()?;
+            Some((Column::Int(i64::from_be_bytes(*value)), rest))
+        }
+        TEXT_TAG => {
+            let (len, rest) = rest.split_first_chunk::<TEXT_LEN_SIZE>()?;
+            let len = u16::from_le_bytes(*len) as usize;
+            (rest.len() >= len).then(|| (Column::Text(&rest[..len]), &rest[len..]))
+        }
+        _ => None,
+    }
+}
+
+// Comparator of encoded keys: ints numerically, texts by their bytes and ints before texts, column by column.
+// A key that runs out of columns first is the smaller one
+pub fn compare(a: &[u8], b: &[u8]) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        match (split_column(a), split_column(b)) {
+            (Some((column_a, rest_a)), Some((column_b, rest_b))) => match column_a.cmp(&column_b) {
+                Ordering::Equal => (a, b) = (rest_a, rest_b),
+                ordering => return ordering,
+            },
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            // both at the end, malformed bytes are compared as they are
+            (None, None) => return a.cmp(b),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn composite(text: &str, id: i64) -> Key {
+        Key::new(&[KeyValue::Text(text.to_string()), KeyValue::Int(id)]).unwrap()
+    }
+
+    #[test]
+    fn test_int_keys_are_ordered_by_value() {
+        let mut keys: Vec<Key> = [5, -1, i64::MAX, 0, i64::MIN, 256, -256].into_iter().map(Key::int).collect();
+        keys.sort();
+        let values: Vec<Vec<KeyValue>> = keys.iter().map(Key::values).collect();
+        let expected: Vec<Vec<KeyValue>> =
+            [i64::MIN, -256, -1, 0, 5, 256, i64::MAX].into_iter().map(|value| vec![KeyValue::Int(value)]).collect();
+        assert_eq!(values, expected);
+    }
+
+    #[test]
+    fn test_text_and_composite_keys() {
+        assert!(Key::text("a").unwrap() < Key::text("b").unwrap());
+        assert!(Key::text("ab").unwrap() < Key::text("b").unwrap());
+        assert!(Key::text("").unwrap() < Key::text("a").unwrap());
+        // ints sort before texts, the empty key before everything
+        assert!(Key::int(i64::MAX) < Key::text("").unwrap());
+        assert!(Key::min() < Key::int(i64::MIN));
+
+        // the first column decides, the second one breaks ties
+        assert!(composite("a", 100) < composite("b", 1));
+        assert!(composite("b", -1) < composite("b", 1));
+        assert!(Key::text("b").unwrap() < composite("b", i64::MIN));
+        assert_eq!(composite("b", 2).values(), vec![KeyValue::Text("b".to_string()), KeyValue::Int(2)]);
+    }
+
+    #[test]
+    fn test_key_size_is_limited() {
+        assert!(Key::text(&"a".repeat(MAX_KEY_SIZE - 3)).is_ok());
+        let err = Key::text(&"a".repeat(MAX_KEY_SIZE - 2)).unwrap_err();
+        assert!(err.to_string().contains("keys are at most"));
+    }
+}
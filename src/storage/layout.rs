@@ -0,0 +1,75 @@
+use crate::storage::constant::{
+    INTERNAL_CELL_SIZE, KEY_VALUE_OFFSET, LEAF_MIN_CELLS, LEAF_NODE_CELLS_OFFSET, MAX_PAGE_SIZE,
+    MAX_CELL_HEADER_SIZE, MIN_PAGE_SIZE, OVERFLOW_PAYLOAD_OFFSET, SLOT_SIZE,
+};
+
+// Node capacities of a database, derived from its page size. The page size is picked when the file is
+// created and stored in the database header, everything here is recomputed from it on open
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PageLayout {
+    pub(crate) page_size: usize,
+    // bytes of a leaf page shared by the slots and the cells, rows are variable length so leaves are
+    // filled by size rather than by cell count
+    pub(crate) leaf_node_space: usize,
+    // a non-root leaf using less space borrows from or is merged with a sibling
+    pub(crate) leaf_node_min_space: usize,
+    // longest value stored in the leaf itself, a longer one keeps only a prefix and the first overflow page num.
+    // Room is left for a key of MAX_KEY_SIZE
+    pub(crate) max_local_value: usize,
+    // value bytes held by one overflow page
+    pub(crate) overflow_page_capacity: usize,
+    pub(crate) internal_node_max_cells: usize,
+    pub(crate) internal_node_min_cells: usize,
+}
+
+impl PageLayout {
+    pub fn new(page_size: usize) -> Self {
+        assert!(is_valid_page_size(page_size), "Unsupported page size {}", page_size);
+
+        let leaf_node_space = page_size - LEAF_NODE_CELLS_OFFSET;
+        let internal_node_max_cells = (page_size - KEY_VALUE_OFFSET) / INTERNAL_CELL_SIZE;
+        Self {
+            page_size,
+            leaf_node_space,
+            leaf_node_min_space: leaf_node_space / 3,
+            max_local_value: leaf_node_space / LEAF_MIN_CELLS - SLOT_SIZE - MAX_CELL_HEADER_SIZE,
+            overflow_page_capacity: page_size - OVERFLOW_PAYLOAD_OFFSET,
+            internal_node_max_cells,
+            internal_node_min_cells: internal_node_max_cells / 2,
+        }
+    }
+}
+
+// powers of two between MIN_PAGE_SIZE and MAX_PAGE_SIZE
+pub fn is_valid_page_size(page_size: usize) -> bool {
+    page_size.is_power_of_two() && (MIN_PAGE_SIZE..=MAX_PAGE_SIZE).contains(&page_size)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_capacities_follow_page_size() {
+        let small = PageLayout::new(MIN_PAGE_SIZE);
+        assert!(small.leaf_node_space >= LEAF_MIN_CELLS * (small.max_local_value + MAX_CELL_HEADER_SIZE + SLOT_SIZE));
+        assert!(small.overflow_page_capacity > small.max_local_value);
+        assert!(small.internal_node_max_cells >= 3);
+
+        let default = PageLayout::new(4096);
+        assert_eq!(default.leaf_node_space, 4096 - LEAF_NODE_CELLS_OFFSET);
+        assert_eq!(default.internal_node_max_cells, (4096 - KEY_VALUE_OFFSET) / INTERNAL_CELL_SIZE);
+
+        let large = PageLayout::new(MAX_PAGE_SIZE);
+        assert!(KEY_VALUE_OFFSET + large.internal_node_max_cells * INTERNAL_CELL_SIZE <= MAX_PAGE_SIZE);
+    }
+
+    #[test]
+    fn test_page_size_bounds() {
+        assert!(is_valid_page_size(512));
+        assert!(is_valid_page_size(65536));
+        assert!(!is_valid_page_size(256));
+        assert!(!is_valid_page_size(131072));
+        assert!(!is_valid_page_size(3000));
+    }
+}
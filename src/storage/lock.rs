@@ -0,0 +1,226 @@
+use std::fs::TryLockError;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::storage::error::StorageError;
+use crate::storage::vfs::{LockKind, Vfs, VfsFile};
+
+// pause between two attempts while waiting for a busy lock
+const BUSY_RETRY_INTERVAL: Duration = Duration::from_millis(1);
+
+// Lock levels of a connection, SQLite style. Every open connection holds SHARED, so any number of
+// processes can read. RESERVED is held by the one connection with uncommitted changes, a second writer
+// is turned away. EXCLUSIVE is only taken on top of RESERVED to rewrite the database file, by a
+// checkpoint or when a new database is set up, and waits for every other connection to go away
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LockLevel {
+    Shared,
+    Reserved,
+    Exclusive,
+}
+
+// Advisory locks on whole files: SHARED and EXCLUSIVE lock the database file, RESERVED locks the WAL.
+// The WAL is only ever removed under EXCLUSIVE, so nobody can be holding a lock on a removed log.
+// Locks belong to the open file, two connections of the same process exclude each other as well.
+// A shared file lock that fails to become exclusive is lost on the way, holding RESERVED makes sure
+// no other connection can sneak in before it is taken again
+pub(crate) struct FileLock {
+    db_file: Box<dyn VfsFile>,
+    wal_file: Box<dyn VfsFile>,
+    reserved: bool,
+    exclusive: bool,
+    busy_timeout: Duration,
+}
+
+impl FileLock {
+    // opens its own handles of the files and waits for SHARED
+    pub fn acquire(vfs: &dyn Vfs, db_path: &str, wal_path: &str, busy_timeout: Duration) -> Result<Self, StorageError> {
+        let lock = Self {
+            db_file: vfs.open(db_path)?,
+            wal_file: vfs.open(wal_path)?,
+            reserved: false,
+            exclusive: false,
+            busy_timeout,
+        };
+        lock.wait(|| lock.db_file.try_lock(LockKind::Shared))?;
+        Ok(lock)
+    }
+
+    pub fn get_level(&self) -> LockLevel {
+        if self.exclusive {
+            LockLevel::Exclusive
+        } else if self.reserved {
+            LockLevel::Reserved
+        } else {
+            LockLevel::Shared
+        }
+    }
+
+    pub fn reserve(&mut self) -> Result<(), StorageError> {
+        if !self.reserved {
+            self.wait(|| self.wal_file.try_lock(LockKind::Exclusive))?;
+            self.reserved = true;
+        }
+        Ok(())
+    }
+
+    // takes RESERVED only if no other connection is writing, false otherwise
+    pub fn try_reserve(&mut self) -> Result<bool, StorageError> {
+        if !self.reserved {
+            match try_once(self.wal_file.try_lock(LockKind::Exclusive)) {
+                Ok(()) => self.reserved = true,
+                Err(StorageError::Locked) => return Ok(false),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(true)
+    }
+
+    pub fn release_reserved(&mut self) -> Result<(), StorageError> {
+        if self.reserved {
+            self.wal_file.unlock()?;
+            self.reserved = false;
+        }
+        Ok(())
+    }
+
+    // waits up to the busy timeout for the other connections to close, RESERVED has to be held
+    pub fn lock_exclusive(&mut self) -> Result<(), StorageError> {
+        if !self.exclusive {
+            self.wait(|| self.upgrade())?;
+            self.exclusive = true;
+        }
+        Ok(())
+    }
+
+    // takes EXCLUSIVE only if nobody else is connected, false otherwise. RESERVED has to be held
+    pub fn try_lock_exclusive(&mut self) -> Result<bool, StorageError> {
+        if !self.exclusive {
+            match try_once(self.upgrade()) {
+                Ok(()) => self.exclusive = true,
+                Err(StorageError::Locked) => return Ok(false),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(true)
+    }
+
+    // the shared lock is given up by a failed attempt and taken back right away, nobody else can go
+    // for EXCLUSIVE in between without RESERVED
+    fn upgrade(&self) -> Result<(), TryLockError> {
+        assert!(self.reserved, "EXCLUSIVE is only taken on top of RESERVED");
+        let result = self.db_file.try_lock(LockKind::Exclusive);
+        if let Err(TryLockError::WouldBlock) = result {
+            self.db_file.try_lock(LockKind::Shared)?;
+        }
+        result
+    }
+
+    // back to SHARED, keeping RESERVED if it is held
+    pub fn release_exclusive(&mut self) -> Result<(), StorageError> {
+        if self.exclusive {
+            try_once(self.db_file.try_lock(LockKind::Shared))?;
+            self.exclusive = false;
+        }
+        Ok(())
+    }
+
+    // drops every lock, like the end of the process holding them
+    #[cfg(test)]
+    pub fn release_all(&mut self) -> Result<(), StorageError> {
+        self.db_file.unlock()?;
+        self.wal_file.unlock()?;
+        self.reserved = false;
+        self.exclusive = false;
+        Ok(())
+    }
+
+    fn wait(&self, mut try_lock: impl FnMut() -> Result<(), TryLockError>) -> Result<(), StorageError> {
+        let deadline = Instant::now() + self.busy_timeout;
+        loop {
+            match try_once(try_lock()) {
+                Err(StorageError::Locked) if Instant::now() < deadline => thread::sleep(BUSY_RETRY_INTERVAL),
+                result => return result,
+            }
+        }
+    }
+}
+
+fn try_once(result: Result<(), TryLockError>) -> Result<(), StorageError> {
+    match result {
+        Ok(()) => Ok(()),
+        Err(TryLockError::WouldBlock) => Err(StorageError::Locked),
+        Err(TryLockError::Error(err)) => Err(StorageError::Io(err)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use crate::storage::vfs::StdVfs;
+
+    use super::*;
+
+    fn acquire(db_path: &str) -> FileLock {
+        FileLock::acquire(&StdVfs, db_path, &format!("{}-wal", db_path), Duration::ZERO).unwrap()
+    }
+
+    #[test]
+    fn test_lock_levels() {
+        let db_path = "test_lock_levels.db";
+        fs::write(db_path, []).unwrap();
+        let mut first = acquire(db_path);
+        let mut second = acquire(db_path);
+        assert_eq!(first.get_level(), LockLevel::Shared);
+
+        // one writer at a time, readers are not affected
+        first.reserve().unwrap();
+        assert_eq!(first.get_level(), LockLevel::Reserved);
+        assert!(matches!(second.reserve(), Err(StorageError::Locked)));
+        let third = acquire(db_path);
+
+        // the database file can only be rewritten once everybody else is gone
+        assert!(!first.try_lock_exclusive().unwrap());
+        drop(second);
+        // the failed attempt kept SHARED
+        assert!(matches!(third.db_file.try_lock(LockKind::Exclusive), Err(TryLockError::WouldBlock)));
+        drop(third);
+        first.lock_exclusive().unwrap();
+        assert_eq!(first.get_level(), LockLevel::Exclusive);
+        assert!(matches!(
+            FileLock::acquire(&StdVfs, db_path, &format!("{}-wal", db_path), Duration::ZERO),
+            Err(StorageError::Locked)
+        ));
+
+        first.release_exclusive().unwrap();
+        assert_eq!(first.get_level(), LockLevel::Reserved);
+        first.release_reserved().unwrap();
+        acquire(db_path).reserve().unwrap();
+
+        drop(first);
+        fs::remove_file(db_path).unwrap();
+        fs::remove_file(format!("{}-wal", db_path)).unwrap();
+    }
+
+    #[test]
+    fn test_busy_timeout_waits_for_the_lock() {
+        let db_path = "test_busy_timeout_waits_for_the_lock.db";
+        fs::write(db_path, []).unwrap();
+        let mut first = acquire(db_path);
+        first.reserve().unwrap();
+
+        let releaser = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            first.release_reserved().unwrap();
+            first
+        });
+        let mut second = FileLock::acquire(&StdVfs, db_path, &format!("{}-wal", db_path), Duration::from_secs(5)).unwrap();
+        second.reserve().unwrap();
+        drop(releaser.join().unwrap());
+
+        drop(second);
+        fs::remove_file(db_path).unwrap();
+        fs::remove_file(format!("{}-wal", db_path)).unwrap();
+    }
+}
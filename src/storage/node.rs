@@ -1,78 +1,205 @@
-use std::fmt::{Display, Formatter};
-use std::usize;
+use std::cmp::Ordering;
 
-use crate::storage::constant::{ID_SIZE, INTERNAL_CELL_SIZE, INTERNAL_NODE_MAX_CELLS, KEY_VALUE_OFFSET, KEY_VALUE_SIZE, PAGE_NUM_SIZE, PAGE_SIZE, RIGHT_CHILD_OFFSET};
-// unused imports will be kept until the end of the project to know if they are really unused
 use crate::storage::constant::{
-    CELL_SIZE, CELLS_COUNT_OFFSET, CELLS_COUNT_SIZE, IS_ROOT_OFFSET,
-    LEAF_NODE_CELLS_OFFSET, LEAF_NODE_MAX_CELLS,
-    NODE_TYPE_OFFSET, PARENT_PAGE_NUM_OFFSET, PARENT_PAGE_NUM_SIZE
-    ,
+    CELLS_COUNT_OFFSET, CHECKSUM_OFFSET, CHECKSUM_SIZE, INTERNAL_CELL_SIZE, INTERNAL_NODE_KEY_COUNT_OFFSET,
+    IS_ROOT_OFFSET, KEY_LEN_SIZE, KEY_VALUE_OFFSET, KEY_VALUE_SIZE, LEAF_FRAGMENTED_BYTES_OFFSET, LEAF_HEAP_START_OFFSET,
+    LEAF_NEXT_LEAF_OFFSET, LEAF_PREV_LEAF_OFFSET, MAX_KEY_SIZE, MIN_CELL_HEADER_SIZE, NEXT_FREE_PAGE_NUM_OFFSET,
+    NODE_TYPE_OFFSET, OVERFLOW_NEXT_PAGE_OFFSET, OVERFLOW_PAYLOAD_OFFSET, OVERFLOW_PAYLOAD_SIZE_OFFSET, PAGE_NUM_SIZE,
+    PARENT_PAGE_NUM_OFFSET, RIGHT_CHILD_OFFSET, SLOT_SIZE, VALUE_LEN_SIZE,
 };
+use crate::storage::error::StorageError;
+use crate::storage::key::{self, Key};
+use crate::storage::layout::PageLayout;
+use crate::storage::slotted_page::SlottedPage;
 
 // replaced Page from previous implementation. Page structure will be restored later on if deemed necessary
-#[derive(Clone, PartialEq)]
+//
+// Page layout, all integers are little endian u64 unless noted otherwise, offsets come from storage::constant.
+// Keys are encoded byte strings of at most MAX_KEY_SIZE bytes, see storage::key, each prefixed by its
+// length as a u16.
+//
+// Common header, both node types:
+//   CHECKSUM_OFFSET          crc32 of the rest of the page, u32. Verified every time the page is read
+//   NODE_TYPE_OFFSET         node type, u8, 0 - leaf, 1 - internal, 2 - free, 3 - overflow
+//   IS_ROOT_OFFSET           1 if the node is the root of the tree, u8
+//   PARENT_PAGE_NUM_OFFSET   page num of the parent, the root points to itself
+//   NEXT_FREE_PAGE_NUM_OFFSET   free list link: every free page holds the next one, 0 terminates the list.
+//                               The first free page is kept in the database header. Unused by other nodes
+//
+// Leaf node, a slotted page, see storage::slotted_page:
+//   CELLS_COUNT_OFFSET             number of cells
+//   LEAF_NEXT_LEAF_OFFSET          page num of the right sibling leaf, 0 for the rightmost leaf
+//   LEAF_PREV_LEAF_OFFSET          page num of the left sibling leaf, 0 for the leftmost leaf
+//   LEAF_HEAP_START_OFFSET         offset of the lowest cell in the page
+//   LEAF_FRAGMENTED_BYTES_OFFSET   bytes lost to holes between cells
+//   LEAF_NODE_CELLS_OFFSET         slot array, followed by free space and the cells at the end of the page.
+//                                  A cell is the key with its u16 length, the length of the row and the row. A row longer
+//                                  than PageLayout::max_local_value keeps only a prefix in the cell, followed
+//                                  by the page num of the first overflow page holding the rest
+//
+// Internal node:
+//   INTERNAL_NODE_KEY_COUNT_OFFSET   number of keys
+//   RIGHT_CHILD_OFFSET               page num of the rightmost child
+//   KEY_VALUE_OFFSET                 keys_count cells of INTERNAL_CELL_SIZE bytes: max key of the
+//                                    child subtree padded to MAX_KEY_SIZE followed by the child page num
+//
+// Free page: only the common header, the page is waiting to be reused.
+//
+// Overflow page, one link of the chain holding the tail of a long row:
+//   OVERFLOW_NEXT_PAGE_OFFSET      page num of the next page of the chain, 0 for the last one
+//   OVERFLOW_PAYLOAD_SIZE_OFFSET   number of row bytes in this page
+//   OVERFLOW_PAYLOAD_OFFSET        the row bytes
+//
+// Everything after the last cell of an internal node is zero padding up to the page size of the database,
+// the number of cells a node can hold follows from it, see storage::layout.
+#[derive(Clone, PartialEq, Debug)]
 pub struct Node {
     // meta, common
     //todo: should be a separate struct perhaps?
     pub(crate) node_type: NodeType,
     pub(crate) is_root: bool,
     pub(crate) parent_page_num: usize,
+    pub(crate) next_free_page_num: usize,
 
     //meta leaf node
-    //need to be here for manual deserialization without billion of rows with 0 values
-    pub(crate) cells_count: usize,
-    pub(crate) cells: Vec<Cell>,
+    //the cell area is kept in its on disk form, empty for other node types
+    pub(crate) cells: SlottedPage,
     pub(crate) next_leaf_num: usize,
+    pub(crate) prev_leaf_num: usize,
 
     //meta internal node
     pub(crate) keys_count: usize,
     pub(crate) keys: Vec<InternalCell>,
     pub(crate) right_child_key: usize,
+
+    //meta overflow node
+    pub(crate) next_overflow_page_num: usize,
+    pub(crate) overflow_payload: Vec<u8>,
 }
 
 impl InternalCell {
-    pub fn get_key(&self) -> usize {
-        let key_bytes = &self.0[..ID_SIZE];
-        usize::from_le_bytes(key_bytes.try_into().unwrap())
+    pub fn new(key: &Key, page_num: usize) -> Self {
+        let key = key.as_bytes();
+        let mut cell = [0; INTERNAL_CELL_SIZE];
+        cell[..KEY_LEN_SIZE].copy_from_slice(&(key.len() as u16).to_le_bytes());
+        cell[KEY_LEN_SIZE..KEY_LEN_SIZE + key.len()].copy_from_slice(key);
+        cell[KEY_LEN_SIZE + MAX_KEY_SIZE..].copy_from_slice(&(page_num as u64).to_le_bytes());
+        InternalCell(cell)
+    }
+
+    fn key_bytes(&self) -> &[u8] {
+        &self.0[KEY_LEN_SIZE..KEY_LEN_SIZE + read_key_len(&self.0)]
+    }
+
+    pub fn get_key(&self) -> Key {
+        Key::from_bytes(self.key_bytes())
     }
 
     pub fn get_page_num(&self) -> usize {
-        let page_num_bytes = &self.0[ID_SIZE..];
-        usize::from_le_bytes(page_num_bytes.try_into().unwrap())
+        read_u64(&self.0, KEY_LEN_SIZE + MAX_KEY_SIZE)
     }
 }
 
 impl Cell {
-    pub fn get_key(&self) -> usize {
-        let key_bytes = &self.0[..ID_SIZE];
-        usize::from_le_bytes(key_bytes.try_into().unwrap())
+    pub fn new(key: &Key, value: &[u8]) -> Self {
+        let mut cell = Cell::with_header(key, value.len(), value.len());
+        cell.extend_from_slice(value);
+        Cell(cell)
+    }
+
+    // the cell keeps local_value, the rest of the value_len bytes are in the chain starting at overflow_page_num
+    pub fn new_overflow(key: &Key, value_len: usize, local_value: &[u8], overflow_page_num: usize) -> Self {
+        let mut cell = Cell::with_header(key, value_len, local_value.len() + PAGE_NUM_SIZE);
+        cell.extend_from_slice(local_value);
+        cell.extend_from_slice(&(overflow_page_num as u64).to_le_bytes());
+        Cell(cell)
+    }
+
+    fn with_header(key: &Key, value_len: usize, capacity: usize) -> Vec<u8> {
+        let key = key.as_bytes();
+        let mut cell = Vec::with_capacity(MIN_CELL_HEADER_SIZE + key.len() + capacity);
+        cell.extend_from_slice(&(key.len() as u16).to_le_bytes());
+        cell.extend_from_slice(key);
+        cell.extend_from_slice(&(value_len as u64).to_le_bytes());
+        cell
+    }
+
+    // space the cell takes in a leaf page, its slot included
+    pub fn get_space(&self) -> usize {
+        self.0.len() + SLOT_SIZE
+    }
+
+    #[cfg(test)]
+    pub fn get_key(&self) -> Key {
+        Key::from_bytes(cell_key(&self.0))
+    }
+
+    // length of the whole value, overflow included
+    pub fn get_value_len(&self) -> usize {
+        read_u64(&self.0, cell_header_size(&self.0) - VALUE_LEN_SIZE)
+    }
+
+    pub fn is_overflow(&self) -> bool {
+        self.get_value_len() > self.0.len() - cell_header_size(&self.0)
+    }
+
+    // the whole value unless the cell overflows
+    pub fn get_local_value(&self) -> &[u8] {
+        cell_local_value(&self.0)
+    }
+
+    pub fn get_overflow_page_num(&self) -> Option<usize> {
+        self.is_overflow().then(|| read_u64(&self.0, self.0.len() - PAGE_NUM_SIZE))
     }
 
-    pub fn get_value(&self) -> &[u8] {
-        &self.0[ID_SIZE..]
+    fn is_well_formed(bytes: &[u8]) -> bool {
+        if bytes.len() < KEY_LEN_SIZE || read_key_len(bytes) > MAX_KEY_SIZE {
+            return false;
+        }
+        let header_size = cell_header_size(bytes);
+        bytes.len() >= header_size
+            && (read_u64(bytes, header_size - VALUE_LEN_SIZE) <= bytes.len() - header_size
+                || bytes.len() >= header_size + PAGE_NUM_SIZE)
     }
 }
 
+fn read_key_len(bytes: &[u8]) -> usize {
+    u16::from_le_bytes(bytes[..KEY_LEN_SIZE].try_into().unwrap()) as usize
+}
+
+fn cell_header_size(cell: &[u8]) -> usize {
+    MIN_CELL_HEADER_SIZE + read_key_len(cell)
+}
+
+fn cell_key(cell: &[u8]) -> &[u8] {
+    &cell[KEY_LEN_SIZE..KEY_LEN_SIZE + read_key_len(cell)]
+}
+
+// the value stored in the cell, without the overflow page num of a long one
+fn cell_local_value(cell: &[u8]) -> &[u8] {
+    let header_size = cell_header_size(cell);
+    let is_overflow = read_u64(cell, header_size - VALUE_LEN_SIZE) > cell.len() - header_size;
+    let local_end = if is_overflow { cell.len() - PAGE_NUM_SIZE } else { cell.len() };
+    &cell[header_size..local_end]
+}
+
 impl Node {
-    pub fn print_nodes(&self) {
-        println!("Node type: {:?}", self.node_type);
-        println!("Is root: {:?}", self.is_root);
-        println!("Parent page num: {:?}", self.parent_page_num);
-        println!("Cells count: {:?}", self.cells_count);
-    }
-    pub fn new_leaf() -> Node {
+    pub fn new_leaf(layout: &PageLayout) -> Node {
         Node {
             node_type: NodeType::Leaf,
             is_root: false,
             parent_page_num: 0,
-            cells_count: 0,
-            cells: Vec::new(),
+            next_free_page_num: 0,
+            cells: SlottedPage::new(layout.page_size),
             next_leaf_num: 0,
+            prev_leaf_num: 0,
 
             keys_count: 0,
             keys: Vec::new(),
             right_child_key: 0,
+
+            next_overflow_page_num: 0,
+            overflow_payload: Vec::new(),
         }
     }
 
@@ -81,16 +208,54 @@ impl Node {
             node_type: NodeType::Internal,
             is_root: false,
             parent_page_num: 0,
-            cells_count: 0,
-            cells: Vec::new(),
+            next_free_page_num: 0,
+            cells: SlottedPage::default(),
 
             keys_count: 0,
             keys: Vec::new(),
             right_child_key: 0,
             next_leaf_num: 0,
+            prev_leaf_num: 0,
+
+            next_overflow_page_num: 0,
+            overflow_payload: Vec::new(),
+        }
+    }
+
+    pub fn new_free(next_free_page_num: usize) -> Node {
+        Node {
+            node_type: NodeType::Free,
+            next_free_page_num,
+            ..Node::new_internal()
         }
     }
 
+    pub fn new_overflow(payload: &[u8], next_overflow_page_num: usize) -> Node {
+        Node {
+            node_type: NodeType::Overflow,
+            next_overflow_page_num,
+            overflow_payload: payload.to_vec(),
+            ..Node::new_internal()
+        }
+    }
+
+    pub fn get_next_overflow_page_num(&self) -> usize {
+        self.next_overflow_page_num
+    }
+
+    pub fn get_overflow_payload(&self) -> &[u8] {
+        &self.overflow_payload
+    }
+
+    pub fn get_next_free_page_num(&self) -> usize {
+        self.next_free_page_num
+    }
+
+    #[cfg(test)]
+    pub fn set_next_free_page_num(&mut self, next_free_page_num: usize) {
+        self.next_free_page_num = next_free_page_num;
+    }
+
     pub fn get_next_leaf_num(&self) -> usize {
         self.next_leaf_num
     }
@@ -99,127 +264,159 @@ impl Node {
         self.next_leaf_num = next_leaf_num;
     }
 
+    pub fn get_prev_leaf_num(&self) -> usize {
+        self.prev_leaf_num
+    }
+
+    pub fn set_prev_leaf_num(&mut self, prev_leaf_num: usize) {
+        self.prev_leaf_num = prev_leaf_num;
+    }
+
     pub fn internal_node_children(&self, cell_num: usize) -> usize {
         if cell_num > self.keys_count {
             panic!("Tried to access child_num {} > keys_count {}", cell_num, self.keys_count);
         } else if cell_num == self.keys_count {
-            return self.right_child_key;
+            self.right_child_key
         } else {
-            return self.internal_get_value(cell_num);
+            self.internal_get_value(cell_num)
         }
     }
 
+    pub fn has_room_for(&self, cell: &Cell) -> bool {
+        self.cells.can_insert(&cell.0)
+    }
+
+    // bytes taken by the cells and their slots
+    pub fn get_used_space(&self) -> usize {
+        self.cells.used_space()
+    }
+
     pub fn insert_cell(&mut self, cell: Cell, cell_num: usize) {
-        if self.cells_count >= LEAF_NODE_MAX_CELLS {
+        if !self.cells.insert(cell_num, &cell.0) {
             panic!("Trying to insert cell into a full leaf node");
         }
-
-        // i really dont like this, but file deser requires cells_count to work
-        // need a better way to serialzie cells to solve this one.
-        self.cells.insert(cell_num, cell);
-        self.cells_count += 1;
     }
 
     pub fn push_cell(&mut self, cell: Cell) {
-        if self.cells_count >= LEAF_NODE_MAX_CELLS {
-            panic!("Trying to insert cell into a full leaf node");
+        self.insert_cell(cell, self.get_cell_count());
+    }
+
+    #[cfg(test)]
+    pub fn insert_key_value(&mut self, key: &Key, value: &[u8], cell_num: usize) {
+        self.insert_cell(Cell::new(key, value), cell_num);
+    }
+
+    // replaces the cells of the leaf, they have to fit into the page
+    pub fn set_cells(&mut self, cells: Vec<Cell>) {
+        self.cells = SlottedPage::new(self.cells.as_bytes().len());
+        for cell in cells {
+            self.push_cell(cell);
         }
+    }
 
-        // i really dont like this, but file deser requires cells_count to work
-        // need a better way to serialzie cells to solve this one.
-        self.cells.push(cell);
-        self.cells_count += 1;
+    pub fn take_cells(&mut self) -> Vec<Cell> {
+        let cells = (0..self.get_cell_count()).map(|cell_num| self.get_cell(cell_num)).collect();
+        self.set_cells(Vec::new());
+        cells
     }
 
-    pub fn insert_key_value(&mut self, key: usize, value: &[u8], cell_num: usize) {
-        let mut cell = [0; CELL_SIZE];
-        cell[..ID_SIZE].copy_from_slice(&key.to_le_bytes());
-        cell[ID_SIZE..].copy_from_slice(&value);
-        self.insert_cell(Cell(cell), cell_num);
+    pub fn set_keys(&mut self, keys: Vec<InternalCell>) {
+        self.keys_count = keys.len();
+        self.keys = keys;
     }
 
-    pub fn leaf_get_index_by_key(&self, key: usize) -> Option<usize> {
+    // page numbers of all children, right child included. Empty for leaf nodes
+    pub fn internal_children(&self) -> Vec<usize> {
+        match self.node_type {
+            NodeType::Leaf | NodeType::Free | NodeType::Overflow => Vec::new(),
+            NodeType::Internal => (0..=self.keys_count)
+                .map(|child_num| self.internal_node_children(child_num))
+                .collect(),
+        }
+    }
+
+    // keys_count is returned for the right child
+    pub fn internal_find_child_index_by_page(&self, page_num: usize) -> usize {
+        self.keys
+            .iter()
+            .position(|cell| cell.get_page_num() == page_num)
+            .unwrap_or(self.keys_count)
+    }
+
+    // index of the cell holding the key, or of the cell it would be inserted at
+    pub fn leaf_find_cell_index_by_key(&self, key: &Key) -> usize {
         let mut min_index = 0;
-        let mut max_index = self.cells_count;
+        let mut max_index = self.get_cell_count();
 
         while min_index != max_index {
             let index = (min_index + max_index) / 2;
-            let key_at_index = self.get_key(index);
-
-            if key_at_index == key {
-                return Some(index);
-            } else if key_at_index < key {
-                min_index = index + 1;
-            } else {
-                max_index = index;
+            match key::compare(cell_key(self.cells.get(index)), key.as_bytes()) {
+                Ordering::Equal => return index,
+                Ordering::Less => min_index = index + 1,
+                Ordering::Greater => max_index = index,
             }
         }
 
-        return None;
+        min_index
     }
 
-    pub fn internal_find_child_index_by_key(&self, key: usize) -> usize {
+    pub fn internal_find_child_index_by_key(&self, key: &Key) -> usize {
         let mut min_index = 0;
         let mut max_index = self.keys_count;
 
         while min_index != max_index {
             let index = (min_index + max_index) / 2;
-            let key_at_index = self.internal_get_key(index);
-
-            if key_at_index == key {
-                return index;
-            } else if key_at_index < key {
-                min_index = index + 1;
-            } else {
-                max_index = index;
+            match key::compare(self.keys[index].key_bytes(), key.as_bytes()) {
+                Ordering::Equal => return index,
+                Ordering::Less => min_index = index + 1,
+                Ordering::Greater => max_index = index,
             }
         }
 
-        return min_index;
+        min_index
     }
 
-    pub fn update_internal_node_key(&mut self, key: usize, new_key: usize) {
-        let index = Self::internal_find_child_index_by_key(self, key);
+    pub fn internal_set_key(&mut self, index: usize, key: &Key) {
+        self.keys[index] = InternalCell::new(key, self.internal_get_value(index));
+    }
 
-        if index < self.keys_count {
-            let mut key = [0; INTERNAL_CELL_SIZE];
-            key[..ID_SIZE].copy_from_slice(&new_key.to_le_bytes());
-            key[ID_SIZE..].copy_from_slice(&self.internal_get_value(index).to_le_bytes());
-            self.keys[index] = InternalCell(key);
+    // the children at left_index and left_index + 1 were merged into the left one:
+    // the left key is dropped and the slot of the right child now points to the left child
+    pub fn internal_merge_children(&mut self, left_index: usize) {
+        let left_page_num = self.internal_get_value(left_index);
+        if left_index + 1 == self.keys_count {
+            self.right_child_key = left_page_num;
+        } else {
+            let right_key = self.internal_get_key(left_index + 1);
+            self.keys[left_index + 1] = InternalCell::new(&right_key, left_page_num);
         }
+
+        self.keys.remove(left_index);
+        self.keys_count -= 1;
     }
 
-    fn leaf_get_node_max_key(&self) -> usize {
-        if self.cells_count == 0 {
-            return usize::MAX;
-        }
-        self.get_key(self.cells_count - 1)
+    // the node must not be empty
+    fn leaf_get_node_max_key(&self) -> Key {
+        self.get_key(self.get_cell_count() - 1)
     }
 
-    fn internal_get_node_max_key(&self) -> usize {
-        if self.keys_count == 0 {
-            return usize::MAX;
-        }
+    fn internal_get_node_max_key(&self) -> Key {
         self.internal_get_key(self.keys_count - 1)
     }
 
-    pub fn get_node_max_key(&self) -> usize {
+    pub fn get_node_max_key(&self) -> Key {
         match self.node_type {
-            NodeType::Leaf => self.leaf_get_node_max_key(),
+            NodeType::Leaf | NodeType::Free | NodeType::Overflow => self.leaf_get_node_max_key(),
             NodeType::Internal => self.internal_get_node_max_key(),
         }
     }
 
-    pub fn is_parent_node(&self) -> bool {
-        self.node_type == NodeType::Internal
-    }
-
     pub fn get_node_type(&self) -> NodeType {
         self.node_type.clone()
     }
 
     pub fn get_cell_count(&self) -> usize {
-        self.cells_count
+        self.cells.len()
     }
 
     pub fn get_key_count(&self) -> usize {
@@ -238,186 +435,255 @@ impl Node {
         self.right_child_key = right_child_key;
     }
 
-    pub fn internal_node_insert_by_index(&mut self, key: usize, value: usize, index: usize) {
-        if self.keys_count >= INTERNAL_NODE_MAX_CELLS {
-            panic!("Trying to insert cell into a full leaf node");
-        }
-
-        let mut cell = [0; INTERNAL_CELL_SIZE];
-        cell[..ID_SIZE].copy_from_slice(&key.to_le_bytes());
-        cell[ID_SIZE..].copy_from_slice(&value.to_le_bytes());
-        self.keys.insert(index, InternalCell(cell));
+    pub fn internal_node_insert_by_index(&mut self, key: &Key, value: usize, index: usize) {
+        self.keys.insert(index, InternalCell::new(key, value));
         self.keys_count += 1;
     }
 
-    pub fn internal_node_insert(&mut self, key: usize, value: usize) {
-        if self.keys_count >= INTERNAL_NODE_MAX_CELLS {
-            panic!("Trying to insert cell into a full leaf node");
-        }
-
-        let mut cell = [0; INTERNAL_CELL_SIZE];
-        cell[..ID_SIZE].copy_from_slice(&key.to_le_bytes());
-        cell[ID_SIZE..].copy_from_slice(&value.to_le_bytes());
-        self.keys.insert(self.keys_count, InternalCell(cell));
-        self.keys_count += 1;
+    pub fn internal_node_insert(&mut self, key: &Key, value: usize) {
+        self.internal_node_insert_by_index(key, value, self.keys_count);
     }
 
     pub fn get_parent_page_num(&self) -> usize {
         self.parent_page_num
     }
 
-    pub fn get_mut_cell(&mut self, cell_index: usize) -> &mut Cell {
-        &mut self.cells[cell_index]
+    pub fn get_cell(&self, cell_index: usize) -> Cell {
+        Cell(self.cells.get(cell_index).to_vec())
     }
 
-    pub fn remove_cell(&mut self, cell_index: usize) {
-        self.cells.remove(cell_index);
-        self.cells_count -= 1;
+    pub fn remove_cell(&mut self, cell_index: usize) -> Cell {
+        Cell(self.cells.remove(cell_index))
     }
 
+    // local part of the value, see Pager::select for the whole value
+    #[cfg(test)]
     pub fn get_value(&self, cell_index: usize) -> &[u8] {
-        &self.cells[cell_index].0[ID_SIZE..]
+        cell_local_value(self.cells.get(cell_index))
     }
 
-    pub fn get_key(&self, cell_index: usize) -> usize {
-        let key_bytes = &self.cells[cell_index].0[..ID_SIZE];
-        usize::from_le_bytes(key_bytes.try_into().unwrap())
+    pub fn get_key(&self, cell_index: usize) -> Key {
+        Key::from_bytes(cell_key(self.cells.get(cell_index)))
     }
 
-    pub fn internal_get_key(&self, cell_index: usize) -> usize {
-        let key_bytes = &self.keys[cell_index].0[..ID_SIZE];
-        usize::from_le_bytes(key_bytes.try_into().unwrap())
+    pub fn internal_get_key(&self, cell_index: usize) -> Key {
+        self.keys[cell_index].get_key()
     }
 
     pub fn internal_get_value(&self, cell_index: usize) -> usize {
-        let key_bytes = &self.keys[cell_index].0[ID_SIZE..];
-        usize::from_le_bytes(key_bytes.try_into().unwrap())
+        self.keys[cell_index].get_page_num()
     }
 
-    pub fn get_internal_key(&self, cell_index: usize) -> usize {
-        let key_bytes = &self.keys[cell_index].0[..ID_SIZE];
-        usize::from_le_bytes(key_bytes.try_into().unwrap())
-    }
+    pub fn serialize(&self, layout: &PageLayout) -> Vec<u8> {
+        assert!(
+            self.keys_count <= layout.internal_node_max_cells,
+            "Node does not fit into a page of {} bytes",
+            layout.page_size
+        );
 
-    pub fn serialize(&self) -> Vec<u8> {
-        let mut bytes = vec![0; PAGE_SIZE];
+        let mut bytes = match self.node_type {
+            NodeType::Leaf => self.cells.as_bytes().to_vec(),
+            NodeType::Internal | NodeType::Free | NodeType::Overflow => vec![0; layout.page_size],
+        };
+        assert_eq!(bytes.len(), layout.page_size, "Leaf was built for another page size");
         bytes[NODE_TYPE_OFFSET] = match self.node_type {
             NodeType::Leaf => 0,
             NodeType::Internal => 1,
+            NodeType::Free => 2,
+            NodeType::Overflow => 3,
         };
         bytes[IS_ROOT_OFFSET] = if self.is_root { 1 } else { 0 };
-        bytes[PARENT_PAGE_NUM_OFFSET..PARENT_PAGE_NUM_OFFSET + PARENT_PAGE_NUM_SIZE]
-            .copy_from_slice(&self.parent_page_num.to_le_bytes());
-
-        bytes[CELLS_COUNT_OFFSET..CELLS_COUNT_OFFSET + CELLS_COUNT_SIZE]
-            .copy_from_slice(&self.cells_count.to_le_bytes());
+        write_u64(&mut bytes, PARENT_PAGE_NUM_OFFSET, self.parent_page_num);
+        write_u64(&mut bytes, NEXT_FREE_PAGE_NUM_OFFSET, self.next_free_page_num);
 
-        let mut cells_offset = LEAF_NODE_CELLS_OFFSET;
-        for cell in &self.cells {
-            bytes[cells_offset..cells_offset + CELL_SIZE].copy_from_slice(&cell.0);
-            cells_offset += CELL_SIZE;
+        match self.node_type {
+            NodeType::Leaf => {
+                write_u64(&mut bytes, CELLS_COUNT_OFFSET, self.get_cell_count());
+                write_u64(&mut bytes, LEAF_NEXT_LEAF_OFFSET, self.next_leaf_num);
+                write_u64(&mut bytes, LEAF_PREV_LEAF_OFFSET, self.prev_leaf_num);
+                write_u64(&mut bytes, LEAF_HEAP_START_OFFSET, self.cells.get_heap_start());
+                write_u64(&mut bytes, LEAF_FRAGMENTED_BYTES_OFFSET, self.cells.get_fragmented_bytes());
+            }
+            NodeType::Internal => {
+                write_u64(&mut bytes, INTERNAL_NODE_KEY_COUNT_OFFSET, self.keys_count);
+                write_u64(&mut bytes, RIGHT_CHILD_OFFSET, self.right_child_key);
+
+                let mut keys_offset = KEY_VALUE_OFFSET;
+                for key in &self.keys {
+                    bytes[keys_offset..keys_offset + INTERNAL_CELL_SIZE].copy_from_slice(&key.0);
+                    keys_offset += INTERNAL_CELL_SIZE;
+                }
+            }
+            NodeType::Overflow => {
+                let payload_len = self.overflow_payload.len();
+                assert!(payload_len <= layout.overflow_page_capacity, "Overflow payload does not fit into a page");
+                write_u64(&mut bytes, OVERFLOW_NEXT_PAGE_OFFSET, self.next_overflow_page_num);
+                write_u64(&mut bytes, OVERFLOW_PAYLOAD_SIZE_OFFSET, payload_len);
+                bytes[OVERFLOW_PAYLOAD_OFFSET..OVERFLOW_PAYLOAD_OFFSET + payload_len]
+                    .copy_from_slice(&self.overflow_payload);
+            }
+            NodeType::Free => {}
         }
 
+        let checksum = page_checksum(&bytes);
+        bytes[CHECKSUM_OFFSET..CHECKSUM_OFFSET + CHECKSUM_SIZE].copy_from_slice(&checksum.to_le_bytes());
         bytes
     }
 
-    pub fn deserialize(bytes: &[u8]) -> Node {
+    // page_num is only used to report where the corruption is
+    pub fn deserialize(page_num: usize, bytes: &[u8], layout: &PageLayout) -> Result<Node, StorageError> {
+        if bytes.len() < layout.page_size {
+            return Err(StorageError::TornPage { page_num, len: bytes.len(), page_size: layout.page_size });
+        }
+        let bytes = &bytes[..layout.page_size];
+
+        let stored = u32::from_le_bytes(bytes[CHECKSUM_OFFSET..CHECKSUM_OFFSET + CHECKSUM_SIZE].try_into().unwrap());
+        let computed = page_checksum(bytes);
+        if stored != computed {
+            return Err(StorageError::ChecksumMismatch { page_num, stored, computed });
+        }
+
         let node_type = match bytes[NODE_TYPE_OFFSET] {
             0 => NodeType::Leaf,
             1 => NodeType::Internal,
-            _ => panic!("Unknown node type {}", bytes[NODE_TYPE_OFFSET]),
+            2 => NodeType::Free,
+            3 => NodeType::Overflow,
+            node_type => return Err(StorageError::InvalidNodeType { page_num, node_type }),
+        };
+
+        let (cell_count, max_cell_count) = match node_type {
+            NodeType::Leaf => (
+                read_u64(bytes, CELLS_COUNT_OFFSET),
+                layout.leaf_node_space / (SLOT_SIZE + MIN_CELL_HEADER_SIZE),
+            ),
+            NodeType::Internal => (read_u64(bytes, INTERNAL_NODE_KEY_COUNT_OFFSET), layout.internal_node_max_cells),
+            NodeType::Free => (0, 0),
+            // the payload of an overflow page is counted in bytes
+            NodeType::Overflow => (read_u64(bytes, OVERFLOW_PAYLOAD_SIZE_OFFSET), layout.overflow_page_capacity),
         };
-        return match node_type {
+        if cell_count > max_cell_count {
+            return Err(StorageError::CellCountOutOfRange { page_num, cell_count, max_cell_count });
+        }
+
+        Ok(match node_type {
             NodeType::Leaf => {
-                Node::deserialize_leaf_node(bytes)
+                Node::deserialize_leaf_node(page_num, bytes)?
             }
             NodeType::Internal => {
-                Node::deserialize_internal_node(bytes)
+                Node::deserialize_internal_node(page_num, bytes)?
             }
-        };
+            NodeType::Free => {
+                Node::new_free(read_u64(bytes, NEXT_FREE_PAGE_NUM_OFFSET))
+            }
+            NodeType::Overflow => {
+                let payload_len = read_u64(bytes, OVERFLOW_PAYLOAD_SIZE_OFFSET);
+                Node::new_overflow(
+                    &bytes[OVERFLOW_PAYLOAD_OFFSET..OVERFLOW_PAYLOAD_OFFSET + payload_len],
+                    read_u64(bytes, OVERFLOW_NEXT_PAGE_OFFSET),
+                )
+            }
+        })
     }
 
-    fn deserialize_internal_node(bytes: &[u8]) -> Node {
-        let is_root = bytes[IS_ROOT_OFFSET] == 1;
-        let parent_page_num = usize::from_le_bytes(
-            bytes[PARENT_PAGE_NUM_OFFSET..PARENT_PAGE_NUM_OFFSET + PARENT_PAGE_NUM_SIZE]
-                .try_into()
-                .unwrap(),
-        );
-
-        let keys_count = usize::from_le_bytes(
-            bytes[CELLS_COUNT_OFFSET..CELLS_COUNT_OFFSET + CELLS_COUNT_SIZE]
-                .try_into()
-                .unwrap(),
-        );
+    fn deserialize_internal_node(page_num: usize, bytes: &[u8]) -> Result<Node, StorageError> {
+        let keys_count = read_u64(bytes, INTERNAL_NODE_KEY_COUNT_OFFSET);
 
         let mut keys = Vec::new();
         let mut keys_offset = KEY_VALUE_OFFSET;
         for _ in 0..keys_count {
             let mut key = [0; KEY_VALUE_SIZE];
             key.copy_from_slice(&bytes[keys_offset..keys_offset + INTERNAL_CELL_SIZE]);
+            if read_key_len(&key) > MAX_KEY_SIZE {
+                return Err(StorageError::InvalidKey { page_num });
+            }
             keys.push(InternalCell(key));
             keys_offset += INTERNAL_CELL_SIZE;
         }
 
-        let right_child_key = usize::from_le_bytes(
-            bytes[RIGHT_CHILD_OFFSET..RIGHT_CHILD_OFFSET + PAGE_NUM_SIZE]
-                .try_into()
-                .unwrap(),
-        );
-
-        Node {
+        Ok(Node {
             node_type: NodeType::Internal,
-            is_root,
-            parent_page_num,
-            cells: Vec::new(),
-            cells_count: 0,
+            is_root: bytes[IS_ROOT_OFFSET] == 1,
+            parent_page_num: read_u64(bytes, PARENT_PAGE_NUM_OFFSET),
+            next_free_page_num: read_u64(bytes, NEXT_FREE_PAGE_NUM_OFFSET),
+            cells: SlottedPage::default(),
             keys,
             keys_count,
-            right_child_key,
+            right_child_key: read_u64(bytes, RIGHT_CHILD_OFFSET),
             next_leaf_num: 0,
-        }
-    }
-
-    fn deserialize_leaf_node(bytes: &[u8]) -> Node {
-        let is_root = bytes[IS_ROOT_OFFSET] == 1;
-        let parent_page_num = usize::from_le_bytes(
-            bytes[PARENT_PAGE_NUM_OFFSET..PARENT_PAGE_NUM_OFFSET + PARENT_PAGE_NUM_SIZE]
-                .try_into()
-                .unwrap(),
-        );
-
-        let cells_count = usize::from_le_bytes(
-            bytes[CELLS_COUNT_OFFSET..CELLS_COUNT_OFFSET + CELLS_COUNT_SIZE]
-                .try_into()
-                .unwrap(),
-        );
-
-        let mut cells = Vec::new();
-        let mut cells_offset = LEAF_NODE_CELLS_OFFSET;
-        for _ in 0..cells_count {
-            let mut cell = [0; CELL_SIZE];
-            cell.copy_from_slice(&bytes[cells_offset..cells_offset + CELL_SIZE]);
-            cells.push(Cell(cell));
-            cells_offset += CELL_SIZE;
-        }
-
-        Node {
+            prev_leaf_num: 0,
+            next_overflow_page_num: 0,
+            overflow_payload: Vec::new(),
+        })
+    }
+
+    fn deserialize_leaf_node(page_num: usize, bytes: &[u8]) -> Result<Node, StorageError> {
+        let cells = SlottedPage::from_page(
+            bytes,
+            read_u64(bytes, CELLS_COUNT_OFFSET),
+            read_u64(bytes, LEAF_HEAP_START_OFFSET),
+            read_u64(bytes, LEAF_FRAGMENTED_BYTES_OFFSET),
+        )
+        .filter(|cells| (0..cells.len()).all(|cell_num| Cell::is_well_formed(cells.get(cell_num))))
+        .ok_or(StorageError::InvalidCellPointer { page_num })?;
+
+        Ok(Node {
             node_type: NodeType::Leaf,
-            is_root,
-            parent_page_num,
+            is_root: bytes[IS_ROOT_OFFSET] == 1,
+            parent_page_num: read_u64(bytes, PARENT_PAGE_NUM_OFFSET),
+            next_free_page_num: read_u64(bytes, NEXT_FREE_PAGE_NUM_OFFSET),
             cells,
-            cells_count,
             keys_count: 0,
             keys: Vec::new(),
             right_child_key: 0,
-            next_leaf_num: 0,
+            next_leaf_num: read_u64(bytes, LEAF_NEXT_LEAF_OFFSET),
+            prev_leaf_num: read_u64(bytes, LEAF_PREV_LEAF_OFFSET),
+            next_overflow_page_num: 0,
+            overflow_payload: Vec::new(),
+        })
+    }
+}
+
+// node fields are u64 on every platform. A value a 32 bit usize cannot hold only comes from a corrupted
+// page, it saturates so the range checks turn it down
+fn read_u64(bytes: &[u8], offset: usize) -> usize {
+    let value = u64::from_le_bytes(bytes[offset..offset + std::mem::size_of::<u64>()].try_into().unwrap());
+    usize::try_from(value).unwrap_or(usize::MAX)
+}
+
+fn write_u64(bytes: &mut [u8], offset: usize, value: usize) {
+    bytes[offset..offset + std::mem::size_of::<u64>()].copy_from_slice(&(value as u64).to_le_bytes());
+}
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+            bit += 1;
         }
+        table[i] = crc;
+        i += 1;
     }
+    table
+};
+
+// crc32 (ieee) of everything in the page but the checksum itself
+fn page_checksum(bytes: &[u8]) -> u32 {
+    crc32(&bytes[CHECKSUM_OFFSET + CHECKSUM_SIZE..])
+}
+
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    let crc = bytes
+        .iter()
+        .fold(!0u32, |crc, &byte| (crc >> 8) ^ CRC32_TABLE[((crc ^ byte as u32) & 0xff) as usize]);
+    !crc
 }
 
 #[derive(Clone, PartialEq, Debug)]
-pub(crate) struct Cell([u8; CELL_SIZE]);
+pub(crate) struct Cell(Vec<u8>);
 
 #[derive(Clone, PartialEq, Debug)]
 pub struct InternalCell([u8; INTERNAL_CELL_SIZE]);
@@ -426,34 +692,41 @@ pub struct InternalCell([u8; INTERNAL_CELL_SIZE]);
 pub enum NodeType {
     Leaf,
     Internal,
+    Free,
+    Overflow,
 }
 
 #[cfg(test)]
 mod test {
+    use crate::storage::constant::{DEFAULT_PAGE_SIZE, LEAF_NODE_CELLS_OFFSET};
     use crate::storage::row::Row;
 
     use super::*;
 
+    fn layout() -> PageLayout {
+        PageLayout::new(DEFAULT_PAGE_SIZE)
+    }
+
     #[test]
     fn test_node() {
-        let mut node = Node::new_leaf();
-        let key: usize = 1;
+        let mut node = Node::new_leaf(&layout());
+        let key = Key::int(1);
         let value = Row::new(1, "test".to_string(), "test".to_string()).serialize().unwrap();
-        node.insert_key_value(key, &value, 0);
-        let serialized = node.serialize();
-        let deserialized = Node::deserialize(&serialized);
+        node.insert_key_value(&key, &value, 0);
+        let serialized = node.serialize(&layout());
+        let deserialized = Node::deserialize(0, &serialized, &layout()).unwrap();
         assert_eq!(deserialized.node_type, NodeType::Leaf);
-        assert_eq!(deserialized.is_root, false);
+        assert!(!deserialized.is_root);
         assert_eq!(deserialized.parent_page_num, 0);
-        assert_eq!(deserialized.cells_count, 1);
+        assert_eq!(deserialized.get_cell_count(), 1);
     }
 
     #[test]
     fn test_node_insert() {
-        let mut node = Node::new_leaf();
-        let key: usize = 1;
+        let mut node = Node::new_leaf(&layout());
+        let key = Key::int(1);
         let value = Row::new(1, "test".to_string(), "test".to_string()).serialize().unwrap();
-        node.insert_key_value(key, &value, 0);
+        node.insert_key_value(&key, &value, 0);
         assert_eq!(node.get_cell_count(), 1);
         assert_eq!(node.get_key(0), key);
         assert_eq!(node.get_value(0), value);
@@ -461,29 +734,167 @@ mod test {
 
     #[test]
     fn test_node_deserialize() {
-        let mut node = Node::new_leaf();
-        let key: usize = 1;
+        let mut node = Node::new_leaf(&layout());
+        let key = Key::int(1);
         let value = Row::new(1, "test".to_string(), "test".to_string()).serialize().unwrap();
-        node.insert_key_value(key, &value, 0);
-        let serialized = node.serialize();
-        let deserialized = Node::deserialize(&serialized);
+        node.insert_key_value(&key, &value, 0);
+        let serialized = node.serialize(&layout());
+        let deserialized = Node::deserialize(0, &serialized, &layout()).unwrap();
         assert_eq!(deserialized.node_type, NodeType::Leaf);
-        assert_eq!(deserialized.is_root, false);
+        assert!(!deserialized.is_root);
         assert_eq!(deserialized.parent_page_num, 0);
-        assert_eq!(deserialized.cells_count, 1);
+        assert_eq!(deserialized.get_cell_count(), 1);
     }
 
     #[test]
     fn test_node_internal() {
         let mut node = Node::new_internal();
-        let key: usize = 1;
-        let value = Row::new(1, "test".to_string(), "test".to_string()).serialize().unwrap();
-        node.insert_key_value(key, &value, 0);
-        let serialized = node.serialize();
-        let deserialized = Node::deserialize(&serialized);
+        node.internal_node_insert(&Key::int(1), 2);
+        let serialized = node.serialize(&layout());
+        let deserialized = Node::deserialize(0, &serialized, &layout()).unwrap();
         assert_eq!(deserialized.node_type, NodeType::Internal);
-        assert_eq!(deserialized.is_root, false);
+        assert!(!deserialized.is_root);
         assert_eq!(deserialized.parent_page_num, 0);
         assert_eq!(deserialized.keys_count, 1);
     }
+
+    #[test]
+    fn test_leaf_node_round_trip() {
+        let mut node = Node::new_leaf(&layout());
+        node.set_parent_page_num(7);
+        node.set_next_leaf_num(12);
+        node.set_prev_leaf_num(5);
+        let mut key = 0;
+        loop {
+            let value = Row::new(key as u32, "test".to_string(), "test".to_string()).serialize().unwrap();
+            if !node.has_room_for(&Cell::new(&Key::int(key as i64), &value)) {
+                break;
+            }
+            node.insert_key_value(&Key::int(key as i64), &value, key);
+            key += 1;
+        }
+        // short rows, a page holds far more of them than fixed size cells
+        assert!(node.get_cell_count() > 50);
+        node.remove_cell(key / 2);
+
+        let deserialized = Node::deserialize(0, &node.serialize(&layout()), &layout()).unwrap();
+        assert_eq!(deserialized, node);
+        assert_eq!(deserialized.get_next_leaf_num(), 12);
+        assert_eq!(deserialized.get_prev_leaf_num(), 5);
+        assert_eq!(deserialized.get_cell_count(), key - 1);
+        assert_eq!(deserialized.get_key(key / 2), Key::int((key / 2 + 1) as i64));
+    }
+
+    #[test]
+    fn test_free_node_round_trip() {
+        let node = Node::new_free(9);
+        let deserialized = Node::deserialize(0, &node.serialize(&layout()), &layout()).unwrap();
+        assert_eq!(deserialized.node_type, NodeType::Free);
+        assert_eq!(deserialized.get_next_free_page_num(), 9);
+    }
+
+    #[test]
+    fn test_overflow_node_round_trip() {
+        let payload = vec![7; layout().overflow_page_capacity];
+        let node = Node::new_overflow(&payload, 11);
+        let deserialized = Node::deserialize(0, &node.serialize(&layout()), &layout()).unwrap();
+        assert_eq!(deserialized.node_type, NodeType::Overflow);
+        assert_eq!(deserialized.get_next_overflow_page_num(), 11);
+        assert_eq!(deserialized.get_overflow_payload(), &payload[..]);
+    }
+
+    #[test]
+    fn test_overflow_cell() {
+        let cell = Cell::new_overflow(&Key::int(3), 1000, b"prefix", 17);
+        assert!(cell.is_overflow());
+        assert_eq!(cell.get_key(), Key::int(3));
+        assert_eq!(cell.get_value_len(), 1000);
+        assert_eq!(cell.get_local_value(), b"prefix");
+        assert_eq!(cell.get_overflow_page_num(), Some(17));
+
+        let mut node = Node::new_leaf(&layout());
+        node.insert_cell(cell, 0);
+        node.insert_key_value(&Key::int(4), b"row", 1);
+        assert_eq!(node.get_value(0), b"prefix");
+        assert_eq!(node.get_value(1), b"row");
+        assert_eq!(node.get_cell(1).get_overflow_page_num(), None);
+    }
+
+    #[test]
+    fn test_internal_node_round_trip() {
+        let mut node = Node::new_internal();
+        node.set_is_root(true);
+        node.set_parent_page_num(3);
+        node.set_next_free_page_num(5);
+        for key in 0..layout().internal_node_max_cells {
+            node.internal_node_insert(&Key::int(key as i64 * 10), key + 100);
+        }
+        node.set_right_child_key(42);
+
+        let deserialized = Node::deserialize(0, &node.serialize(&layout()), &layout()).unwrap();
+        assert_eq!(deserialized, node);
+        assert_eq!(deserialized.internal_node_children(layout().internal_node_max_cells), 42);
+        assert_eq!(deserialized.internal_get_key(1), Key::int(10));
+        assert_eq!(deserialized.internal_get_value(1), 101);
+    }
+
+    #[test]
+    fn test_corrupted_page_is_rejected() {
+        let mut bytes = Node::new_leaf(&layout()).serialize(&layout());
+        bytes[DEFAULT_PAGE_SIZE - 1] ^= 1;
+        assert!(matches!(
+            Node::deserialize(7, &bytes, &layout()),
+            Err(StorageError::ChecksumMismatch { page_num: 7, .. })
+        ));
+
+        assert!(matches!(
+            Node::deserialize(7, &bytes[..100], &layout()),
+            Err(StorageError::TornPage { page_num: 7, len: 100, .. })
+        ));
+    }
+
+    #[test]
+    fn test_invalid_node_content_is_rejected() {
+        // the checksum is valid, the content is not
+        let mut bytes = Node::new_leaf(&layout()).serialize(&layout());
+        bytes[NODE_TYPE_OFFSET] = 9;
+        let checksum = page_checksum(&bytes);
+        bytes[CHECKSUM_OFFSET..CHECKSUM_OFFSET + CHECKSUM_SIZE].copy_from_slice(&checksum.to_le_bytes());
+        assert!(matches!(
+            Node::deserialize(3, &bytes, &layout()),
+            Err(StorageError::InvalidNodeType { page_num: 3, node_type: 9 })
+        ));
+
+        let mut bytes = Node::new_internal().serialize(&layout());
+        write_u64(&mut bytes, INTERNAL_NODE_KEY_COUNT_OFFSET, layout().internal_node_max_cells + 1);
+        let checksum = page_checksum(&bytes);
+        bytes[CHECKSUM_OFFSET..CHECKSUM_OFFSET + CHECKSUM_SIZE].copy_from_slice(&checksum.to_le_bytes());
+        let err = Node::deserialize(3, &bytes, &layout()).unwrap_err();
+        assert!(matches!(err, StorageError::CellCountOutOfRange { page_num: 3, .. }));
+        assert!(err.to_string().contains("Page 3 is corrupted"));
+
+        let mut node = Node::new_internal();
+        node.internal_node_insert(&Key::int(1), 2);
+        let mut bytes = node.serialize(&layout());
+        // a key longer than its slot
+        bytes[KEY_VALUE_OFFSET..KEY_VALUE_OFFSET + KEY_LEN_SIZE].copy_from_slice(&(MAX_KEY_SIZE as u16 + 1).to_le_bytes());
+        let checksum = page_checksum(&bytes);
+        bytes[CHECKSUM_OFFSET..CHECKSUM_OFFSET + CHECKSUM_SIZE].copy_from_slice(&checksum.to_le_bytes());
+        assert!(matches!(Node::deserialize(3, &bytes, &layout()), Err(StorageError::InvalidKey { page_num: 3 })));
+    }
+
+    #[test]
+    fn test_invalid_cell_pointer_is_rejected() {
+        let mut node = Node::new_leaf(&layout());
+        node.insert_key_value(&Key::int(1), b"row", 0);
+        let mut bytes = node.serialize(&layout());
+        // the slot of the only cell points past the end of the page
+        bytes[LEAF_NODE_CELLS_OFFSET..LEAF_NODE_CELLS_OFFSET + 2].copy_from_slice(&u16::MAX.to_le_bytes());
+        let checksum = page_checksum(&bytes);
+        bytes[CHECKSUM_OFFSET..CHECKSUM_OFFSET + CHECKSUM_SIZE].copy_from_slice(&checksum.to_le_bytes());
+        assert!(matches!(
+            Node::deserialize(5, &bytes, &layout()),
+            Err(StorageError::InvalidCellPointer { page_num: 5 })
+        ));
+    }
 }
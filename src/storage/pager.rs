@@ -1,315 +1,2109 @@
-use std::fs::{File, OpenOptions};
+use std::collections::HashMap;
 use std::io;
-use std::io::{Read, Seek, Write};
+use std::sync::Arc;
 
-use crate::storage::constant::{ID_SIZE, INTERNAL_CELL_SIZE, LEAF_NODE_MAX_CELLS, PAGE_SIZE, TABLE_MAX_PAGES};
-use crate::storage::node::{InternalCell, Node, NodeType};
+use anyhow::Context;
+use memmap2::Mmap;
+
+use crate::storage::buffer_pool::BufferPool;
+use crate::storage::config::{ConnectionConfig, SyncMode};
+use crate::storage::error::StorageError;
+use crate::storage::constant::{MIN_PAGE_SIZE, PAGE_NUM_SIZE, WAL_AUTOCHECKPOINT_FRAMES};
+use crate::storage::header::{DatabaseHeader, HEADER_PAGE_NUM};
+use crate::storage::index::Index;
+use crate::storage::key::Key;
+use crate::storage::layout::{is_valid_page_size, PageLayout};
+use crate::storage::lock::{FileLock, LockLevel};
+use crate::storage::node::{Cell, InternalCell, Node, NodeType};
+use crate::storage::vfs::{read_up_to, Vfs, VfsFile};
+use crate::storage::wal::{ReadVersions, Wal};
 
 pub struct Pager {
-    file: File,
-    nodes_count: usize,
-    nodes: Vec<Option<Node>>,
+    vfs: Arc<dyn Vfs>,
+    file: Box<dyn VfsFile>,
+    // mapping of the database file when mmap is enabled and the vfs can map it, pages past its end are
+    // read as usual until it is remapped
+    mapping: Option<Mmap>,
+    mmap: bool,
+    wal: Wal,
+    lock: FileLock,
+    header: DatabaseHeader,
+    layout: PageLayout,
+    buffer_pool: BufferPool,
+    // header as of BEGIN while a transaction is active, restored on rollback
+    transaction: Option<DatabaseHeader>,
+    // savepoints of the active transaction, innermost last
+    savepoints: Vec<Savepoint>,
+}
+
+// A committed version of the database, see Pager::begin_snapshot. The page versions it sees are kept
+// until it is dropped or handed back to Pager::end_snapshot, which also frees them right away
+#[derive(Debug)]
+pub struct Snapshot {
+    version: u64,
+    readers: ReadVersions,
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        self.readers.unpin(self.version);
+    }
+}
+
+// Pages are recorded by the innermost savepoint the first time they are modified after it was set,
+// rolling back to a savepoint puts back the images of every savepoint from the innermost to it
+struct Savepoint {
+    // None for the savepoint of a statement, names only refer to the savepoints of the user
+    name: Option<String>,
+    header: DatabaseHeader,
+    // page content as of the savepoint, None for pages allocated after it
+    before_images: HashMap<usize, Option<Node>>,
+}
+
+impl Savepoint {
+    fn new(name: Option<&str>, header: DatabaseHeader) -> Self {
+        Self {
+            name: name.map(str::to_string),
+            header,
+            before_images: HashMap::new(),
+        }
+    }
 }
 
 impl Pager {
-    pub fn new(file_path: &str) -> anyhow::Result<Self> {
-        let file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(file_path)
-            .unwrap_or_else(|err| panic!("Failed to open file {} {:?}", file_path, err));
-
-        let file_len = file.metadata()?.len();
-        let nodes_count = (file_len / PAGE_SIZE as u64) as usize;
-
-        Ok(Self {
-            file,
-            nodes_count,
-            nodes: vec![None; TABLE_MAX_PAGES],
-        })
-    }
-
-    pub fn print_tree(&self, page_num: usize, indentation: usize) {
-        if let Some(node) = &self.nodes[page_num] {
-            let indent = " ".repeat(indentation);
-            println!("{}Node Type: {:?}", indent, node.node_type);
-            println!("{}Is Root: {}", indent, node.is_root);
-            println!("{}Parent Page Num: {}", indent, node.parent_page_num);
-
-            match node.node_type {
-                NodeType::Leaf => {
-                    println!("{}Next Leaf Num: {}", indent, node.next_leaf_num);
-                    println!("{}Cells Count: {}", indent, node.cells_count);
-                    for cell in &node.cells {
-                        println!("{}Cell: {:?}", indent, cell.get_key());
-                    }
-                }
-                NodeType::Internal => {
-                    println!("{}Right Child Key: {}", indent, node.right_child_key);
-                    println!("{}Keys Count: {}", indent, node.keys_count);
-                    for key in &node.keys {
-                        println!("{}Key: {:?}", indent, key);
-                    }
-                    // Recursively print child nodes
-                    for key in &node.keys {
-                        self.print_tree(
-                            key.get_key(),
-                            indentation + 4,
-                        );
-                    }
-                    self.print_tree(node.right_child_key, indentation + 4);
-                }
+    pub fn new(file_path: &str, config: &ConnectionConfig) -> anyhow::Result<Self> {
+        let vfs = config.get_vfs().clone();
+        let file = vfs.open(file_path).with_context(|| format!("Failed to open file {}", file_path))?;
+        // a file that cannot become a database is turned down before the lock creates the WAL next to it
+        if Self::is_set_up(&*file)? {
+            Self::read_header(&*file, file_path)?;
+        } else if !is_valid_page_size(config.get_page_size()) {
+            anyhow::bail!("Page size {} is not supported, expected a power of two between 512 and 65536", config.get_page_size());
+        }
+        let wal_path = format!("{}-wal", file_path);
+        let mut lock = FileLock::acquire(&*vfs, file_path, &wal_path, config.get_busy_timeout())?;
+
+        // a new database is set up under EXCLUSIVE, another connection may have done it in the meantime
+        if !Self::is_set_up(&*file)? {
+            lock.reserve()?;
+            lock.lock_exclusive()?;
+        }
+        let wal = if !Self::is_set_up(&*file)? {
+            // a new database is written in place, the WAL only ever holds changes to it. A log left behind
+            // by an earlier database of the same name is emptied first, the root page is durable before
+            // the header that makes the database exist
+            let header = DatabaseHeader::new(config.get_page_size());
+            let layout = PageLayout::new(header.get_page_size());
+            let wal = Wal::create(&vfs, &wal_path, layout.page_size, config.get_sync_mode())?;
+            let root_page_num = header.get_root_page_num();
+            let mut root_node = Node::new_leaf(&layout);
+            root_node.set_is_root(true);
+            root_node.set_parent_page_num(root_page_num);
+            file.truncate(0)?;
+            file.write_at(&root_node.serialize(&layout), (root_page_num * layout.page_size) as u64)?;
+            if config.get_sync_mode() != SyncMode::Off {
+                file.sync()?;
+            }
+            file.write_at(&header.serialize(), 0)?;
+            if config.get_sync_mode() != SyncMode::Off {
+                file.sync()?;
+                vfs.sync_dir(file_path)?;
+            }
+            wal
+        } else {
+            let page_size = Self::read_header(&*file, file_path)?.get_page_size();
+            let mut wal = Wal::open(&vfs, &wal_path, page_size, config.get_sync_mode())?;
+            // replays the commits of a connection that did not get to checkpoint them. The log has to stay
+            // as it is while other connections are reading from it
+            if lock.try_reserve()? && lock.try_lock_exclusive()? {
+                wal.checkpoint(&*file)?;
             }
+            wal
+        };
+        lock.release_exclusive()?;
+        lock.release_reserved()?;
+
+        // the latest header is the last commit frame, if the log still holds one
+        let header = match wal.read_page(HEADER_PAGE_NUM)? {
+            Some(buffer) => DatabaseHeader::deserialize(&buffer)?,
+            None => Self::read_header(&*file, file_path)?,
+        };
+        let mut pager = Self {
+            vfs,
+            file,
+            mapping: None,
+            mmap: config.get_mmap(),
+            wal,
+            lock,
+            layout: PageLayout::new(header.get_page_size()),
+            header,
+            buffer_pool: BufferPool::new(config.get_cache_size()),
+            transaction: None,
+            savepoints: Vec::new(),
+        };
+        pager.remap();
+        Ok(pager)
+    }
+
+    // the header is the last thing written when a database is set up, a file without one is new or
+    // its setup was cut short
+    fn is_set_up(file: &dyn VfsFile) -> io::Result<bool> {
+        Ok(read_up_to(file, 0, MIN_PAGE_SIZE)?.iter().any(|&byte| byte != 0))
+    }
+
+    // the header fits into the smallest page, its page size tells the geometry of the rest of the file
+    fn read_header(file: &dyn VfsFile, file_path: &str) -> anyhow::Result<DatabaseHeader> {
+        let buffer = read_up_to(file, 0, MIN_PAGE_SIZE)?;
+        DatabaseHeader::deserialize(&buffer).map_err(|err| err.context(format!("Failed to open database {}", file_path)))
+    }
+
+    pub fn get_sync_mode(&self) -> SyncMode {
+        self.wal.get_sync_mode()
+    }
+
+    // takes effect with the next commit
+    pub fn set_sync_mode(&mut self, sync_mode: SyncMode) {
+        self.wal.set_sync_mode(sync_mode);
+    }
+
+    #[cfg(test)]
+    pub fn get_lock_level(&self) -> LockLevel {
+        self.lock.get_level()
+    }
+
+    // catches up with the commits of other connections, the pages they changed are read again on the
+    // next access. Nobody else can commit while this connection holds RESERVED, it is up to date then
+    pub fn refresh(&mut self) -> anyhow::Result<()> {
+        if self.lock.get_level() < LockLevel::Reserved {
+            self.catch_up()?;
+        }
+        Ok(())
+    }
+
+    // returns whether other connections committed anything since the last catch up
+    fn catch_up(&mut self) -> anyhow::Result<bool> {
+        let page_nums = self.wal.refresh()?;
+        for &page_num in &page_nums {
+            self.buffer_pool.remove(page_num);
+        }
+        if page_nums.contains(&HEADER_PAGE_NUM) {
+            let buffer = self.wal.read_page(HEADER_PAGE_NUM)?.unwrap();
+            self.header = DatabaseHeader::deserialize(&buffer)?;
+        }
+        Ok(!page_nums.is_empty())
+    }
+
+    // takes RESERVED before the first change, one connection writes at a time. When another connection
+    // committed since this one last caught up, the pages the change was based on may be stale: the lock
+    // is given up again and the change fails as if the database was locked, a retry sees the new commits
+    fn begin_write(&mut self) -> anyhow::Result<()> {
+        if self.lock.get_level() >= LockLevel::Reserved {
+            return Ok(());
+        }
+
+        self.lock.reserve()?;
+        if self.catch_up()? {
+            self.lock.release_reserved()?;
+            return Err(StorageError::Locked.into());
         }
+        self.wal.begin_write()?;
+        Ok(())
+    }
+
+    pub fn get_root_page_num(&self) -> usize {
+        self.header.get_root_page_num()
+    }
+
+    #[cfg(test)]
+    pub fn get_layout(&self) -> &PageLayout {
+        &self.layout
+    }
+
+    #[cfg(test)]
+    pub fn get_schema_version(&self) -> u64 {
+        self.header.get_schema_version()
+    }
+
+    #[cfg(test)]
+    pub fn bump_schema_version(&mut self) -> anyhow::Result<()> {
+        self.begin_write()?;
+        self.header.schema_version += 1;
+        Ok(())
+    }
+
+    pub fn get_indexes(&self) -> &[Index] {
+        self.header.get_indexes()
     }
 
-    pub fn get_empty_page_num(&mut self) -> usize {
-        // we do not free old pages yet, so this is always the next page
-        self.nodes_count
+    // an empty tree next to the table, its root stays at the returned page like the table root does
+    pub fn create_tree(&mut self) -> anyhow::Result<usize> {
+        self.begin_write()?;
+        let root_page_num = self.allocate_page_num()?;
+        let mut root_node = Node::new_leaf(&self.layout);
+        root_node.set_is_root(true);
+        root_node.set_parent_page_num(root_page_num);
+        self.set_node(root_node, root_page_num)?;
+        Ok(root_page_num)
     }
 
+    // records the index in the header, its tree has to be built already
+    pub fn add_index(&mut self, index: Index) -> anyhow::Result<()> {
+        self.begin_write()?;
+        self.header.indexes.push(index);
+        self.header.schema_version += 1;
+        Ok(())
+    }
+
+    // reuses the first page of the free list, the file only grows when the list is empty.
+    // The caller is expected to set a node at the returned page right away
+    fn allocate_page_num(&mut self) -> Result<usize, StorageError> {
+        let free_page_num = self.header.free_list_head;
+        if free_page_num == 0 {
+            self.header.page_count += 1;
+            return Ok(self.header.page_count - 1);
+        }
+
+        self.header.free_list_head = self.get_node(free_page_num)?.get_next_free_page_num();
+        Ok(free_page_num)
+    }
+
+    // pushes the page to the front of the free list, its content is dropped
+    fn free_page(&mut self, page_num: usize) -> Result<(), StorageError> {
+        self.set_node(Node::new_free(self.header.free_list_head), page_num)?;
+        self.header.free_list_head = page_num;
+        Ok(())
+    }
+
+    #[cfg(test)]
+    pub fn get_free_page_count(&mut self) -> Result<usize, StorageError> {
+        let mut free_page_count = 0;
+        let mut free_page_num = self.header.free_list_head;
+        while free_page_num != 0 {
+            free_page_count += 1;
+            free_page_num = self.get_node(free_page_num)?.get_next_free_page_num();
+        }
+        Ok(free_page_count)
+    }
+
+    #[cfg(test)]
+    pub fn get_wal_frame_count(&self) -> usize {
+        self.wal.get_frame_count()
+    }
+
+    #[cfg(test)]
     pub fn get_page_count(&self) -> usize {
-        self.nodes_count
+        self.header.get_page_count()
     }
 
     //todo: get rid of the page notation if possible, seems to have no use here
-    pub fn insert(&mut self, key: usize, value: &[u8], node_index: usize, cell_index: usize) -> anyhow::Result<()> {
-        let (current_node_max_key, current_node_cell_count, node_parent_page_num) = {
-            let current_node = self.get_node_mut(node_index);
-            (current_node.get_node_max_key(), current_node.get_cell_count(), current_node.get_parent_page_num())
+    pub fn insert(&mut self, key: &Key, value: &[u8], page_num: usize, cell_num: usize) -> anyhow::Result<()> {
+        self.begin_write()?;
+        let cell = if value.len() > self.layout.max_local_value {
+            // the prefix and the overflow page num together take max_local_value bytes
+            let local_len = self.layout.max_local_value - PAGE_NUM_SIZE;
+            let overflow_page_num = self.write_overflow_chain(&value[local_len..])?;
+            Cell::new_overflow(key, value.len(), &value[..local_len], overflow_page_num)
+        } else {
+            Cell::new(key, value)
         };
 
-        if LEAF_NODE_MAX_CELLS <= current_node_cell_count {
-            let right_child_page_num = self.leaf_node_split_and_insert(node_index, cell_index, key, value);
-
-            if node_parent_page_num == node_index {
-                Self::create_new_root_node(self, node_index, right_child_page_num);
-            } else {
-                let new_max_key = self.get_node_mut(node_index).get_node_max_key();
-                let mut parent_node = self.get_node_mut(node_parent_page_num);
-                parent_node.update_internal_node_key(current_node_max_key, new_max_key);
-                Self::internal_node_insert(self, node_parent_page_num, right_child_page_num);
-            }
+        if !self.get_node(page_num)?.has_room_for(&cell) {
+            self.leaf_node_split_and_insert(page_num, cell_num, cell)?;
             return Ok(());
         }
 
-        self.get_node_mut(node_index).insert_key_value(key, value, cell_index);
+        self.get_node_mut(page_num)?.insert_cell(cell, cell_num);
         Ok(())
     }
 
-    fn internal_node_insert(&mut self, parent_page_num: usize, new_page_num: usize) {
-        let (new_node_max_key) = {
-            let new_node = self.get_node_mut(new_page_num);
-            (new_node.get_node_max_key())
-        };
+    // writes the value into a chain of overflow pages, returns the page num of the first one.
+    // The chain is built from its end so every page is written knowing its successor
+    fn write_overflow_chain(&mut self, value: &[u8]) -> Result<usize, StorageError> {
+        let mut next_page_num = 0;
+        for payload in value.chunks(self.layout.overflow_page_capacity).rev() {
+            let page_num = self.allocate_page_num()?;
+            self.set_node(Node::new_overflow(payload, next_page_num), page_num)?;
+            next_page_num = page_num;
+        }
+        Ok(next_page_num)
+    }
+
+    fn free_overflow_chain(&mut self, mut page_num: usize) -> Result<(), StorageError> {
+        while page_num != 0 {
+            let next_page_num = self.get_node(page_num)?.get_next_overflow_page_num();
+            self.free_page(page_num)?;
+            page_num = next_page_num;
+        }
+        Ok(())
+    }
+
+    // the cells are spread by size over the old page and a new page which is then linked into the parent.
+    // A large cell landing in a full leaf may need two new pages, each is linked right after the previous one
+    fn leaf_node_split_and_insert(&mut self, page_num: usize, cell_num: usize, cell: Cell) -> Result<(), StorageError> {
+        let mut cells = self.get_node_mut(page_num)?.take_cells();
+        cells.insert(cell_num, cell);
+        let mut groups = split_cells(cells, self.layout.leaf_node_space);
+        let right_groups = groups.split_off(1);
+        self.get_node_mut(page_num)?.set_cells(groups.pop().unwrap());
+
+        let mut left_page_num = page_num;
+        for right_cells in right_groups {
+            let new_page_num = self.allocate_page_num()?;
+            let mut new_node = Node::new_leaf(&self.layout);
+            new_node.set_cells(right_cells);
 
-        let (new_child_index, original_parent_keys_num, parent_right_child_key) = {
-            let parent_node = self.get_node_mut(parent_page_num);
-            let new_child_index = parent_node.internal_find_child_index_by_key(new_node_max_key);
-            let original_parent_keys_num = parent_node.get_key_count();
-            let parent_right_child_key = parent_node.right_child_key;
-            (new_child_index, original_parent_keys_num, parent_right_child_key)
+            let left_node = self.get_node_mut(left_page_num)?;
+            let next_page_num = left_node.get_next_leaf_num();
+            new_node.set_next_leaf_num(next_page_num);
+            new_node.set_prev_leaf_num(left_page_num);
+            left_node.set_next_leaf_num(new_page_num);
+            let left_max_key = left_node.get_node_max_key();
+            if next_page_num != 0 {
+                self.get_node_mut(next_page_num)?.set_prev_leaf_num(new_page_num);
+            }
+
+            self.set_node(new_node, new_page_num)?;
+            self.insert_split_node(left_page_num, left_max_key, new_page_num)?;
+            left_page_num = new_page_num;
+        }
+        Ok(())
+    }
+
+    // links the freshly split off right node into the parent of the split node,
+    // growing a new root if the split node was the root
+    fn insert_split_node(&mut self, page_num: usize, left_max_key: Key, new_page_num: usize) -> Result<(), StorageError> {
+        let (is_root, parent_page_num) = {
+            let node = self.get_node(page_num)?;
+            (node.is_root, node.get_parent_page_num())
         };
 
-        if original_parent_keys_num >= LEAF_NODE_MAX_CELLS {
-            panic!("Need to implement internal node split")
+        if is_root {
+            return self.create_new_root_node(page_num, left_max_key, new_page_num);
         }
 
-        let right_child_node = self.get_node_mut(parent_right_child_key);
-        let right_child_node_max_key = right_child_node.get_node_max_key();
+        self.get_node_mut(new_page_num)?.set_parent_page_num(parent_page_num);
+        self.internal_node_insert(parent_page_num, page_num, left_max_key, new_page_num)
+    }
 
-        let parent_node = self.get_node_mut(parent_page_num);
+    fn internal_node_insert(&mut self, parent_page_num: usize, left_page_num: usize, left_max_key: Key, right_page_num: usize) -> Result<(), StorageError> {
+        let (keys, right_child_page_num) = {
+            let parent_node = self.get_node_mut(parent_page_num)?;
+            let left_child_index = parent_node.internal_find_child_index_by_page(left_page_num);
+            let mut keys = std::mem::take(&mut parent_node.keys);
+            let mut right_child_page_num = parent_node.right_child_key;
 
-        if new_node_max_key > right_child_node_max_key {
-            parent_node.internal_node_insert(right_child_node_max_key, parent_right_child_key);
-            parent_node.set_right_child_key(new_child_index)
-        } else {
-            parent_node.internal_node_insert_by_index(new_node_max_key, new_page_num, new_child_index)
+            if left_child_index == keys.len() {
+                // the split node was the right child, so the new node becomes the right child
+                keys.push(InternalCell::new(&left_max_key, left_page_num));
+                right_child_page_num = right_page_num;
+            } else {
+                let old_max_key = keys[left_child_index].get_key();
+                keys[left_child_index] = InternalCell::new(&left_max_key, left_page_num);
+                keys.insert(left_child_index + 1, InternalCell::new(&old_max_key, right_page_num));
+            }
+
+            (keys, right_child_page_num)
+        };
+
+        if keys.len() <= self.layout.internal_node_max_cells {
+            let parent_node = self.get_node_mut(parent_page_num)?;
+            parent_node.set_keys(keys);
+            parent_node.set_right_child_key(right_child_page_num);
+            return Ok(());
         }
+
+        self.internal_node_split(parent_page_num, keys, right_child_page_num)
     }
 
+    // the middle child becomes the right child of the left node,
+    // its key is promoted to the parent as the new max key of the left node
+    fn internal_node_split(&mut self, page_num: usize, mut keys: Vec<InternalCell>, right_child_page_num: usize) -> Result<(), StorageError> {
+        let new_page_num = self.allocate_page_num()?;
+        let split_index = keys.len() / 2;
+        let right_keys = keys.split_off(split_index + 1);
+        let middle = keys.pop().unwrap();
 
-    fn create_new_root_node(&mut self, root_page_num: usize, right_child_page_num: usize) {
-        let root_node_cell_count;
-        let last_key: usize;
-        let mut left_child_node = Node::new_leaf();
+        let mut new_node = Node::new_internal();
+        new_node.set_keys(right_keys);
+        new_node.set_right_child_key(right_child_page_num);
+        let moved_children = new_node.internal_children();
 
-        {
-            let root_node = self.get_node_mut(root_page_num);
-            root_node_cell_count = root_node.get_cell_count();
-            for cell_id in 0..root_node_cell_count {
-                left_child_node.insert_cell(root_node.get_mut_cell(cell_id).clone(), cell_id);
-            }
-            last_key = root_node.get_key(root_node_cell_count - 1);
+        let old_node = self.get_node_mut(page_num)?;
+        old_node.set_keys(keys);
+        old_node.set_right_child_key(middle.get_page_num());
+
+        self.set_node(new_node, new_page_num)?;
+        for child_page_num in moved_children {
+            self.get_node_mut(child_page_num)?.set_parent_page_num(new_page_num);
         }
 
+        self.insert_split_node(page_num, middle.get_key(), new_page_num)
+    }
+
+    // root always stays at the same page: its content is moved to a new left child page
+    // and the root is replaced with an internal node pointing to both halves
+    fn create_new_root_node(&mut self, root_page_num: usize, left_max_key: Key, right_child_page_num: usize) -> Result<(), StorageError> {
+        let left_child_page_num = self.allocate_page_num()?;
+
+        let mut left_child_node = self.get_node(root_page_num)?.clone();
+        left_child_node.set_is_root(false);
         left_child_node.set_parent_page_num(root_page_num);
-        left_child_node.set_next_leaf_num(right_child_page_num);
+        let moved_children = left_child_node.internal_children();
 
         let mut new_root_node = Node::new_internal();
+        new_root_node.set_is_root(true);
         new_root_node.set_parent_page_num(root_page_num);
+        new_root_node.internal_node_insert(&left_max_key, left_child_page_num);
+        new_root_node.set_right_child_key(right_child_page_num);
 
-        Self::set_node(self, left_child_node, self.nodes_count);
+        // a leaf root has only the right half as its sibling
+        let next_leaf_num = left_child_node.get_next_leaf_num();
+        self.set_node(left_child_node, left_child_page_num)?;
+        self.set_node(new_root_node, root_page_num)?;
+        if next_leaf_num != 0 {
+            self.get_node_mut(next_leaf_num)?.set_prev_leaf_num(left_child_page_num);
+        }
+        self.get_node_mut(right_child_page_num)?.set_parent_page_num(root_page_num);
+        for child_page_num in moved_children {
+            self.get_node_mut(child_page_num)?.set_parent_page_num(left_child_page_num);
+        }
+        Ok(())
+    }
 
-        new_root_node.set_is_root(true);
-        new_root_node.set_right_child_key(right_child_page_num);
-        new_root_node.internal_node_insert(
-            last_key,
-            self.nodes_count,
-        );
+    pub fn delete(&mut self, page_num: usize, cell_num: usize) -> anyhow::Result<()> {
+        self.begin_write()?;
+        let (is_root, old_max_key, cell_count, cell) = {
+            let node = self.get_node_mut(page_num)?;
+            let old_max_key = node.get_node_max_key();
+            let cell = node.remove_cell(cell_num);
+            (node.is_root, old_max_key, node.get_cell_count(), cell)
+        };
+        if let Some(overflow_page_num) = cell.get_overflow_page_num() {
+            self.free_overflow_chain(overflow_page_num)?;
+        }
 
-        self.nodes_count += 1;
-        self.nodes[root_page_num] = Some(new_root_node);
+        if is_root {
+            return Ok(());
+        }
+
+        // the root page never moves, it is looked up before rebalancing may free the leaf
+        let root_page_num = match cell_num == cell_count {
+            true => Some(self.find_root_page_num(page_num)?),
+            false => None,
+        };
+        if self.get_node(page_num)?.get_used_space() < self.layout.leaf_node_min_space {
+            self.rebalance(page_num)?;
+        }
+        if let Some(root_page_num) = root_page_num {
+            // the max key of the leaf was removed, keep the separator holding it tight
+            self.replace_separator(root_page_num, &old_max_key)?;
+        }
+
+        Ok(())
     }
 
-    fn set_node(&mut self, node: Node, page_num: usize) {
-        self.nodes[page_num] = Some(node);
+    fn find_root_page_num(&mut self, mut page_num: usize) -> Result<usize, StorageError> {
+        loop {
+            let node = self.get_node(page_num)?;
+            if node.is_root {
+                return Ok(page_num);
+            }
+            page_num = node.get_parent_page_num();
+        }
     }
 
-    // returning usize which is the new page num, but looks like a temp hack to me
-    fn leaf_node_split_and_insert(&mut self, page_num: usize, cell_num: usize, key: usize, value: &[u8]) -> usize {
-        let nodes_count = self.nodes_count;
-        let mut new_node = Node::new_leaf();
-        let mut old_node = self.get_node_mut(page_num);
-        new_node.set_parent_page_num(page_num);
-        new_node.set_next_leaf_num(old_node.get_next_leaf_num());
-        old_node.set_next_leaf_num(nodes_count);
+    // a removed key can still be the separator of the subtree that held it, on any level: in the parent
+    // of its leaf, or further up when the leaf is a rightmost child. It is replaced by the max key left
+    // in that subtree, otherwise seeks for keys after it would stop at the end of a leaf before theirs
+    fn replace_separator(&mut self, root_page_num: usize, removed_key: &Key) -> Result<(), StorageError> {
+        let mut page_num = root_page_num;
+        while self.get_node(page_num)?.get_node_type() == NodeType::Internal {
+            let node = self.get_node(page_num)?;
+            let index = node.internal_find_child_index_by_key(removed_key);
+            let child_page_num = node.internal_node_children(index);
+            if index < node.get_key_count() && node.internal_get_key(index) == *removed_key {
+                let new_key = self.get_subtree_max_key(child_page_num)?;
+                self.get_node_mut(page_num)?.internal_set_key(index, &new_key);
+                return Ok(());
+            }
+            page_num = child_page_num;
+        }
+        Ok(())
+    }
 
-        Self::redistribute_cells(&mut old_node, &mut new_node);
-        let old_node_cell_count = old_node.get_cell_count();
+    fn get_subtree_max_key(&mut self, mut page_num: usize) -> Result<Key, StorageError> {
+        loop {
+            let node = self.get_node(page_num)?;
+            if node.get_node_type() != NodeType::Internal {
+                return Ok(node.get_node_max_key());
+            }
+            page_num = node.right_child_key;
+        }
+    }
 
+    // restores the minimal fill of an underflowing non-root node, either by borrowing from a sibling
+    // or by merging with it. The left sibling is preferred, the leftmost child uses its right one.
+    // Leaves hold cells of different sizes, they merge whenever both fit into one page
+    // and share their cells evenly by size otherwise
+    fn rebalance(&mut self, page_num: usize) -> Result<(), StorageError> {
+        let parent_page_num = self.get_node(page_num)?.get_parent_page_num();
+        let (left_index, left_page_num, right_page_num, borrow_from_left) = {
+            let parent_node = self.get_node(parent_page_num)?;
+            let child_index = parent_node.internal_find_child_index_by_page(page_num);
+            let left_index = child_index.saturating_sub(1);
+            (
+                left_index,
+                parent_node.internal_node_children(left_index),
+                parent_node.internal_node_children(left_index + 1),
+                child_index > 0,
+            )
+        };
 
-        if cell_num <= old_node_cell_count {
-            old_node.insert_key_value(key, value, cell_num);
-        } else {
-            new_node.insert_key_value(key, value, cell_num - old_node_cell_count);
+        let sibling_page_num = if borrow_from_left { left_page_num } else { right_page_num };
+        let leaf_node_space = self.layout.leaf_node_space;
+        let can_borrow = match self.get_node(sibling_page_num)?.get_node_type() {
+            NodeType::Leaf => {
+                let used_space = self.get_node(page_num)?.get_used_space();
+                used_space + self.get_node(sibling_page_num)?.get_used_space() > leaf_node_space
+            }
+            NodeType::Internal => {
+                self.get_node(sibling_page_num)?.get_key_count() > self.layout.internal_node_min_cells
+            }
+            node_type @ (NodeType::Free | NodeType::Overflow) => {
+                return Err(StorageError::MisplacedPage { page_num: sibling_page_num, node_type })
+            }
+        };
+
+        if !can_borrow {
+            return self.merge_nodes(parent_page_num, left_index, left_page_num, right_page_num);
         }
 
-        Self::append_new_node(self, new_node);
+        let mut left_node = self.get_node(left_page_num)?.clone();
+        let mut right_node = self.get_node(right_page_num)?.clone();
+        let separator_key = self.get_node(parent_page_num)?.internal_get_key(left_index);
+
+        let (new_separator_key, moved_child_page_num) = match (left_node.get_node_type(), borrow_from_left) {
+            (NodeType::Leaf, _) => {
+                let mut cells = left_node.take_cells();
+                cells.extend(right_node.take_cells());
+                let mut groups = split_cells(cells, self.layout.leaf_node_space);
+                right_node.set_cells(groups.pop().unwrap());
+                left_node.set_cells(groups.pop().unwrap());
+                (left_node.get_node_max_key(), None)
+            }
+            (NodeType::Internal, true) => {
+                // the right child of the left node becomes the first child of the right node
+                let moved_child_page_num = left_node.right_child_key;
+                right_node.internal_node_insert_by_index(&separator_key, moved_child_page_num, 0);
+                let last_key = left_node.keys.pop().unwrap();
+                left_node.keys_count -= 1;
+                left_node.set_right_child_key(last_key.get_page_num());
+                (last_key.get_key(), Some((moved_child_page_num, right_page_num)))
+            }
+            (NodeType::Internal, false) => {
+                // the first child of the right node becomes the right child of the left node
+                let first_key = right_node.keys.remove(0);
+                right_node.keys_count -= 1;
+                left_node.internal_node_insert(&separator_key, left_node.right_child_key);
+                left_node.set_right_child_key(first_key.get_page_num());
+                (first_key.get_key(), Some((first_key.get_page_num(), left_page_num)))
+            }
+            (node_type @ (NodeType::Free | NodeType::Overflow), _) => {
+                return Err(StorageError::MisplacedPage { page_num: left_page_num, node_type })
+            }
+        };
 
-        // new page number
-        nodes_count
+        self.set_node(left_node, left_page_num)?;
+        self.set_node(right_node, right_page_num)?;
+        self.get_node_mut(parent_page_num)?.internal_set_key(left_index, &new_separator_key);
+        if let Some((child_page_num, new_parent_page_num)) = moved_child_page_num {
+            self.get_node_mut(child_page_num)?.set_parent_page_num(new_parent_page_num);
+        }
+        Ok(())
     }
 
-    fn append_new_node(&mut self, node: Node) {
-        self.nodes[self.nodes_count] = Some(node);
-        self.nodes_count += 1;
+    // moves everything from the right node into the left one and drops the right node from the parent,
+    // which may in turn underflow or, for the root, collapse
+    fn merge_nodes(&mut self, parent_page_num: usize, left_index: usize, left_page_num: usize, right_page_num: usize) -> Result<(), StorageError> {
+        let mut left_node = self.get_node(left_page_num)?.clone();
+        let mut right_node = self.get_node(right_page_num)?.clone();
+
+        match left_node.get_node_type() {
+            NodeType::Leaf => {
+                let mut cells = left_node.take_cells();
+                cells.extend(right_node.take_cells());
+                left_node.set_cells(cells);
+                left_node.set_next_leaf_num(right_node.next_leaf_num);
+                if right_node.next_leaf_num != 0 {
+                    self.get_node_mut(right_node.next_leaf_num)?.set_prev_leaf_num(left_page_num);
+                }
+            }
+            NodeType::Internal => {
+                let separator_key = self.get_node(parent_page_num)?.internal_get_key(left_index);
+                let mut keys = std::mem::take(&mut left_node.keys);
+                keys.push(InternalCell::new(&separator_key, left_node.right_child_key));
+                keys.extend(right_node.keys.iter().cloned());
+                left_node.set_keys(keys);
+                left_node.set_right_child_key(right_node.right_child_key);
+
+                for child_page_num in right_node.internal_children() {
+                    self.get_node_mut(child_page_num)?.set_parent_page_num(left_page_num);
+                }
+            }
+            node_type @ (NodeType::Free | NodeType::Overflow) => {
+                return Err(StorageError::MisplacedPage { page_num: left_page_num, node_type })
+            }
+        }
+        self.set_node(left_node, left_page_num)?;
+        self.free_page(right_page_num)?;
+
+        let (is_root, keys_count) = {
+            let parent_node = self.get_node_mut(parent_page_num)?;
+            parent_node.internal_merge_children(left_index);
+            (parent_node.is_root, parent_node.get_key_count())
+        };
+
+        if is_root && keys_count == 0 {
+            self.collapse_root(parent_page_num)?;
+        } else if !is_root && keys_count < self.layout.internal_node_min_cells {
+            self.rebalance(parent_page_num)?;
+        }
+        Ok(())
     }
 
-    fn redistribute_cells(split_old_node: &mut Node, split_new_node: &mut Node) {
-        let split_old_node_cell_count = split_old_node.get_cell_count();
+    // the root has a single child left: the child content moves into the root page, the tree gets one level lower
+    fn collapse_root(&mut self, root_page_num: usize) -> Result<(), StorageError> {
+        let child_page_num = self.get_node(root_page_num)?.right_child_key;
+        let mut child_node = self.get_node(child_page_num)?.clone();
+        child_node.set_is_root(true);
+        child_node.set_parent_page_num(root_page_num);
 
-        let old_node_new_max_cells = split_old_node_cell_count / 2;
+        for grandchild_page_num in child_node.internal_children() {
+            self.get_node_mut(grandchild_page_num)?.set_parent_page_num(root_page_num);
+        }
+        self.set_node(child_node, root_page_num)?;
+        self.free_page(child_page_num)
+    }
+
+    fn set_node(&mut self, node: Node, page_num: usize) -> Result<(), StorageError> {
+        self.record_before_image(page_num)?;
+        self.put_node(node, page_num)
+    }
+
+    // caches the node as modified without recording it for savepoints
+    fn put_node(&mut self, node: Node, page_num: usize) -> Result<(), StorageError> {
+        let evicted = self.buffer_pool.insert(page_num, node, true);
+        self.write_back(evicted)
+    }
+
+    // keeps the page as it was when the innermost savepoint was set, before its first modification
+    fn record_before_image(&mut self, page_num: usize) -> Result<(), StorageError> {
+        let page_count = match self.savepoints.last() {
+            Some(savepoint) if !savepoint.before_images.contains_key(&page_num) => savepoint.header.get_page_count(),
+            _ => return Ok(()),
+        };
 
-        for _ in old_node_new_max_cells..split_old_node_cell_count {
-            let cell = split_old_node.get_mut_cell(old_node_new_max_cells).clone();
-            split_new_node.push_cell(cell);
-            split_old_node.remove_cell(old_node_new_max_cells);
+        let before_image = if page_num < page_count {
+            Some(self.get_node(page_num)?.clone())
+        } else {
+            None
+        };
+        self.savepoints.last_mut().unwrap().before_images.insert(page_num, before_image);
+        Ok(())
+    }
+
+    // the whole value of the cell, an overflowing value is reassembled from its chain
+    pub fn select(&mut self, page_num: usize, cell_num: usize) -> Result<Vec<u8>, StorageError> {
+        let cell = self.get_node(page_num)?.get_cell(cell_num);
+        read_value(page_num, cell, |overflow_page_num| Ok(self.get_node(overflow_page_num)?.clone()))
+    }
+
+    // the value of a cell read from a leaf of the snapshot, see get_node_at
+    pub fn select_at(&self, page_num: usize, cell: Cell, snapshot: &Snapshot) -> Result<Vec<u8>, StorageError> {
+        read_value(page_num, cell, |overflow_page_num| self.get_node_at(overflow_page_num, snapshot))
+    }
+
+    // pins the last committed version of the database, pages read through the snapshot do not see
+    // later commits nor uncommitted changes. Old page versions are kept until the snapshot is ended
+    #[cfg(test)]
+    pub fn begin_snapshot(&mut self) -> anyhow::Result<Snapshot> {
+        self.refresh()?;
+        Ok(self.pin_snapshot())
+    }
+
+    // like begin_snapshot, without catching up with other connections first. Only needs shared access
+    pub fn pin_snapshot(&self) -> Snapshot {
+        Snapshot {
+            version: self.wal.begin_read(),
+            readers: self.wal.get_readers().clone(),
         }
     }
 
-    pub fn select(&mut self, page_num: usize, cell_num: usize) -> &[u8] {
-        let node = self.get_node_mut(page_num);
-        node.get_value(cell_num)
+    #[cfg(test)]
+    pub fn end_snapshot(&mut self, snapshot: Snapshot) {
+        drop(snapshot);
+        self.wal.collect_garbage();
     }
 
-    pub fn get_key(&mut self, page_num: usize, cell_num: usize) -> usize {
-        let node = self.get_node_mut(page_num);
-        node.get_key(cell_num)
+    // the page as committed at the version of the snapshot. Read around the buffer pool, which only
+    // holds the latest version of a page
+    pub fn get_node_at(&self, page_num: usize, snapshot: &Snapshot) -> Result<Node, StorageError> {
+        match self.wal.read_page_at(page_num, snapshot.version)? {
+            Some(buffer) => Node::deserialize(page_num, &buffer, &self.layout),
+            None => self.read_node_from_file(page_num),
+        }
     }
 
-    pub fn get_node(&self, page_num: usize) -> Option<&Node> {
-        self.nodes[page_num].as_ref()
+    pub fn get_key(&mut self, page_num: usize, cell_num: usize) -> Result<Key, StorageError> {
+        let node = self.get_node(page_num)?;
+        Ok(node.get_key(cell_num))
+    }
+
+    pub fn get_node(&mut self, page_num: usize) -> Result<&Node, StorageError> {
+        if !self.buffer_pool.contains(page_num) {
+            //cache miss
+            Self::load_page_from_file(self, page_num)?;
+        }
+
+        Ok(self.buffer_pool.get(page_num).unwrap())
     }
 
-    pub fn get_node_mut(&mut self, page_num: usize) -> &mut Node {
-        if self.nodes[page_num].is_none() {
+    // marks the page dirty, use get_node for reads
+    pub fn get_node_mut(&mut self, page_num: usize) -> Result<&mut Node, StorageError> {
+        self.record_before_image(page_num)?;
+        if !self.buffer_pool.contains(page_num) {
             //cache miss
-            Self::load_page_from_file(self, page_num);
+            Self::load_page_from_file(self, page_num)?;
         }
 
-        self.nodes[page_num].as_mut().unwrap()
+        Ok(self.buffer_pool.get_mut(page_num).unwrap())
+    }
+
+    // keeps the page cached until it is unpinned, used by cursors for the page they point at
+    pub fn pin(&mut self, page_num: usize) -> Result<(), StorageError> {
+        self.get_node(page_num)?;
+        self.buffer_pool.pin(page_num);
+        Ok(())
+    }
+
+    pub fn unpin(&mut self, page_num: usize) {
+        self.buffer_pool.unpin(page_num);
     }
 
+    // commits the modified pages to the WAL, they stay cached. Not allowed inside a transaction,
+    // its changes only become durable together on commit
     pub fn flush(&mut self) -> anyhow::Result<()> {
-        for i in 0..self.nodes.len() {
-            let node = self.nodes[i].take();
+        anyhow::ensure!(self.transaction.is_none(), "Cannot flush inside a transaction, commit or roll it back first");
+        self.commit_pages()?;
+        Ok(())
+    }
+
+    // the header goes last as the commit frame, once every page it counts is in the log.
+    // The database file itself is only written by checkpoints. Gives up RESERVED, nothing is left to commit
+    fn commit_pages(&mut self) -> Result<(), StorageError> {
+        if self.lock.get_level() < LockLevel::Reserved {
+            // nothing was changed
+            return Ok(());
+        }
+
+        for page_num in self.buffer_pool.dirty_pages() {
+            let bytes = self.buffer_pool.peek(page_num).unwrap().serialize(&self.layout);
+            self.wal.append(page_num, &bytes)?;
+            self.buffer_pool.mark_clean(page_num);
+        }
+        self.wal.commit(HEADER_PAGE_NUM, &self.header.serialize())?;
+
+        // skipped while other connections are open, the log grows until the last one leaves
+        if self.wal.get_frame_count() >= WAL_AUTOCHECKPOINT_FRAMES && self.lock.try_lock_exclusive()? {
+            let result = self.wal.checkpoint(&*self.file);
+            self.lock.release_exclusive()?;
+            result?;
+            self.remap();
+        }
+        self.lock.release_reserved()
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.transaction.is_some()
+    }
+
+    // changes made before BEGIN are committed first, so everything left uncommitted afterwards
+    // belongs to the transaction. RESERVED is taken right away and held until commit or rollback,
+    // the transaction starts from the latest commit of any connection
+    pub fn begin_transaction(&mut self) -> anyhow::Result<()> {
+        anyhow::ensure!(self.transaction.is_none(), "A transaction is already active");
+        self.commit_pages()?;
+        self.lock.reserve()?;
+        self.catch_up()?;
+        self.wal.begin_write()?;
+        self.transaction = Some(self.header.clone());
+        Ok(())
+    }
+
+    pub fn commit_transaction(&mut self) -> anyhow::Result<()> {
+        anyhow::ensure!(self.transaction.is_some(), "No transaction is active");
+        self.commit_pages()?;
+        self.transaction = None;
+        self.savepoints.clear();
+        Ok(())
+    }
+
+    // forgets every page modified since BEGIN, cached or already spilled to the WAL, and the header
+    // changes with them. Pages allocated by the transaction fall past the restored page count
+    pub fn rollback_transaction(&mut self) -> anyhow::Result<()> {
+        let header = self.transaction.take().ok_or_else(|| anyhow::anyhow!("No transaction is active"))?;
+        self.savepoints.clear();
+        self.buffer_pool.discard_dirty();
+        for page_num in self.wal.rollback()? {
+            self.buffer_pool.remove(page_num);
+        }
+        self.header = header;
+        self.lock.release_reserved()?;
+        Ok(())
+    }
+
+    pub fn savepoint(&mut self, name: &str) -> anyhow::Result<()> {
+        anyhow::ensure!(self.transaction.is_some(), "Savepoints are only allowed inside a transaction");
+        self.savepoints.push(Savepoint::new(Some(name), self.header.clone()));
+        Ok(())
+    }
 
-            match node {
-                None => {
-                    continue;
+    // forgets the savepoint and every one set after it, their changes stay part of the transaction
+    pub fn release_savepoint(&mut self, name: &str) -> anyhow::Result<()> {
+        let index = self.find_savepoint(name)?;
+        self.release_savepoints_from(index);
+        Ok(())
+    }
+
+    fn release_savepoints_from(&mut self, index: usize) {
+        let released = self.savepoints.split_off(index);
+        if let Some(outer) = self.savepoints.last_mut() {
+            // the outer savepoint keeps its own images, they are older
+            for savepoint in released {
+                for (page_num, before_image) in savepoint.before_images {
+                    outer.before_images.entry(page_num).or_insert(before_image);
                 }
-                Some(node) => {
-                    self.file
-                        .seek(io::SeekFrom::Start((i * PAGE_SIZE) as u64))
-                        .unwrap();
-                    self.file.write(&node.serialize()).unwrap();
-                    self.file.flush().unwrap();
+            }
+        }
+    }
+
+    // undoes every change made since the savepoint was set, splits and allocations included.
+    // The savepoint itself stays active, the ones set after it are gone
+    pub fn rollback_to_savepoint(&mut self, name: &str) -> anyhow::Result<()> {
+        let index = self.find_savepoint(name)?;
+        self.rollback_to_savepoint_at(index)
+    }
+
+    fn rollback_to_savepoint_at(&mut self, index: usize) -> anyhow::Result<()> {
+        let rolled_back = self.savepoints.split_off(index);
+        let name = rolled_back[0].name.clone();
+        let header = rolled_back[0].header.clone();
+
+        // innermost first, an outer savepoint holds the older image of a page both recorded
+        for savepoint in rolled_back.into_iter().rev() {
+            for (page_num, before_image) in savepoint.before_images {
+                match before_image {
+                    Some(node) => self.put_node(node, page_num)?,
+                    None => {
+                        self.buffer_pool.remove(page_num);
+                    }
                 }
             }
         }
 
+        self.header = header.clone();
+        self.savepoints.push(Savepoint {
+            name,
+            header,
+            before_images: HashMap::new(),
+        });
+        Ok(())
+    }
+
+    // a statement that changes several trees is undone as a whole when one of its changes fails,
+    // inside a transaction or not. Its savepoint has no name, the savepoints of the user cannot reach it
+    pub fn begin_statement(&mut self) -> anyhow::Result<()> {
+        // the header kept for the rollback is the one the changes start from
+        self.begin_write()?;
+        self.savepoints.push(Savepoint::new(None, self.header.clone()));
         Ok(())
     }
 
-    fn load_page_from_file(&mut self, page_num: usize) {
-        if page_num <= self.nodes_count {
-            self.file
-                .seek(io::SeekFrom::Start((page_num * PAGE_SIZE) as u64))
-                .unwrap();
-            let mut buffer = vec![0; PAGE_SIZE];
-            self.file.read(&mut buffer).unwrap();
-            self.nodes[page_num] = Some(Node::deserialize(&buffer));
-            self.nodes_count += 1;
+    pub fn end_statement(&mut self, succeeded: bool) -> anyhow::Result<()> {
+        let index = self
+            .savepoints
+            .iter()
+            .rposition(|savepoint| savepoint.name.is_none())
+            .ok_or_else(|| anyhow::anyhow!("No statement is active"))?;
+        if !succeeded {
+            self.rollback_to_savepoint_at(index)?;
+        }
+        self.release_savepoints_from(index);
+        Ok(())
+    }
+
+    // the innermost savepoint of that name
+    fn find_savepoint(&self, name: &str) -> anyhow::Result<usize> {
+        self.savepoints
+            .iter()
+            .rposition(|savepoint| savepoint.name.as_deref() == Some(name))
+            .ok_or_else(|| anyhow::anyhow!("No such savepoint: {}", name))
+    }
+
+    // commits the modified pages and copies the whole WAL into the database file. Needs EXCLUSIVE,
+    // waits up to the busy timeout for the other connections to close
+    #[cfg(test)]
+    pub fn checkpoint(&mut self) -> anyhow::Result<()> {
+        self.flush()?;
+        self.lock.reserve()?;
+        let result = self.lock.lock_exclusive().map_err(anyhow::Error::from).and_then(|_| {
+            // the last commits of connections closed in the meantime are only in the log
+            self.catch_up()?;
+            Ok(self.wal.checkpoint(&*self.file)?)
+        });
+        self.remap();
+        self.lock.release_exclusive()?;
+        self.lock.release_reserved()?;
+        result
+    }
+
+    // evicted pages go to the WAL uncommitted, they are read back from there until the next commit
+    fn write_back(&mut self, evicted: Vec<(usize, Node)>) -> Result<(), StorageError> {
+        for (page_num, node) in evicted {
+            self.wal.append(page_num, &node.serialize(&self.layout))?;
+        }
+        Ok(())
+    }
+
+    fn read_page_from_file(&self, page_num: usize) -> io::Result<Vec<u8>> {
+        let page_size = self.layout.page_size;
+        // a short read means the file was cut in the middle of the page
+        read_up_to(&*self.file, (page_num * page_size) as u64, page_size)
+    }
+
+    // decodes the page straight from the mapping if it covers the page
+    fn read_node_from_file(&self, page_num: usize) -> Result<Node, StorageError> {
+        let page_range = page_num * self.layout.page_size..(page_num + 1) * self.layout.page_size;
+        match &self.mapping {
+            Some(mapping) if page_range.end <= mapping.len() => Node::deserialize(page_num, &mapping[page_range], &self.layout),
+            _ => Node::deserialize(page_num, &self.read_page_from_file(page_num)?, &self.layout),
+        }
+    }
+
+    // maps the database file again once it grew, by a checkpoint. A file that cannot be mapped is read as usual
+    fn remap(&mut self) {
+        if !self.mmap {
+            return;
         }
+        let len = self.file.size().ok();
+        if self.mapping.as_ref().map(|mapping| mapping.len() as u64) != len {
+            self.mapping = self.file.map().ok().flatten();
+        }
+    }
+
+    pub fn get_mapped_len(&self) -> Option<usize> {
+        self.mapping.as_ref().map(|mapping| mapping.len())
+    }
+
+    fn load_page_from_file(&mut self, page_num: usize) -> Result<(), StorageError> {
+        // only a corrupted pointer leads to the header page or past the pages there are
+        if page_num == HEADER_PAGE_NUM || page_num >= self.header.page_count {
+            return Err(StorageError::PageOutOfRange {
+                page_num,
+                page_count: self.header.page_count,
+            });
+        }
+
+        if self.get_mapped_len().is_some_and(|len| (page_num + 1) * self.layout.page_size > len) {
+            self.remap();
+        }
+        let node = match self.wal.read_page(page_num)? {
+            Some(buffer) => Node::deserialize(page_num, &buffer, &self.layout)?,
+            None => self.read_node_from_file(page_num)?,
+        };
+        let evicted = self.buffer_pool.insert(page_num, node, false);
+        self.write_back(evicted)
+    }
+
+    // goes away like a crashed process: nothing is flushed or checkpointed, the locks are gone
+    #[cfg(test)]
+    pub fn crash(mut self) {
+        self.lock.release_all().unwrap();
+        std::mem::forget(self);
+    }
+}
+
+// committed frames are moved into the database file when the last connection closes and the WAL is removed.
+// Pages modified since the last flush are dropped
+impl Drop for Pager {
+    fn drop(&mut self) {
+        self.wal.end_all_reads();
+        // the last connection to leave cleans up, the others still read from the log
+        if !matches!(self.lock.try_reserve(), Ok(true)) || !matches!(self.lock.try_lock_exclusive(), Ok(true)) {
+            return;
+        }
+        if self.catch_up().is_ok() && self.wal.checkpoint(&*self.file).is_ok() {
+            let _ = self.vfs.delete(self.wal.get_path());
+        }
+    }
+}
+
+// splits cells into as few leaves of the given space as possible, two leaves are balanced by size.
+// A third leaf is only needed when a cell larger than half of the page lands in a full leaf
+fn split_cells(mut cells: Vec<Cell>, space: usize) -> Vec<Vec<Cell>> {
+    let total_space: usize = cells.iter().map(Cell::get_space).sum();
+    let mut left_space = 0;
+    let mut best_split: Option<(usize, usize)> = None;
+    for split_index in 1..cells.len() {
+        left_space += cells[split_index - 1].get_space();
+        let right_space = total_space - left_space;
+        let imbalance = left_space.abs_diff(right_space);
+        if left_space <= space && right_space <= space && best_split.is_none_or(|(_, best)| imbalance < best) {
+            best_split = Some((split_index, imbalance));
+        }
+    }
+
+    if let Some((split_index, _)) = best_split {
+        let right_cells = cells.split_off(split_index);
+        return vec![cells, right_cells];
+    }
+
+    let mut groups = vec![Vec::new()];
+    let mut used_space = 0;
+    for cell in cells {
+        if used_space + cell.get_space() > space {
+            groups.push(Vec::new());
+            used_space = 0;
+        }
+        used_space += cell.get_space();
+        groups.last_mut().unwrap().push(cell);
+    }
+    groups
+}
+
+// the local part of the value followed by its overflow chain, get_overflow_node loads the chain pages
+fn read_value(
+    page_num: usize,
+    cell: Cell,
+    mut get_overflow_node: impl FnMut(usize) -> Result<Node, StorageError>,
+) -> Result<Vec<u8>, StorageError> {
+    let mut value = Vec::with_capacity(cell.get_value_len());
+    value.extend_from_slice(cell.get_local_value());
+
+    let mut overflow_page_num = cell.get_overflow_page_num().unwrap_or(0);
+    while overflow_page_num != 0 && value.len() < cell.get_value_len() {
+        let overflow_node = get_overflow_node(overflow_page_num)?;
+        if overflow_node.get_node_type() != NodeType::Overflow {
+            break;
+        }
+        value.extend_from_slice(overflow_node.get_overflow_payload());
+        overflow_page_num = overflow_node.get_next_overflow_page_num();
+    }
+
+    if value.len() != cell.get_value_len() {
+        return Err(StorageError::InvalidOverflowChain { page_num });
     }
+    Ok(value)
 }
 
 #[cfg(test)]
 mod test {
-    use std::{fs, panic};
+    use std::fs;
+    use std::fs::OpenOptions;
 
+    use crate::storage::cursor::Cursor;
+    use crate::storage::fault_vfs::{Crash, FaultVfs};
+    use crate::storage::key::KeyValue;
     use crate::storage::row::Row;
+    use crate::storage::table::Table;
+    use crate::storage::test_util::{
+        delete_rows, insert_rows, open_test_table_with_config, scan_ids,
+    };
+    use crate::storage::vfs::MemoryVfs;
 
     use super::*;
 
-    // #[test]
-    // fn test_pager() {
-    //     let test_db_path = "test.db";
-    //
-    //     let result = panic::catch_unwind(|| {
-    //         let mut pager = Pager::new(test_db_path).unwrap();
-    //         let mut node = pager.get_node_mut(0);
-    //         let row = Row::new(1, "hello world".to_string(), "hello world".to_string());
-    //         node.insert_key_value(&row.get_id().to_le_bytes(), &row.serialize().unwrap(), 0);
-    //
-    //         pager.flush().unwrap();
-    //         let mut pager = Pager::new(test_db_path).unwrap();
-    //         let node = pager.get_node_mut(0);
-    //         let row = Row::deserialize(node.get_value(0)).unwrap();
-    //         assert_eq!(row.get_id(), 1);
-    //         assert_eq!(row.get_user_name(), "hello world");
-    //
-    //         fs::remove_file(test_db_path).expect("Failed to remove test database file");
-    //     });
-    //
-    //     // Re-panic if the test failed
-    //     assert!(result.is_ok());
-    // }
-    //
-    // #[test]
-    // fn test_node_split() {
-    //     let test_db_path = "test.db";
-    //
-    //     let mut pager = Pager::new(test_db_path).unwrap();
-    //     let row = Row::new(1, "hello world".to_string(), "hello world".to_string());
-    //     pager.insert(&(row.get_id() as usize).to_le_bytes(), &row.serialize().unwrap(), 0, 0).unwrap();
-    //     let row = Row::new(2, "hello world".to_string(), "hello world".to_string());
-    //     pager.insert(&(row.get_id() as usize).to_le_bytes(), &row.serialize().unwrap(), 0, 1).unwrap();
-    //     let row = Row::new(3, "hello world".to_string(), "hello world".to_string());
-    //     pager.insert(&(row.get_id() as usize).to_le_bytes(), &row.serialize().unwrap(), 0, 2).unwrap();
-    //     let row = Row::new(4, "hello world".to_string(), "hello world".to_string());
-    //     pager.insert(&(row.get_id() as usize).to_le_bytes(), &row.serialize().unwrap(), 0, 3).unwrap();
-    // }
+    // three rows per leaf, keeps the tree deep enough for splits and merges on every level with a few thousand keys
+    const TEST_PAGE_SIZE: usize = 1024;
+
+    fn open_small_page_table(test_db_path: &str) -> Table {
+        open_test_table_with_config(test_db_path, &ConnectionConfig::new().with_page_size(TEST_PAGE_SIZE))
+    }
+
+    fn check_tree(pager: &mut Pager) -> Option<Key> {
+        let root_page_num = pager.get_root_page_num();
+        let max_key = check_subtree(pager, root_page_num, None, None);
+
+        // the leaves are linked both ways in key order
+        let mut leaves = Vec::new();
+        collect_leaves(pager, root_page_num, &mut leaves);
+        for (index, &page_num) in leaves.iter().enumerate() {
+            let node = pager.get_node(page_num).unwrap();
+            let prev_page_num = if index == 0 { 0 } else { leaves[index - 1] };
+            let next_page_num = leaves.get(index + 1).copied().unwrap_or(0);
+            assert_eq!(node.get_prev_leaf_num(), prev_page_num);
+            assert_eq!(node.get_next_leaf_num(), next_page_num);
+        }
+        max_key
+    }
+
+    fn collect_leaves(pager: &mut Pager, page_num: usize, leaves: &mut Vec<usize>) {
+        let node = pager.get_node(page_num).unwrap().clone();
+        match node.node_type {
+            NodeType::Internal => {
+                for child_num in 0..=node.get_key_count() {
+                    collect_leaves(pager, node.internal_node_children(child_num), leaves);
+                }
+            }
+            _ => leaves.push(page_num),
+        }
+    }
+
+    // walks the subtree and checks parent links and key bounds, returns the max key of the subtree
+    fn check_subtree(pager: &mut Pager, page_num: usize, lower_bound: Option<Key>, upper_bound: Option<Key>) -> Option<Key> {
+        let node = pager.get_node(page_num).unwrap().clone();
+        let layout = *pager.get_layout();
+        match node.node_type {
+            NodeType::Leaf => {
+                assert!(node.is_root || node.get_cell_count() > 0);
+                assert!(node.get_used_space() <= layout.leaf_node_space);
+                let keys: Vec<Key> = (0..node.get_cell_count()).map(|cell_num| node.get_key(cell_num)).collect();
+                assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+                assert!(keys.iter().all(|key| lower_bound.as_ref().is_none_or(|bound| key > bound)));
+                assert!(keys.iter().all(|key| upper_bound.as_ref().is_none_or(|bound| key <= bound)));
+                keys.last().cloned()
+            }
+            NodeType::Internal => {
+                assert!(node.get_key_count() <= layout.internal_node_max_cells);
+                assert!(node.get_key_count() >= if node.is_root { 1 } else { layout.internal_node_min_cells });
+                let mut max_key = None;
+                let mut child_lower_bound = lower_bound;
+                for child_num in 0..=node.get_key_count() {
+                    let child_page_num = node.internal_node_children(child_num);
+                    assert_eq!(pager.get_node(child_page_num).unwrap().get_parent_page_num(), page_num);
+
+                    let child_upper_bound = if child_num < node.get_key_count() {
+                        Some(node.internal_get_key(child_num))
+                    } else {
+                        upper_bound.clone()
+                    };
+                    let child_max_key = check_subtree(pager, child_page_num, child_lower_bound, child_upper_bound.clone());
+                    if child_num < node.get_key_count() {
+                        // separators are tight, a stale one would send seeks to a leaf without the key
+                        assert_eq!(child_max_key, child_upper_bound);
+                    }
+                    max_key = child_max_key.or(max_key);
+                    child_lower_bound = child_upper_bound;
+                }
+                max_key
+            }
+            node_type @ (NodeType::Free | NodeType::Overflow) => {
+                panic!("{:?} page {} is linked into the tree", node_type, page_num)
+            }
+        }
+    }
+
+    #[test]
+    fn test_leaf_node_split() {
+        let test_db_path = "test_leaf_node_split.db";
+        let mut table = open_small_page_table(test_db_path);
+        let root_page_num = table.get_root_page_num();
+        let mut key_count = 0;
+        while table.get_pager_mut().get_node(root_page_num).unwrap().get_node_type() == NodeType::Leaf {
+            key_count += 1;
+            insert_rows(&mut table, key_count..=key_count);
+        }
+
+        let pager = table.get_pager_mut();
+        let root_node = pager.get_node(root_page_num).unwrap();
+        assert!(root_node.is_root);
+        assert_eq!(root_node.get_key_count(), 1);
+        assert_eq!(check_tree(pager), Some(Key::int(key_count.into())));
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_internal_node_split() {
+        let test_db_path = "test_internal_node_split.db";
+        let mut table = open_small_page_table(test_db_path);
+        insert_rows(&mut table, 1..=1000);
+
+        let pager = table.get_pager_mut();
+        let root_page_num = pager.get_root_page_num();
+        let root_node = pager.get_node(root_page_num).unwrap();
+        assert_eq!(root_node.get_node_type(), NodeType::Internal);
+        let first_child_page_num = root_node.internal_node_children(0);
+        assert_eq!(pager.get_node(first_child_page_num).unwrap().get_node_type(), NodeType::Internal);
+        assert_eq!(check_tree(pager), Some(Key::int(1000)));
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_internal_node_split_descending() {
+        let test_db_path = "test_internal_node_split_descending.db";
+        let mut table = open_small_page_table(test_db_path);
+        insert_rows(&mut table, (1..=1000).rev());
+
+        assert_eq!(check_tree(table.get_pager_mut()), Some(Key::int(1000)));
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_text_and_composite_keys() {
+        let test_db_path = "test_text_and_composite_keys.db";
+        let mut table = open_small_page_table(test_db_path);
+        // a text column compared by its bytes, ties broken by a descending int column
+        let key = |id: i64| Key::new(&[KeyValue::Text(format!("name{}", id % 50)), KeyValue::Int(-id)]).unwrap();
+        let scan = |table: &mut Table| {
+            let mut entries = Vec::new();
+            let mut cursor = Cursor::table_start(table).unwrap();
+            while !cursor.is_end_of_table() {
+                entries.push((cursor.get_key().unwrap().unwrap(), cursor.select().unwrap()));
+                cursor.advance().unwrap();
+            }
+            entries
+        };
+        let ids: Vec<i64> = (0..1000).map(|i| i * 37 % 1000).collect();
+        for &id in &ids {
+            let key = key(id);
+            Cursor::table_find(&mut table, &key).unwrap().insert_key_value(&key, format!("value{}", id).as_bytes()).unwrap();
+        }
+
+        let mut expected: Vec<(Key, Vec<u8>)> = ids.iter().map(|&id| (key(id), format!("value{}", id).into_bytes())).collect();
+        expected.sort();
+        assert_eq!(expected.first().unwrap().0, key(950));
+        assert_eq!(check_tree(table.get_pager_mut()), Some(key(9)));
+        assert_eq!(scan(&mut table), expected);
+
+        for id in ids.iter().filter(|&id| id % 3 == 0) {
+            let mut cursor = Cursor::table_find(&mut table, &key(*id)).unwrap();
+            assert_eq!(cursor.get_key().unwrap(), Some(key(*id)));
+            cursor.delete().unwrap();
+        }
+        expected.retain(|(key, _)| !matches!(key.values()[1], KeyValue::Int(id) if id % 3 == 0));
+        check_tree(table.get_pager_mut());
+        assert_eq!(scan(&mut table), expected);
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_delete_from_root_leaf() {
+        let test_db_path = "test_delete_from_root_leaf.db";
+        let mut table = open_small_page_table(test_db_path);
+        insert_rows(&mut table, 1..=3);
+        delete_rows(&mut table, [2].into_iter());
+        assert_eq!(scan_ids(&mut table), vec![1, 3]);
+
+        delete_rows(&mut table, [1, 3].into_iter());
+        assert!(scan_ids(&mut table).is_empty());
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_delete_ascending_collapses_root() {
+        let test_db_path = "test_delete_ascending_collapses_root.db";
+        let mut table = open_small_page_table(test_db_path);
+        insert_rows(&mut table, 1..=1000);
+
+        for key in 1..=1000 {
+            delete_rows(&mut table, [key].into_iter());
+            if key % 97 == 0 {
+                check_tree(table.get_pager_mut());
+                assert_eq!(scan_ids(&mut table), (key + 1..=1000).collect::<Vec<u32>>());
+            }
+        }
+
+        let root_page_num = table.get_root_page_num();
+        let root_node = table.get_pager_mut().get_node(root_page_num).unwrap();
+        assert_eq!(root_node.get_node_type(), NodeType::Leaf);
+        assert!(root_node.is_root);
+        assert_eq!(root_node.get_cell_count(), 0);
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_delete_descending() {
+        let test_db_path = "test_delete_descending.db";
+        let mut table = open_small_page_table(test_db_path);
+        insert_rows(&mut table, 1..=1000);
+
+        delete_rows(&mut table, (501..=1000).rev());
+        check_tree(table.get_pager_mut());
+        assert_eq!(scan_ids(&mut table), (1..=500).collect::<Vec<u32>>());
+
+        delete_rows(&mut table, (1..=500).rev());
+        assert!(scan_ids(&mut table).is_empty());
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_delete_interleaved_and_reinsert() {
+        let test_db_path = "test_delete_interleaved_and_reinsert.db";
+        let mut table = open_small_page_table(test_db_path);
+        insert_rows(&mut table, 1..=2000);
+
+        // every third key, scattered over all leaves
+        delete_rows(&mut table, (1..=2000).filter(|key| key % 3 == 0));
+        check_tree(table.get_pager_mut());
+        assert_eq!(scan_ids(&mut table), (1..=2000).filter(|key| key % 3 != 0).collect::<Vec<u32>>());
+
+        delete_rows(&mut table, (1..=2000).filter(|key| key % 3 == 1).rev());
+        check_tree(table.get_pager_mut());
+        assert_eq!(scan_ids(&mut table), (1..=2000).filter(|key| key % 3 == 2).collect::<Vec<u32>>());
+
+        insert_rows(&mut table, (1..=2000).filter(|key| key % 3 != 2));
+        check_tree(table.get_pager_mut());
+        assert_eq!(scan_ids(&mut table), (1..=2000).collect::<Vec<u32>>());
+
+        table.flush().unwrap();
+        let mut table = Table::open_db_connection(test_db_path).unwrap();
+        assert_eq!(scan_ids(&mut table), (1..=2000).collect::<Vec<u32>>());
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_deleting_max_key_of_rightmost_leaf() {
+        let test_db_path = "test_deleting_max_key_of_rightmost_leaf.db";
+        let mut table = open_small_page_table(test_db_path);
+        insert_rows(&mut table, 1..=3000);
+        // the max keys of leaves that are the rightmost child of their parent, their separators are in
+        // the grandparent or higher
+        for key in [50, 98, 146, 194] {
+            delete_rows(&mut table, std::iter::once(key));
+            check_tree(table.get_pager_mut());
+        }
+        assert_eq!(scan_ids(&mut table).len(), 2996);
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_deleted_pages_are_reused() {
+        let test_db_path = "test_deleted_pages_are_reused.db";
+        let mut table = open_small_page_table(test_db_path);
+        insert_rows(&mut table, 1..=1000);
+        let page_count = table.get_pager().get_page_count();
+
+        delete_rows(&mut table, 1..=1000);
+        let pager = table.get_pager_mut();
+        // everything but the header and the root ends up in the free list
+        assert_eq!(pager.get_free_page_count().unwrap(), page_count - 2);
+
+        insert_rows(&mut table, 1..=1000);
+        check_tree(table.get_pager_mut());
+        assert_eq!(table.get_pager().get_page_count(), page_count);
+        assert_eq!(scan_ids(&mut table), (1..=1000).collect::<Vec<u32>>());
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_free_list_survives_reopen() {
+        let test_db_path = "test_free_list_survives_reopen.db";
+        let mut table = open_small_page_table(test_db_path);
+        insert_rows(&mut table, 1..=1000);
+        delete_rows(&mut table, 201..=1000);
+        let page_count = table.get_pager().get_page_count();
+        let free_page_count = table.get_pager_mut().get_free_page_count().unwrap();
+        assert!(free_page_count > 0);
+        table.flush().unwrap();
+
+        let mut table = Table::open_db_connection(test_db_path).unwrap();
+        assert_eq!(table.get_pager().get_page_count(), page_count);
+        assert_eq!(table.get_pager_mut().get_free_page_count().unwrap(), free_page_count);
+
+        insert_rows(&mut table, 201..=600);
+        check_tree(table.get_pager_mut());
+        assert_eq!(table.get_pager().get_page_count(), page_count);
+        assert!(table.get_pager_mut().get_free_page_count().unwrap() < free_page_count);
+        assert_eq!(scan_ids(&mut table), (1..=600).collect::<Vec<u32>>());
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_small_cache_writes_back_evicted_pages() {
+        let test_db_path = "test_small_cache_writes_back_evicted_pages.db";
+        let config = ConnectionConfig::new().with_cache_size(4).with_page_size(TEST_PAGE_SIZE);
+        let mut table = open_test_table_with_config(test_db_path, &config);
+        insert_rows(&mut table, (1..=2000).rev());
+        delete_rows(&mut table, (1..=2000).filter(|key| key % 4 == 0));
+        assert!(table.get_pager().buffer_pool.len() <= 4);
+
+        let expected_keys: Vec<u32> = (1..=2000).filter(|key| key % 4 != 0).collect();
+        check_tree(table.get_pager_mut());
+        assert_eq!(scan_ids(&mut table), expected_keys);
+        table.flush().unwrap();
+
+        let mut table = Table::open_db_connection_with_config(test_db_path, &config).unwrap();
+        assert_eq!(scan_ids(&mut table), expected_keys);
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_flush_keeps_pages_cached_and_clean() {
+        let test_db_path = "test_flush_keeps_pages_cached_and_clean.db";
+        let mut table = open_small_page_table(test_db_path);
+        insert_rows(&mut table, 1..=20);
+        let pager = table.get_pager_mut();
+        assert!(!pager.buffer_pool.dirty_pages().is_empty());
+
+        pager.flush().unwrap();
+        assert!(pager.buffer_pool.dirty_pages().is_empty());
+        assert!(pager.buffer_pool.contains(pager.get_root_page_num()));
+
+        // reads do not dirty pages
+        scan_ids(&mut table);
+        assert!(table.get_pager().buffer_pool.dirty_pages().is_empty());
+
+        insert_rows(&mut table, 21..=21);
+        let dirty_pages = table.get_pager().buffer_pool.dirty_pages();
+        assert!(!dirty_pages.is_empty() && dirty_pages.len() < table.get_pager().get_page_count());
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_corrupted_page_is_reported() {
+        let test_db_path = "test_corrupted_page_is_reported.db";
+        let mut table = open_small_page_table(test_db_path);
+        insert_rows(&mut table, 1..=20);
+        let root_page_num = table.get_root_page_num();
+        let leaf_page_num = table.get_pager_mut().get_node(root_page_num).unwrap().internal_node_children(0);
+        table.checkpoint().unwrap();
+
+        let mut bytes = fs::read(test_db_path).unwrap();
+        bytes[leaf_page_num * TEST_PAGE_SIZE + TEST_PAGE_SIZE / 2] ^= 0xff;
+        fs::write(test_db_path, &bytes).unwrap();
+
+        let mut table = Table::open_db_connection(test_db_path).unwrap();
+        let err = table.get_pager_mut().get_node_mut(leaf_page_num).unwrap_err();
+        assert!(matches!(err, StorageError::ChecksumMismatch { page_num, .. } if page_num == leaf_page_num));
+
+        // the error reaches the cursor instead of a panic
+        let err = Cursor::table_find(&mut table, &Key::int(1)).err().unwrap();
+        assert!(format!("{:#}", err).contains(&format!("Page {} is corrupted", leaf_page_num)));
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_corrupted_page_num_is_reported() {
+        let test_db_path = "test_corrupted_page_num_is_reported.db";
+        let mut table = open_small_page_table(test_db_path);
+        insert_rows(&mut table, 1..=20);
+        let row = Row::new(21, "user21".to_string(), "b".repeat(10_000));
+        Cursor::table_find(&mut table, &Key::int(21)).unwrap().insert(&row).unwrap();
+        let root_page_num = table.get_root_page_num();
+        let page_count = table.get_pager().get_page_count();
+        let pager = table.get_pager_mut();
+        let overflow_page_num = (1..page_count)
+            .find(|&page_num| pager.get_node(page_num).unwrap().get_node_type() == NodeType::Overflow)
+            .unwrap();
+
+        // pointers to the header page and past the end of the file
+        for page_num in [HEADER_PAGE_NUM, page_count + 3] {
+            table.get_pager_mut().get_node_mut(root_page_num).unwrap().set_right_child_key(page_num);
+            let err = Cursor::table_end(&mut table).err().unwrap();
+            assert!(matches!(err.downcast_ref::<StorageError>(), Some(StorageError::PageOutOfRange { page_num: found, .. }) if *found == page_num));
+            assert_eq!(table.get_pager().get_page_count(), page_count);
+        }
+
+        table.get_pager_mut().get_node_mut(root_page_num).unwrap().set_right_child_key(overflow_page_num);
+        let err = Cursor::table_end(&mut table).err().unwrap();
+        assert!(matches!(err.downcast_ref::<StorageError>(), Some(StorageError::MisplacedPage { node_type: NodeType::Overflow, .. })));
+
+        // a sibling that is not a tree node fails the rebalancing of an underflowing leaf
+        drop(table);
+        let mut table = open_small_page_table(test_db_path);
+        insert_rows(&mut table, 1..=20);
+        let row = Row::new(21, "user21".to_string(), "b".repeat(10_000));
+        Cursor::table_find(&mut table, &Key::int(21)).unwrap().insert(&row).unwrap();
+        Cursor::table_find(&mut table, &Key::int(21)).unwrap().delete().unwrap();
+        let root = table.get_pager_mut().get_node_mut(root_page_num).unwrap();
+        let last_index = root.get_key_count() - 1;
+        let key = root.internal_get_key(last_index);
+        root.keys[last_index] = InternalCell::new(&key, overflow_page_num);
+        let err = (1..=20)
+            .rev()
+            .find_map(|key| Cursor::table_find(&mut table, &Key::int(key)).unwrap().delete().err())
+            .unwrap();
+        assert!(matches!(err.root_cause().downcast_ref::<StorageError>(), Some(StorageError::MisplacedPage { .. })));
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_torn_page_is_reported() {
+        let test_db_path = "test_torn_page_is_reported.db";
+        let mut table = open_small_page_table(test_db_path);
+        insert_rows(&mut table, 1..=20);
+        let page_count = table.get_pager().get_page_count();
+        table.checkpoint().unwrap();
+
+        let file = OpenOptions::new().write(true).open(test_db_path).unwrap();
+        file.set_len(((page_count - 1) * TEST_PAGE_SIZE + 100) as u64).unwrap();
+        drop(file);
+
+        let mut pager = Pager::new(test_db_path, &ConnectionConfig::default()).unwrap();
+        assert!(matches!(
+            pager.get_node(page_count - 1),
+            Err(StorageError::TornPage { len: 100, .. })
+        ));
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_page_size_is_kept_on_reopen() {
+        let test_db_path = "test_page_size_is_kept_on_reopen.db";
+        let config = ConnectionConfig::new().with_page_size(8192);
+        let mut table = open_test_table_with_config(test_db_path, &config);
+        insert_rows(&mut table, 1..=500);
+        let page_count = table.get_pager().get_page_count();
+        table.checkpoint().unwrap();
+        assert_eq!(fs::metadata(test_db_path).unwrap().len(), (page_count * 8192) as u64);
+
+        // the page size of the file wins over the configured one
+        let mut table = Table::open_db_connection(test_db_path).unwrap();
+        assert_eq!(table.get_pager().get_layout().page_size, 8192);
+        check_tree(table.get_pager_mut());
+        assert_eq!(scan_ids(&mut table), (1..=500).collect::<Vec<u32>>());
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_unsupported_page_size_is_rejected() {
+        let test_db_path = "test_unsupported_page_size_is_rejected.db";
+        let _ = fs::remove_file(test_db_path);
+        let config = ConnectionConfig::new().with_page_size(1000);
+        assert!(Table::open_db_connection_with_config(test_db_path, &config).is_err());
+
+        let config = ConnectionConfig::new().with_page_size(128 * 1024);
+        assert!(Table::open_db_connection_with_config(test_db_path, &config).is_err());
+        assert!(!fs::exists(format!("{}-wal", test_db_path)).unwrap());
+
+        let _ = fs::remove_file(test_db_path);
+    }
+
+    #[test]
+    fn test_smallest_page_holds_a_row_per_leaf() {
+        let test_db_path = "test_smallest_page_holds_a_row_per_leaf.db";
+        let config = ConnectionConfig::new().with_page_size(MIN_PAGE_SIZE);
+        let mut table = open_test_table_with_config(test_db_path, &config);
+        insert_rows(&mut table, 1..=200);
+        delete_rows(&mut table, (1..=200).filter(|key| key % 2 == 0));
+        check_tree(table.get_pager_mut());
+        assert_eq!(scan_ids(&mut table), (1..=200).filter(|key| key % 2 == 1).collect::<Vec<u32>>());
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    fn long_row(key: u32) -> Row {
+        // from a few bytes up to several overflow pages
+        let email = format!("user{}@mail.com", key).repeat(key as usize * 37 % 500);
+        Row::new(key, format!("user{}", key), email)
+    }
+
+    #[test]
+    fn test_long_rows_use_overflow_pages() {
+        let test_db_path = "test_long_rows_use_overflow_pages.db";
+        let mut table = open_small_page_table(test_db_path);
+        for key in 1..=200 {
+            Cursor::table_find(&mut table, &Key::int(key.into())).unwrap().insert(&long_row(key)).unwrap();
+        }
+        check_tree(table.get_pager_mut());
+        table.flush().unwrap();
+
+        let mut table = Table::open_db_connection(test_db_path).unwrap();
+        for key in 1..=200 {
+            let mut cursor = Cursor::table_find(&mut table, &Key::int(key.into())).unwrap();
+            let row = Row::deserialize(&cursor.select().unwrap()).unwrap();
+            assert_eq!(row.get_email(), long_row(key).get_email());
+        }
+
+        // the chains of deleted rows go to the free list and are reused by the next long rows
+        delete_rows(&mut table, (1..=200).filter(|key| key % 2 == 0));
+        let page_count = table.get_pager().get_page_count();
+        assert!(table.get_pager_mut().get_free_page_count().unwrap() > 0);
+        for key in (1..=200).filter(|key| key % 2 == 0) {
+            Cursor::table_find(&mut table, &Key::int(key.into())).unwrap().insert(&long_row(key)).unwrap();
+        }
+        check_tree(table.get_pager_mut());
+        assert!(table.get_pager().get_page_count() <= page_count + 2);
+        assert_eq!(scan_ids(&mut table), (1..=200).collect::<Vec<u32>>());
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_deleting_long_row_frees_its_chain() {
+        let test_db_path = "test_deleting_long_row_frees_its_chain.db";
+        let mut table = open_small_page_table(test_db_path);
+        let row = Row::new(1, "user1".to_string(), "x".repeat(10 * TEST_PAGE_SIZE));
+        Cursor::table_find(&mut table, &Key::int(1)).unwrap().insert(&row).unwrap();
+        let page_count = table.get_pager().get_page_count();
+        assert!(page_count > 10);
+
+        delete_rows(&mut table, [1].into_iter());
+        // everything but the header and the root
+        assert_eq!(table.get_pager_mut().get_free_page_count().unwrap(), page_count - 2);
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_broken_overflow_chain_is_reported() {
+        let test_db_path = "test_broken_overflow_chain_is_reported.db";
+        let mut table = open_small_page_table(test_db_path);
+        let row = Row::new(1, "user1".to_string(), "x".repeat(3 * TEST_PAGE_SIZE));
+        Cursor::table_find(&mut table, &Key::int(1)).unwrap().insert(&row).unwrap();
+        let root_page_num = table.get_root_page_num();
+        let overflow_page_num = table.get_pager_mut().get_node(root_page_num).unwrap().get_cell(0).get_overflow_page_num();
+        // cut the chain after its first page
+        table.get_pager_mut().get_node_mut(overflow_page_num.unwrap()).unwrap().next_overflow_page_num = 0;
+
+        let err = table.select(root_page_num, 0).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<StorageError>(),
+            Some(StorageError::InvalidOverflowChain { page_num }) if *page_num == root_page_num
+        ));
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_committed_changes_survive_crash() {
+        let test_db_path = "test_committed_changes_survive_crash.db";
+        let mut table = open_small_page_table(test_db_path);
+        insert_rows(&mut table, 1..=500);
+        table.flush().unwrap();
+        insert_rows(&mut table, 501..=600);
+        // the connection goes away without closing, nothing but the WAL holds the commit
+        table.crash();
+        assert_eq!(fs::metadata(test_db_path).unwrap().len(), (2 * TEST_PAGE_SIZE) as u64);
+
+        let mut table = Table::open_db_connection(test_db_path).unwrap();
+        check_tree(table.get_pager_mut());
+        assert_eq!(scan_ids(&mut table), (1..=500).collect::<Vec<u32>>());
+        drop(table);
+        assert!(fs::metadata(format!("{}-wal", test_db_path)).is_err());
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_evicted_pages_are_not_committed() {
+        let test_db_path = "test_evicted_pages_are_not_committed.db";
+        let config = ConnectionConfig::new().with_cache_size(4).with_page_size(TEST_PAGE_SIZE);
+        let mut table = open_test_table_with_config(test_db_path, &config);
+        insert_rows(&mut table, 1..=100);
+        table.flush().unwrap();
+        // evicted pages are written to the WAL, but no commit frame follows them
+        insert_rows(&mut table, 101..=2000);
+        assert_eq!(scan_ids(&mut table), (1..=2000).collect::<Vec<u32>>());
+        table.crash();
+
+        let mut table = Table::open_db_connection_with_config(test_db_path, &config).unwrap();
+        check_tree(table.get_pager_mut());
+        assert_eq!(scan_ids(&mut table), (1..=100).collect::<Vec<u32>>());
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_rollback_undoes_splits_and_new_root() {
+        let test_db_path = "test_rollback_undoes_splits_and_new_root.db";
+        let mut table = open_small_page_table(test_db_path);
+        insert_rows(&mut table, 1..=3);
+        let page_count = table.get_pager().get_page_count();
+
+        table.begin_transaction().unwrap();
+        insert_rows(&mut table, 4..=2000);
+        delete_rows(&mut table, 1..=2);
+        let root_page_num = table.get_root_page_num();
+        assert_eq!(table.get_pager_mut().get_node(root_page_num).unwrap().get_node_type(), NodeType::Internal);
+        table.rollback().unwrap();
+
+        let pager = table.get_pager_mut();
+        assert_eq!(pager.get_node(root_page_num).unwrap().get_node_type(), NodeType::Leaf);
+        assert_eq!(pager.get_page_count(), page_count);
+        assert_eq!(pager.get_free_page_count().unwrap(), 0);
+        assert_eq!(scan_ids(&mut table), vec![1, 2, 3]);
+
+        // the pages of the rolled back transaction are handed out again
+        insert_rows(&mut table, 4..=2000);
+        check_tree(table.get_pager_mut());
+        assert_eq!(scan_ids(&mut table), (1..=2000).collect::<Vec<u32>>());
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_rollback_drops_pages_spilled_to_wal() {
+        let test_db_path = "test_rollback_drops_pages_spilled_to_wal.db";
+        let config = ConnectionConfig::new().with_cache_size(4).with_page_size(TEST_PAGE_SIZE);
+        let mut table = open_test_table_with_config(test_db_path, &config);
+        insert_rows(&mut table, 1..=300);
+
+        table.begin_transaction().unwrap();
+        insert_rows(&mut table, 301..=2000);
+        delete_rows(&mut table, 1..=100);
+        table.rollback().unwrap();
+        assert_eq!(scan_ids(&mut table), (1..=300).collect::<Vec<u32>>());
+
+        table.begin_transaction().unwrap();
+        insert_rows(&mut table, 301..=400);
+        table.commit().unwrap();
+        table.crash();
+
+        // neither the rolled back frames nor the cache survive, only the two commits
+        let mut table = Table::open_db_connection_with_config(test_db_path, &config).unwrap();
+        check_tree(table.get_pager_mut());
+        assert_eq!(scan_ids(&mut table), (1..=400).collect::<Vec<u32>>());
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_rollback_to_savepoint_undoes_splits() {
+        let test_db_path = "test_rollback_to_savepoint_undoes_splits.db";
+        let mut table = open_small_page_table(test_db_path);
+        insert_rows(&mut table, 1..=3);
+        table.begin_transaction().unwrap();
+        insert_rows(&mut table, 4..=10);
+        table.savepoint("a").unwrap();
+        let page_count = table.get_pager().get_page_count();
+
+        insert_rows(&mut table, 11..=2000);
+        delete_rows(&mut table, 4..=6);
+        table.rollback_to_savepoint("a").unwrap();
+
+        let root_page_num = table.get_root_page_num();
+        let pager = table.get_pager_mut();
+        assert_eq!(pager.get_node(root_page_num).unwrap().get_node_type(), NodeType::Leaf);
+        assert_eq!(pager.get_page_count(), page_count);
+        assert_eq!(scan_ids(&mut table), (1..=10).collect::<Vec<u32>>());
+
+        // the savepoint is still there and can be rolled back to again
+        insert_rows(&mut table, 11..=1000);
+        table.rollback_to_savepoint("a").unwrap();
+        insert_rows(&mut table, 11..=20);
+        table.commit().unwrap();
+        table.crash();
+
+        let mut table = Table::open_db_connection(test_db_path).unwrap();
+        check_tree(table.get_pager_mut());
+        assert_eq!(scan_ids(&mut table), (1..=20).collect::<Vec<u32>>());
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_nested_savepoints() {
+        let test_db_path = "test_nested_savepoints.db";
+        let mut table = open_small_page_table(test_db_path);
+        assert!(table.savepoint("a").is_err());
+
+        table.begin_transaction().unwrap();
+        insert_rows(&mut table, 1..=100);
+        table.savepoint("a").unwrap();
+        insert_rows(&mut table, 101..=300);
+        table.savepoint("b").unwrap();
+        insert_rows(&mut table, 301..=600);
+        delete_rows(&mut table, 1..=50);
+
+        table.rollback_to_savepoint("b").unwrap();
+        assert_eq!(scan_ids(&mut table), (1..=300).collect::<Vec<u32>>());
+
+        // released changes are undone by a rollback to the outer savepoint
+        insert_rows(&mut table, 301..=400);
+        delete_rows(&mut table, 1..=10);
+        table.release_savepoint("b").unwrap();
+        assert!(table.rollback_to_savepoint("b").is_err());
+        assert_eq!(scan_ids(&mut table), (11..=400).collect::<Vec<u32>>());
+        table.rollback_to_savepoint("a").unwrap();
+        check_tree(table.get_pager_mut());
+        assert_eq!(scan_ids(&mut table), (1..=100).collect::<Vec<u32>>());
+
+        // a repeated name refers to the innermost savepoint
+        table.savepoint("a").unwrap();
+        insert_rows(&mut table, 101..=200);
+        table.rollback_to_savepoint("a").unwrap();
+        table.release_savepoint("a").unwrap();
+        table.rollback_to_savepoint("a").unwrap();
+        table.release_savepoint("a").unwrap();
+        assert!(table.release_savepoint("a").is_err());
+
+        // the savepoints the statements set on their own cannot be named
+        table.savepoint("").unwrap();
+        insert_rows(&mut table, 101..=200);
+        table.rollback_to_savepoint("").unwrap();
+        table.release_savepoint("").unwrap();
+        assert!(table.release_savepoint("").is_err());
+
+        table.commit().unwrap();
+        assert_eq!(scan_ids(&mut table), (1..=100).collect::<Vec<u32>>());
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_rollback_to_savepoint_restores_spilled_pages() {
+        let test_db_path = "test_rollback_to_savepoint_restores_spilled_pages.db";
+        let config = ConnectionConfig::new().with_cache_size(4).with_page_size(TEST_PAGE_SIZE);
+        let mut table = open_test_table_with_config(test_db_path, &config);
+        table.begin_transaction().unwrap();
+        insert_rows(&mut table, 1..=500);
+        table.savepoint("a").unwrap();
+        insert_rows(&mut table, 501..=1500);
+        delete_rows(&mut table, 1..=250);
+        table.rollback_to_savepoint("a").unwrap();
+
+        check_tree(table.get_pager_mut());
+        assert_eq!(scan_ids(&mut table), (1..=500).collect::<Vec<u32>>());
+        table.commit().unwrap();
+        table.crash();
+
+        let mut table = Table::open_db_connection_with_config(test_db_path, &config).unwrap();
+        check_tree(table.get_pager_mut());
+        assert_eq!(scan_ids(&mut table), (1..=500).collect::<Vec<u32>>());
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    fn is_locked(err: &anyhow::Error) -> bool {
+        matches!(err.downcast_ref::<StorageError>(), Some(StorageError::Locked))
+    }
+
+    #[test]
+    fn test_second_writer_is_locked_out() {
+        let test_db_path = "test_second_writer_is_locked_out.db";
+        let mut first = open_small_page_table(test_db_path);
+        let mut second = Table::open_db_connection(test_db_path).unwrap();
+        insert_rows(&mut first, 1..=100);
+        assert_eq!(first.get_pager().get_lock_level(), LockLevel::Reserved);
+
+        // readers are not blocked, they see the last commit
+        assert!(scan_ids(&mut second).is_empty());
+        let row = Row::new(1_000, "user".to_string(), "user@mail.com".to_string());
+        let err = Cursor::table_find(&mut second, &Key::int(1_000)).unwrap().insert(&row).unwrap_err();
+        assert!(is_locked(&err));
+        assert!(format!("{:#}", err).ends_with("Database is locked"));
+        assert!(is_locked(&second.begin_transaction().unwrap_err()));
+
+        first.flush().unwrap();
+        assert_eq!(first.get_pager().get_lock_level(), LockLevel::Shared);
+        assert_eq!(scan_ids(&mut second), (1..=100).collect::<Vec<u32>>());
+        insert_rows(&mut second, 101..=200);
+        second.flush().unwrap();
+        check_tree(first.get_pager_mut());
+        assert_eq!(scan_ids(&mut first), (1..=200).collect::<Vec<u32>>());
+
+        // the database file is only rewritten once the other connection is gone
+        assert!(is_locked(&first.checkpoint().unwrap_err()));
+        drop(second);
+        first.checkpoint().unwrap();
+        assert_eq!(first.get_pager().get_wal_frame_count(), 0);
+        drop(first);
+
+        let mut table = Table::open_db_connection(test_db_path).unwrap();
+        assert_eq!(scan_ids(&mut table), (1..=200).collect::<Vec<u32>>());
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_write_based_on_stale_pages_is_rejected() {
+        let test_db_path = "test_write_based_on_stale_pages_is_rejected.db";
+        let mut first = open_small_page_table(test_db_path);
+        let mut second = Table::open_db_connection(test_db_path).unwrap();
+        insert_rows(&mut first, 1..=100);
+        first.flush().unwrap();
+
+        let row = Row::new(1_000, "user".to_string(), "user@mail.com".to_string());
+        let mut cursor = Cursor::table_find(&mut second, &Key::int(1_000)).unwrap();
+        // another connection commits between the read and the write
+        delete_rows(&mut first, 1..=90);
+        first.flush().unwrap();
+        assert!(is_locked(&cursor.insert(&row).unwrap_err()));
+        drop(cursor);
+
+        // a retry starts from the new commit
+        Cursor::table_find(&mut second, &Key::int(1_000)).unwrap().insert(&row).unwrap();
+        second.flush().unwrap();
+        check_tree(second.get_pager_mut());
+        let mut expected_keys: Vec<u32> = (91..=100).collect();
+        expected_keys.push(1_000);
+        assert_eq!(scan_ids(&mut first), expected_keys);
+
+        drop(first);
+        drop(second);
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_busy_timeout_waits_for_the_writer() {
+        let test_db_path = "test_busy_timeout_waits_for_the_writer.db";
+        let mut first = open_small_page_table(test_db_path);
+        let config = ConnectionConfig::new().with_busy_timeout(std::time::Duration::from_secs(10));
+        let mut second = Table::open_db_connection_with_config(test_db_path, &config).unwrap();
+        insert_rows(&mut first, 1..=100);
+
+        let writer = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            first.flush().unwrap();
+            first
+        });
+        second.begin_transaction().unwrap();
+        insert_rows(&mut second, 101..=200);
+        second.commit().unwrap();
+        let mut first = writer.join().unwrap();
+        assert_eq!(scan_ids(&mut first), (1..=200).collect::<Vec<u32>>());
+
+        drop(first);
+        drop(second);
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_mmap_follows_file_growth() {
+        let test_db_path = "test_mmap_follows_file_growth.db";
+        let config = ConnectionConfig::new().with_page_size(TEST_PAGE_SIZE).with_cache_size(8).with_mmap(true);
+        let mut table = open_test_table_with_config(test_db_path, &config);
+        assert_eq!(table.get_pager().get_mapped_len(), Some(2 * TEST_PAGE_SIZE));
+        insert_rows(&mut table, 1..=500);
+        table.checkpoint().unwrap();
+        let file_len = fs::metadata(test_db_path).unwrap().len() as usize;
+        assert!(file_len > 2 * TEST_PAGE_SIZE);
+        assert_eq!(table.get_pager().get_mapped_len(), Some(file_len));
+        // evicted pages are decoded from the mapping again
+        assert_eq!(scan_ids(&mut table), (1..=500).collect::<Vec<u32>>());
+        drop(table);
+
+        let mut table = Table::open_db_connection_with_config(test_db_path, &config).unwrap();
+        check_tree(table.get_pager_mut());
+        insert_rows(&mut table, 501..=1_000);
+        table.flush().unwrap();
+        assert_eq!(scan_ids(&mut table), (1..=1_000).collect::<Vec<u32>>());
+        drop(table);
+
+        // a backend without files to map is read as usual
+        let config = config.with_vfs(Arc::new(MemoryVfs::new()));
+        let mut table = Table::open_db_connection_with_config(test_db_path, &config).unwrap();
+        assert_eq!(table.get_pager().get_mapped_len(), None);
+        insert_rows(&mut table, 1..=100);
+        table.checkpoint().unwrap();
+        assert_eq!(scan_ids(&mut table), (1..=100).collect::<Vec<u32>>());
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    const CRASH_TEST_DB_PATH: &str = "crash_test.db";
+    const CRASH_TEST_BATCH_COUNT: usize = 8;
+    const CRASH_TEST_BATCH_SIZE: usize = 12;
+
+    fn crash_test_config(vfs: &FaultVfs, sync_mode: SyncMode) -> ConnectionConfig {
+        ConnectionConfig::new()
+            .with_page_size(TEST_PAGE_SIZE)
+            .with_cache_size(16)
+            .with_sync_mode(sync_mode)
+            .with_vfs(Arc::new(vfs.clone()))
+    }
+
+    // keys of the first batches of the crash workload, sorted. Spread over the key space so that every
+    // batch splits leaves all over the tree
+    fn crash_test_keys(batch_count: usize) -> Vec<u32> {
+        let mut keys: Vec<u32> = (0..(batch_count * CRASH_TEST_BATCH_SIZE) as u32).map(|i| i * 37 % 97 + 1).collect();
+        keys.sort_unstable();
+        keys
+    }
+
+    // commits the batches one by one with a checkpoint halfway and closes the database, up to the first
+    // I/O error. Returns the number of batches committed and the number of them checkpointed
+    fn run_crash_workload(config: &ConnectionConfig) -> (usize, usize) {
+        let mut committed = 0;
+        let mut checkpointed = 0;
+        let _ = (|| -> anyhow::Result<()> {
+            let mut table = Table::open_db_connection_with_config(CRASH_TEST_DB_PATH, config)?;
+            for batch in 0..CRASH_TEST_BATCH_COUNT {
+                for i in batch * CRASH_TEST_BATCH_SIZE..(batch + 1) * CRASH_TEST_BATCH_SIZE {
+                    let key = i as u32 * 37 % 97 + 1;
+                    let row = Row::new(key, format!("user{}", key), format!("user{}@mail.com", key));
+                    Cursor::table_find(&mut table, &Key::int(key.into()))?.insert(&row)?;
+                }
+                table.flush()?;
+                committed += 1;
+                if batch == CRASH_TEST_BATCH_COUNT / 2 {
+                    table.checkpoint()?;
+                    checkpointed = committed;
+                }
+            }
+            Ok(())
+        })();
+        (committed, checkpointed)
+    }
+
+    #[test]
+    fn test_recovery_after_crash_at_every_io_step() {
+        for sync_mode in [SyncMode::Full, SyncMode::Normal] {
+            // a run without failures counts the steps
+            let vfs = FaultVfs::new();
+            let done = (CRASH_TEST_BATCH_COUNT, CRASH_TEST_BATCH_COUNT / 2 + 1);
+            assert_eq!(run_crash_workload(&crash_test_config(&vfs, sync_mode)), done);
+            assert!(!vfs.has_failed());
+
+            for step in 0..vfs.get_step_count() {
+                let vfs = FaultVfs::new();
+                vfs.fail_at_step(step);
+                let (committed, checkpointed) = run_crash_workload(&crash_test_config(&vfs, sync_mode));
+                assert!(vfs.has_failed());
+                // commits are durable once they return in full mode, once they are checkpointed otherwise.
+                // The commit cut short by the crash may or may not have made it
+                let durable = if sync_mode == SyncMode::Full { committed } else { checkpointed };
+                let batch_counts = durable..=(committed + 1).min(CRASH_TEST_BATCH_COUNT);
+
+                for crash in [Crash::Process, Crash::PowerLoss, Crash::TornWrites] {
+                    let context = format!("{:?} at step {} in {:?} mode", crash, step, sync_mode);
+                    let config = crash_test_config(&vfs.restart(crash), sync_mode);
+                    let mut table = Table::open_db_connection_with_config(CRASH_TEST_DB_PATH, &config)
+                        .unwrap_or_else(|err| panic!("{}: reopen failed: {:#}", context, err));
+                    check_tree(table.get_pager_mut());
+                    let keys = scan_ids(&mut table);
+                    assert!(
+                        batch_counts.clone().any(|batch_count| keys == crash_test_keys(batch_count)),
+                        "{}: recovered {} keys, expected the keys of {:?} batches",
+                        context,
+                        keys.len(),
+                        batch_counts
+                    );
+
+                    // whatever the crash left behind does not get in the way of the next commit
+                    insert_rows(&mut table, 1_000..=1_010);
+                    table.flush().unwrap();
+                    check_tree(table.get_pager_mut());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_short_reads() {
+        let vfs = FaultVfs::new().with_short_reads();
+        let config = crash_test_config(&vfs, SyncMode::Full);
+        run_crash_workload(&config);
+        let mut table = Table::open_db_connection_with_config(CRASH_TEST_DB_PATH, &config).unwrap();
+        check_tree(table.get_pager_mut());
+        assert_eq!(scan_ids(&mut table), crash_test_keys(CRASH_TEST_BATCH_COUNT));
+    }
 }
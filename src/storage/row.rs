@@ -1,7 +1,8 @@
-use crate::storage::constant::ROW_SIZE;
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
+use crate::storage::key::Key;
+
 //todo: enforce char count for the string smh
 #[derive(Debug, Serialize, Deserialize)]
 pub(crate) struct Row {
@@ -23,6 +24,11 @@ impl Row {
         self.id
     }
 
+    // rows are keyed by their id, there is no other primary key type
+    pub fn get_key(&self) -> Key {
+        Key::int(self.id.into())
+    }
+
     pub fn get_email(&self) -> &str {
         &self.email
     }
@@ -32,20 +38,36 @@ impl Row {
     }
 
     //todo: custom serializer and deserializer are a pain for the time being, so using this hack for now
+    // rows are stored at their real length, long ones continue in overflow pages
     pub fn serialize(&self) -> Result<Vec<u8>> {
-        serde_json::to_vec(self)
-            .map_err(|err| anyhow::Error::msg(err))
-            .map(|mut bytes| {
-                bytes.resize(ROW_SIZE, 0);
-                bytes
-            })
+        serde_json::to_vec(self).map_err(anyhow::Error::msg)
     }
 
     pub fn deserialize(bytes: &[u8]) -> Result<Row> {
-        let offset = bytes[0..ROW_SIZE]
-            .iter()
-            .position(|&byte| byte == 0u8)
-            .unwrap_or(ROW_SIZE);
-        serde_json::from_slice(&bytes[0..offset]).map_err(|err| anyhow::Error::msg(err))
+        serde_json::from_slice(bytes).map_err(anyhow::Error::msg)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_row_is_stored_at_its_length() {
+        let row = Row::new(1, "a".to_string(), "b".to_string());
+        let bytes = row.serialize().unwrap();
+        assert_eq!(bytes, br#"{"id":1,"email":"b","user_name":"a"}"#);
+
+        let row = Row::deserialize(&bytes).unwrap();
+        assert_eq!(row.get_user_name(), "a");
+        assert_eq!(row.get_email(), "b");
+    }
+
+    #[test]
+    fn test_long_row_is_kept_whole() {
+        let email = "b".repeat(100_000);
+        let row = Row::new(1, "a".to_string(), email.clone());
+        let row = Row::deserialize(&row.serialize().unwrap()).unwrap();
+        assert_eq!(row.get_email(), email);
     }
 }
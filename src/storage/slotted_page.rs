@@ -0,0 +1,263 @@
+use crate::storage::constant::{LEAF_NODE_CELLS_OFFSET, SLOT_SIZE};
+
+// Cell area of a leaf page. The slot array starts right after the leaf header and grows towards the end
+// of the page, cell contents are stored in a heap growing from the end of the page towards the slots:
+//
+//   | headers | slot 0 | slot 1 | ... | free space | cell 1 | hole | cell 0 |
+//                                     ^           ^
+//                        end of the slot array    heap_start
+//
+// A slot is the u16 offset of the cell in the page followed by its u16 length, both little endian.
+// Slots are kept in key order, cells in the heap are in no particular order. Removing a cell leaves a hole
+// in the heap, the holes are counted in fragmented_bytes and reclaimed by defragment once the contiguous
+// free space runs out.
+//
+// data holds the whole page so it can be written to disk as is, the header bytes are filled in by the node
+#[derive(Clone, PartialEq, Debug, Default)]
+pub(crate) struct SlottedPage {
+    data: Vec<u8>,
+    cell_count: usize,
+    heap_start: usize,
+    fragmented_bytes: usize,
+}
+
+impl SlottedPage {
+    pub fn new(page_size: usize) -> Self {
+        Self {
+            data: vec![0; page_size],
+            cell_count: 0,
+            heap_start: page_size,
+            fragmented_bytes: 0,
+        }
+    }
+
+    // rebuilds the cell area from a page read from disk, None if the slots or the heap do not add up
+    pub fn from_page(
+        page: &[u8],
+        cell_count: usize,
+        heap_start: usize,
+        fragmented_bytes: usize,
+    ) -> Option<Self> {
+        let page_size = page.len();
+        if cell_count > (page_size - LEAF_NODE_CELLS_OFFSET) / SLOT_SIZE
+            || heap_start < LEAF_NODE_CELLS_OFFSET + cell_count * SLOT_SIZE
+            || heap_start > page_size
+            || fragmented_bytes > page_size - heap_start
+        {
+            return None;
+        }
+
+        let mut data = page.to_vec();
+        // header bytes belong to the node, keep them out of the cell area
+        data[..LEAF_NODE_CELLS_OFFSET].fill(0);
+        let slotted_page = Self {
+            data,
+            cell_count,
+            heap_start,
+            fragmented_bytes,
+        };
+
+        let cells_fit = (0..cell_count).all(|cell_num| {
+            let (offset, len) = slotted_page.slot(cell_num);
+            offset >= heap_start && offset + len <= page_size
+        });
+        cells_fit.then_some(slotted_page)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn len(&self) -> usize {
+        self.cell_count
+    }
+
+    #[cfg(test)]
+    pub fn is_empty(&self) -> bool {
+        self.cell_count == 0
+    }
+
+    pub fn get_heap_start(&self) -> usize {
+        self.heap_start
+    }
+
+    pub fn get_fragmented_bytes(&self) -> usize {
+        self.fragmented_bytes
+    }
+
+    pub fn get(&self, cell_num: usize) -> &[u8] {
+        let (offset, len) = self.slot(cell_num);
+        &self.data[offset..offset + len]
+    }
+
+    // space taken by the cells and their slots
+    pub fn used_space(&self) -> usize {
+        self.data.len() - LEAF_NODE_CELLS_OFFSET - self.free_space()
+    }
+
+    // contiguous free space plus the holes left by removed cells
+    pub fn free_space(&self) -> usize {
+        self.contiguous_free_space() + self.fragmented_bytes
+    }
+
+    pub fn can_insert(&self, cell: &[u8]) -> bool {
+        cell.len() + SLOT_SIZE <= self.free_space()
+    }
+
+    // inserts the cell before the cell_num slot, false if the page has no room left for it
+    pub fn insert(&mut self, cell_num: usize, cell: &[u8]) -> bool {
+        if !self.can_insert(cell) {
+            return false;
+        }
+        if cell.len() + SLOT_SIZE > self.contiguous_free_space() {
+            self.defragment();
+        }
+
+        self.heap_start -= cell.len();
+        self.data[self.heap_start..self.heap_start + cell.len()].copy_from_slice(cell);
+
+        let slot_offset = Self::slot_offset(cell_num);
+        let slots_end = Self::slot_offset(self.cell_count);
+        self.data.copy_within(slot_offset..slots_end, slot_offset + SLOT_SIZE);
+        self.cell_count += 1;
+        self.set_slot(cell_num, self.heap_start, cell.len());
+        true
+    }
+
+    pub fn remove(&mut self, cell_num: usize) -> Vec<u8> {
+        let (offset, len) = self.slot(cell_num);
+        let cell = self.data[offset..offset + len].to_vec();
+        self.data[offset..offset + len].fill(0);
+        if offset == self.heap_start {
+            self.heap_start += len;
+        } else {
+            self.fragmented_bytes += len;
+        }
+
+        let slot_offset = Self::slot_offset(cell_num);
+        let slots_end = Self::slot_offset(self.cell_count);
+        self.data.copy_within(slot_offset + SLOT_SIZE..slots_end, slot_offset);
+        self.data[slots_end - SLOT_SIZE..slots_end].fill(0);
+        self.cell_count -= 1;
+        cell
+    }
+
+    // moves every cell to the end of the page so the holes become contiguous free space again
+    pub fn defragment(&mut self) {
+        let cells: Vec<Vec<u8>> = (0..self.cell_count).map(|cell_num| self.get(cell_num).to_vec()).collect();
+        let page_size = self.data.len();
+        self.data[self.heap_start..].fill(0);
+        self.heap_start = page_size;
+        self.fragmented_bytes = 0;
+
+        for (cell_num, cell) in cells.iter().enumerate() {
+            self.heap_start -= cell.len();
+            self.data[self.heap_start..self.heap_start + cell.len()].copy_from_slice(cell);
+            self.set_slot(cell_num, self.heap_start, cell.len());
+        }
+    }
+
+    fn contiguous_free_space(&self) -> usize {
+        self.heap_start - Self::slot_offset(self.cell_count)
+    }
+
+    fn slot_offset(cell_num: usize) -> usize {
+        LEAF_NODE_CELLS_OFFSET + cell_num * SLOT_SIZE
+    }
+
+    fn slot(&self, cell_num: usize) -> (usize, usize) {
+        let slot_offset = Self::slot_offset(cell_num);
+        let offset = u16::from_le_bytes([self.data[slot_offset], self.data[slot_offset + 1]]) as usize;
+        let len = u16::from_le_bytes([self.data[slot_offset + 2], self.data[slot_offset + 3]]) as usize;
+        (offset, len)
+    }
+
+    fn set_slot(&mut self, cell_num: usize, offset: usize, len: usize) {
+        let slot_offset = Self::slot_offset(cell_num);
+        self.data[slot_offset..slot_offset + 2].copy_from_slice(&(offset as u16).to_le_bytes());
+        self.data[slot_offset + 2..slot_offset + 4].copy_from_slice(&(len as u16).to_le_bytes());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_insert_keeps_slot_order() {
+        let mut page = SlottedPage::new(512);
+        assert!(page.insert(0, b"bbb"));
+        assert!(page.insert(0, b"a"));
+        assert!(page.insert(2, b"cccccc"));
+
+        assert_eq!(page.len(), 3);
+        assert_eq!(page.get(0), b"a");
+        assert_eq!(page.get(1), b"bbb");
+        assert_eq!(page.get(2), b"cccccc");
+        assert_eq!(page.used_space(), 10 + 3 * SLOT_SIZE);
+    }
+
+    #[test]
+    fn test_remove_leaves_hole_until_defragmented() {
+        let mut page = SlottedPage::new(512);
+        for (cell_num, cell) in [b"aaaa", b"bbbb", b"cccc"].iter().enumerate() {
+            page.insert(cell_num, *cell);
+        }
+        let free_space = page.free_space();
+
+        // the first cell sits at the end of the page, removing it punches a hole
+        assert_eq!(page.remove(0), b"aaaa");
+        assert_eq!(page.get_fragmented_bytes(), 4);
+        assert_eq!(page.free_space(), free_space + 4 + SLOT_SIZE);
+
+        page.defragment();
+        assert_eq!(page.get_fragmented_bytes(), 0);
+        assert_eq!(page.get_heap_start(), 512 - 8);
+        assert_eq!(page.get(0), b"bbbb");
+        assert_eq!(page.get(1), b"cccc");
+    }
+
+    #[test]
+    fn test_insert_defragments_when_contiguous_space_runs_out() {
+        let page_size = 512;
+        let cell_size = 40;
+        let mut page = SlottedPage::new(page_size);
+        let mut cell_count = 0;
+        while page.insert(cell_count, &vec![cell_count as u8; cell_size]) {
+            cell_count += 1;
+        }
+        assert!(page.free_space() < cell_size + SLOT_SIZE);
+
+        // every other cell removed, the free space is scattered across the heap
+        for cell_num in (0..cell_count).step_by(2).rev() {
+            page.remove(cell_num);
+        }
+        assert!(page.get_fragmented_bytes() > 0);
+
+        let big_cell = vec![0xff; 3 * cell_size];
+        assert!(page.insert(0, &big_cell));
+        assert_eq!(page.get(0), &big_cell[..]);
+        for cell_num in 1..page.len() {
+            assert_eq!(page.get(cell_num), &vec![(2 * cell_num - 1) as u8; cell_size][..]);
+        }
+    }
+
+    #[test]
+    fn test_insert_fails_without_room() {
+        let mut page = SlottedPage::new(512);
+        assert!(!page.insert(0, &[1; 512]));
+        assert!(page.is_empty());
+    }
+
+    #[test]
+    fn test_from_page_rejects_bad_slots() {
+        let mut page = SlottedPage::new(512);
+        page.insert(0, b"abc");
+        let bytes = page.as_bytes().to_vec();
+        assert_eq!(SlottedPage::from_page(&bytes, 1, page.get_heap_start(), 0), Some(page.clone()));
+
+        assert!(SlottedPage::from_page(&bytes, 2, page.get_heap_start(), 0).is_none());
+        assert!(SlottedPage::from_page(&bytes, 1, 513, 0).is_none());
+        assert!(SlottedPage::from_page(&bytes, 200, 512, 0).is_none());
+    }
+}
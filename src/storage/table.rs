@@ -1,6 +1,11 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 
-use crate::storage::pager::Pager;
+use crate::storage::config::{ConnectionConfig, SyncMode};
+use crate::storage::cursor::Cursor;
+use crate::storage::error::StorageError;
+use crate::storage::index::{Index, IndexedColumn};
+use crate::storage::key::Key;
+use crate::storage::pager::{Pager, Snapshot};
 use crate::storage::row::Row;
 
 pub struct Table {
@@ -9,9 +14,14 @@ pub struct Table {
 }
 
 impl Table {
+    #[cfg(test)]
     pub fn open_db_connection(file_path: &str) -> Result<Self> {
-        let mut pager = Pager::new(file_path).unwrap();
-        let root_page_num = pager.get_node_mut(0).get_parent_page_num();
+        Self::open_db_connection_with_config(file_path, &ConnectionConfig::default())
+    }
+
+    pub fn open_db_connection_with_config(file_path: &str, config: &ConnectionConfig) -> Result<Self> {
+        let pager = Pager::new(file_path, config)?;
+        let root_page_num = pager.get_root_page_num();
         Ok(Self {
             root_page_num,
             pager,
@@ -19,27 +29,202 @@ impl Table {
     }
 
     // should probably have a better solutuin instead of a dangling argument in a function
-    pub fn insert(&mut self, page_num: usize, cell_num: usize, row: &Row) {
-        let row_id = row.get_id() as usize;
-        if self.pager.get_node_mut(page_num).get_cell_count() > cell_num {
-            let target_key = self.pager.get_key(page_num, cell_num);
-            if target_key == row_id {
-                panic!("Duplicate key.")
-            }
+    pub fn insert(&mut self, page_num: usize, cell_num: usize, row: &Row) -> Result<()> {
+        if self.get_key(page_num, cell_num)? == Some(row.get_key()) {
+            return Err(constraint_violation("id", &row.get_id().to_string()));
+        }
+        self.check_unique(row)?;
+        let value = row.serialize()?;
+        self.statement(|table| {
+            table.insert_key_value(page_num, cell_num, &row.get_key(), &value)?;
+            table.insert_index_entries(row)
+        })
+    }
+
+    // stores the value under any key, the position has to come from a cursor at that key
+    pub fn insert_key_value(&mut self, page_num: usize, cell_num: usize, key: &Key, value: &[u8]) -> Result<()> {
+        if self.get_key(page_num, cell_num)?.as_ref() == Some(key) {
+            anyhow::bail!("Duplicate key {:?}", key);
         }
 
         self.pager
             .insert(
-                row_id,
-                &row.serialize().unwrap(),
+                key,
+                value,
                 page_num,
                 cell_num,
             )
-            .expect("Insert failed.");
+            .context("Insert failed.")
+    }
+
+    pub fn delete(&mut self, page_num: usize, cell_num: usize) -> Result<()> {
+        // values are only read as rows when there are indexes to keep in sync
+        let row = match self.get_indexes().is_empty() {
+            true => None,
+            false => Some(Row::deserialize(&self.select(page_num, cell_num)?)?),
+        };
+        self.statement(|table| {
+            table.delete_key_value(page_num, cell_num)?;
+            match row {
+                Some(row) => table.delete_index_entries(&row),
+                None => Ok(()),
+            }
+        })
+    }
+
+    pub fn delete_key_value(&mut self, page_num: usize, cell_num: usize) -> Result<()> {
+        self.pager
+            .delete(page_num, cell_num)
+            .context("Delete failed.")
+    }
+
+    // replaces the row at the position with one of the same id
+    pub fn update(&mut self, page_num: usize, cell_num: usize, row: &Row) -> Result<()> {
+        let old_row = Row::deserialize(&self.select(page_num, cell_num)?)?;
+        anyhow::ensure!(
+            old_row.get_id() == row.get_id(),
+            "Update of row {} would change its id to {}",
+            old_row.get_id(),
+            row.get_id()
+        );
+        self.check_unique(row)?;
+        // the row must not get lost when putting it back fails
+        self.statement(|table| {
+            table.delete(page_num, cell_num).context("Update failed.")?;
+            // deleting may have rebalanced the tree, the position is looked up again
+            let mut cursor = Cursor::table_find(table, &row.get_key())?;
+            cursor.insert(row).context("Update failed.")
+        })
+    }
+
+    // the changes of a statement to the table and its indexes are undone together when one of them fails
+    fn statement(&mut self, changes: impl FnOnce(&mut Self) -> Result<()>) -> Result<()> {
+        self.pager.begin_statement()?;
+        let result = changes(self);
+        self.pager.end_statement(result.is_ok())?;
+        result
+    }
+
+    pub fn get_indexes(&self) -> &[Index] {
+        self.pager.get_indexes()
+    }
+
+    pub fn get_index(&self, column: IndexedColumn) -> Option<&Index> {
+        self.get_indexes().iter().find(|index| index.get_column() == column)
+    }
+
+    // builds the tree of a new index from the rows there are, later changes keep it in sync. A unique
+    // index can only be created while no two rows hold the same value
+    pub fn create_index(&mut self, column: IndexedColumn, unique: bool) -> Result<()> {
+        if self.get_index(column).is_some() {
+            anyhow::bail!("Index on {} already exists", column.get_name());
+        }
+        let rows = Cursor::range(self, ..)?.collect::<Result<Vec<Row>>>()?;
+        if unique {
+            let mut values: Vec<&str> = rows.iter().map(|row| column.get_value(row)).collect();
+            values.sort_unstable();
+            if let Some(pair) = values.windows(2).find(|pair| pair[0] == pair[1]) {
+                return Err(constraint_violation(column.get_name(), pair[0]));
+            }
+        }
+        self.statement(|table| {
+            let index = Index::new(column, table.pager.create_tree()?, unique);
+            for row in &rows {
+                table.insert_index_entry(&index, row)?;
+            }
+            table.pager.add_index(index)
+        })
+    }
+
+    // rows whose column holds the value, in id order. Goes through the index on the column if there is
+    // one, otherwise every row is read
+    pub fn find_rows(&mut self, column: IndexedColumn, value: &str) -> Result<Vec<Row>> {
+        let Some(index) = self.get_index(column).cloned() else {
+            let rows = Cursor::range(self, ..)?.collect::<Result<Vec<Row>>>()?;
+            return Ok(rows.into_iter().filter(|row| column.get_value(row) == value).collect());
+        };
+
+        let range = index.get_lookup_range(value);
+        let mut keys = Vec::new();
+        let mut cursor = Cursor::index_scan(self, &index, range.start())?;
+        while let Some(key) = cursor.get_key()? {
+            if !range.contains(&key) {
+                break;
+            }
+            keys.push(Key::from_bytes(&cursor.select()?));
+            cursor.advance()?;
+        }
+        drop(cursor);
+
+        let mut rows = Vec::new();
+        for key in keys {
+            let mut cursor = Cursor::table_find(self, &key)?;
+            if cursor.get_key()? != Some(key.clone()) {
+                anyhow::bail!("Index on {} refers to the missing row {:?}", column.get_name(), key);
+            }
+            let row = Row::deserialize(&cursor.select()?)?;
+            // entries of long values only hold a prefix of them
+            if column.get_value(&row) == value {
+                rows.push(row);
+            }
+        }
+        Ok(rows)
+    }
+
+    // key at the position, None past the last cell of the page
+    fn get_key(&mut self, page_num: usize, cell_num: usize) -> Result<Option<Key>> {
+        if self.pager.get_node(page_num)?.get_cell_count() > cell_num {
+            return Ok(Some(self.pager.get_key(page_num, cell_num)?));
+        }
+        Ok(None)
+    }
+
+    // the values of the row that have to be unique must not be held by any other row
+    fn check_unique(&mut self, row: &Row) -> Result<()> {
+        for index in self.get_indexes().to_vec() {
+            if !index.is_unique() {
+                continue;
+            }
+            let column = index.get_column();
+            let value = column.get_value(row);
+            if self.find_rows(column, value)?.iter().any(|other| other.get_id() != row.get_id()) {
+                return Err(constraint_violation(column.get_name(), value));
+            }
+        }
+        Ok(())
+    }
+
+    fn insert_index_entries(&mut self, row: &Row) -> Result<()> {
+        for index in self.get_indexes().to_vec() {
+            self.insert_index_entry(&index, row)?;
+        }
+        Ok(())
+    }
+
+    fn insert_index_entry(&mut self, index: &Index, row: &Row) -> Result<()> {
+        let key = index.get_entry_key(row);
+        let cursor = Cursor::index_find(self, index, &key)?;
+        let (page_num, cell_num) = (cursor.get_page_num(), cursor.get_cell_num());
+        drop(cursor);
+        self.insert_key_value(page_num, cell_num, &key, row.get_key().as_bytes())
+    }
+
+    fn delete_index_entries(&mut self, row: &Row) -> Result<()> {
+        for index in self.get_indexes().to_vec() {
+            let key = index.get_entry_key(row);
+            let mut cursor = Cursor::index_find(self, &index, &key)?;
+            if cursor.get_key()? != Some(key) {
+                anyhow::bail!("Index on {} has no entry for row {}", index.get_column().get_name(), row.get_id());
+            }
+            let (page_num, cell_num) = (cursor.get_page_num(), cursor.get_cell_num());
+            drop(cursor);
+            self.delete_key_value(page_num, cell_num)?;
+        }
+        Ok(())
     }
 
-    pub fn select(&mut self, page_num: usize, cell_num: usize) -> &[u8] {
-        self.pager.select(page_num, cell_num)
+    pub fn select(&mut self, page_num: usize, cell_num: usize) -> Result<Vec<u8>> {
+        Ok(self.pager.select(page_num, cell_num)?)
     }
 
     pub fn get_pager_mut(&mut self) -> &mut Pager {
@@ -57,24 +242,105 @@ impl Table {
     pub fn flush(&mut self) -> Result<()> {
         self.pager.flush()
     }
+
+    #[cfg(test)]
+    pub fn checkpoint(&mut self) -> Result<()> {
+        self.pager.checkpoint()
+    }
+
+    pub fn get_sync_mode(&self) -> SyncMode {
+        self.pager.get_sync_mode()
+    }
+
+    pub fn set_sync_mode(&mut self, sync_mode: SyncMode) {
+        self.pager.set_sync_mode(sync_mode)
+    }
+
+    // groups the following changes until commit or rollback
+    pub fn begin_transaction(&mut self) -> Result<()> {
+        self.pager.begin_transaction()
+    }
+
+    pub fn commit(&mut self) -> Result<()> {
+        self.pager.commit_transaction()
+    }
+
+    pub fn rollback(&mut self) -> Result<()> {
+        self.pager.rollback_transaction()
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.pager.in_transaction()
+    }
+
+    // a consistent view of the last commit for long reads, see SnapshotCursor. Writes can go on meanwhile
+    #[cfg(test)]
+    pub fn begin_snapshot(&mut self) -> Result<Snapshot> {
+        self.pager.begin_snapshot()
+    }
+
+    // a snapshot of the last commit this connection has seen, for readers sharing the table
+    pub fn pin_snapshot(&self) -> Snapshot {
+        self.pager.pin_snapshot()
+    }
+
+    #[cfg(test)]
+    pub fn end_snapshot(&mut self, snapshot: Snapshot) {
+        self.pager.end_snapshot(snapshot)
+    }
+
+    #[cfg(test)]
+    pub fn crash(self) {
+        self.pager.crash()
+    }
+
+    // savepoints nest inside a transaction, names may repeat and refer to the innermost one
+    pub fn savepoint(&mut self, name: &str) -> Result<()> {
+        self.pager.savepoint(name)
+    }
+
+    pub fn release_savepoint(&mut self, name: &str) -> Result<()> {
+        self.pager.release_savepoint(name)
+    }
+
+    pub fn rollback_to_savepoint(&mut self, name: &str) -> Result<()> {
+        self.pager.rollback_to_savepoint(name)
+    }
+}
+
+fn constraint_violation(column: &str, value: &str) -> anyhow::Error {
+    StorageError::ConstraintViolation {
+        column: column.to_string(),
+        value: value.to_string(),
+    }
+    .into()
 }
 
 #[cfg(test)]
 mod test {
     use std::fs;
+    use std::sync::Arc;
+
+    use crate::storage::constant::DEFAULT_PAGE_SIZE;
+    use crate::storage::key::KeyValue;
+    use crate::storage::node::NodeType;
+    use crate::storage::test_util::{insert_rows, open_test_table, open_test_table_with_config, scan_ids};
+    use crate::storage::vfs::{MemoryVfs, Vfs};
 
     use super::*;
 
     #[test]
     fn test_table() {
-        let test_db_path = "test.db";
-        let mut table = Table::open_db_connection(test_db_path).unwrap();
+        let test_db_path = "test_table.db";
+        let mut table = open_test_table(test_db_path);
+        let root_page_num = table.get_root_page_num();
         let row = Row::new(1, "test".to_string(), "test".to_string());
-        table.insert(0, 0, &row);
+        table.insert(root_page_num, 0, &row).unwrap();
         table.flush().unwrap();
 
         let mut table = Table::open_db_connection(test_db_path).unwrap();
-        let row = Row::deserialize(table.select(0, 0)).unwrap();
+        let root_page_num = table.get_root_page_num();
+        let row = Row::deserialize(&table.select(root_page_num, 0).unwrap()).unwrap();
         assert_eq!(row.get_id(), 1);
         assert_eq!(row.get_user_name(), "test");
         assert_eq!(row.get_email(), "test");
@@ -83,44 +349,468 @@ mod test {
     }
 
     #[test]
-    #[should_panic]
     fn test_table_duplicate_key() {
-        let test_db_path = "test.db";
-        let mut table = Table::open_db_connection(test_db_path).unwrap();
+        let test_db_path = "test_table_duplicate_key.db";
+        let mut table = open_test_table(test_db_path);
+        let root_page_num = table.get_root_page_num();
         let row = Row::new(1, "test".to_string(), "test".to_string());
-        table.insert(0, 0, &row);
-        table.insert(0, 0, &row);
+        table.insert(root_page_num, 0, &row).unwrap();
+        let err = table.insert(root_page_num, 0, &row).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<StorageError>(),
+            Some(StorageError::ConstraintViolation { column, value }) if column == "id" && value == "1"
+        ));
         table.flush().unwrap();
+        assert_eq!(scan_ids(&mut table), vec![1]);
 
         fs::remove_file(test_db_path).unwrap();
     }
 
     #[test]
     fn test_insert_multiple_rows() {
-        let test_db_path = "test.db";
-        let mut table = Table::open_db_connection(test_db_path).unwrap();
+        let test_db_path = "test_insert_multiple_rows.db";
+        let mut table = open_test_table(test_db_path);
+        let root_page_num = table.get_root_page_num();
         let row = Row::new(1, "test".to_string(), "test".to_string());
-        table.insert(0, 0, &row);
+        table.insert(root_page_num, 0, &row).unwrap();
         let row = Row::new(2, "test".to_string(), "test".to_string());
-        table.insert(0, 1, &row);
+        table.insert(root_page_num, 1, &row).unwrap();
         let row = Row::new(3, "test".to_string(), "test".to_string());
-        table.insert(0, 2, &row);
+        table.insert(root_page_num, 2, &row).unwrap();
         table.flush().unwrap();
 
         let mut table = Table::open_db_connection(test_db_path).unwrap();
-        let row = Row::deserialize(table.select(0, 0)).unwrap();
+        let root_page_num = table.get_root_page_num();
+        let row = Row::deserialize(&table.select(root_page_num, 0).unwrap()).unwrap();
         assert_eq!(row.get_id(), 1);
         assert_eq!(row.get_user_name(), "test");
         assert_eq!(row.get_email(), "test");
-        let row = Row::deserialize(table.select(0, 1)).unwrap();
+        let row = Row::deserialize(&table.select(root_page_num, 1).unwrap()).unwrap();
         assert_eq!(row.get_id(), 2);
         assert_eq!(row.get_user_name(), "test");
         assert_eq!(row.get_email(), "test");
-        let row = Row::deserialize(table.select(0, 2)).unwrap();
+        let row = Row::deserialize(&table.select(root_page_num, 2).unwrap()).unwrap();
         assert_eq!(row.get_id(), 3);
         assert_eq!(row.get_user_name(), "test");
         assert_eq!(row.get_email(), "test");
 
         fs::remove_file(test_db_path).unwrap();
     }
+
+    #[test]
+    fn test_reopen_multi_level_tree() {
+        let test_db_path = "test_reopen_multi_level_tree.db";
+        let mut table = open_test_table(test_db_path);
+        insert_rows(&mut table, (1..=500).rev());
+        table.flush().unwrap();
+
+        let mut table = Table::open_db_connection(test_db_path).unwrap();
+        let root_page_num = table.get_root_page_num();
+        assert_eq!(table.get_pager_mut().get_node(root_page_num).unwrap().get_node_type(), NodeType::Internal);
+
+        let mut cursor = Cursor::table_start(&mut table).unwrap();
+        let mut scanned_ids = Vec::new();
+        while !cursor.is_end_of_table() {
+            scanned_ids.push(Row::deserialize(&cursor.select().unwrap()).unwrap().get_id());
+            cursor.advance().unwrap();
+        }
+        drop(cursor);
+        assert_eq!(scanned_ids, (1..=500).collect::<Vec<u32>>());
+
+        for id in [1, 250, 499, 500] {
+            let mut cursor = Cursor::table_find(&mut table, &Key::int(id.into())).unwrap();
+            let row = Row::deserialize(&cursor.select().unwrap()).unwrap();
+            assert_eq!(row.get_id(), id);
+            assert_eq!(row.get_email(), format!("user{}@mail.com", id));
+        }
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_open_rejects_foreign_file() {
+        let test_db_path = "test_open_rejects_foreign_file.db";
+        fs::write(test_db_path, b"SQLite format 3\0 definitely not ours").unwrap();
+
+        let err = Table::open_db_connection(test_db_path).err().unwrap();
+        assert!(format!("{:#}", err).contains("not a simple_db database"));
+        assert!(!fs::exists(format!("{}-wal", test_db_path)).unwrap());
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_header_is_updated_on_flush() {
+        let test_db_path = "test_header_is_updated_on_flush.db";
+        let mut table = open_test_table(test_db_path);
+        insert_rows(&mut table, 1..=50);
+        table.get_pager_mut().bump_schema_version().unwrap();
+        let page_count = table.get_pager().get_page_count();
+        table.flush().unwrap();
+
+        let table = Table::open_db_connection(test_db_path).unwrap();
+        assert_eq!(table.get_pager().get_page_count(), page_count);
+        assert_eq!(table.get_pager().get_schema_version(), 1);
+        assert_eq!(fs::metadata(test_db_path).unwrap().len(), (page_count * DEFAULT_PAGE_SIZE) as u64);
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_rollback_restores_rows() {
+        let test_db_path = "test_rollback_restores_rows.db";
+        let mut table = open_test_table(test_db_path);
+        insert_rows(&mut table, 1..=10);
+
+        table.begin_transaction().unwrap();
+        assert!(table.in_transaction());
+        insert_rows(&mut table, 11..=20);
+        Cursor::table_find(&mut table, &Key::int(5)).unwrap().delete().unwrap();
+        assert_eq!(scan_ids(&mut table).len(), 19);
+        table.rollback().unwrap();
+
+        assert!(!table.in_transaction());
+        assert_eq!(scan_ids(&mut table), (1..=10).collect::<Vec<u32>>());
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_committed_transaction_is_durable() {
+        let test_db_path = "test_committed_transaction_is_durable.db";
+        let mut table = open_test_table(test_db_path);
+        table.begin_transaction().unwrap();
+        insert_rows(&mut table, 1..=500);
+        table.commit().unwrap();
+        table.begin_transaction().unwrap();
+        insert_rows(&mut table, 501..=600);
+        // the connection dies in the middle of the second transaction
+        table.crash();
+
+        let mut table = Table::open_db_connection(test_db_path).unwrap();
+        assert_eq!(scan_ids(&mut table), (1..=500).collect::<Vec<u32>>());
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_transaction_state_is_checked() {
+        let test_db_path = "test_transaction_state_is_checked.db";
+        let mut table = open_test_table(test_db_path);
+        assert!(table.commit().is_err());
+        assert!(table.rollback().is_err());
+
+        table.begin_transaction().unwrap();
+        assert!(table.begin_transaction().is_err());
+        assert!(table.flush().is_err());
+        table.commit().unwrap();
+        table.flush().unwrap();
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_commits_survive_crash_in_every_sync_mode() {
+        let test_db_path = "test_commits_survive_crash_in_every_sync_mode.db";
+        let config = ConnectionConfig::new().with_sync_mode(SyncMode::Off);
+        let mut table = open_test_table_with_config(test_db_path, &config);
+        assert_eq!(table.get_sync_mode(), SyncMode::Off);
+        insert_rows(&mut table, 1..=100);
+        table.flush().unwrap();
+
+        // syncing only guards against the operating system going down, a crashed process loses nothing
+        table.set_sync_mode(SyncMode::Normal);
+        insert_rows(&mut table, 101..=200);
+        table.flush().unwrap();
+        table.set_sync_mode(SyncMode::Full);
+        insert_rows(&mut table, 201..=300);
+        table.flush().unwrap();
+        table.crash();
+
+        let mut table = Table::open_db_connection_with_config(test_db_path, &config).unwrap();
+        assert_eq!(scan_ids(&mut table), (1..=300).collect::<Vec<u32>>());
+        table.set_sync_mode(SyncMode::Normal);
+        table.checkpoint().unwrap();
+        drop(table);
+
+        let mut table = Table::open_db_connection(test_db_path).unwrap();
+        assert_eq!(table.get_sync_mode(), SyncMode::Full);
+        assert_eq!(scan_ids(&mut table), (1..=300).collect::<Vec<u32>>());
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    fn find_ids(table: &mut Table, column: IndexedColumn, value: &str) -> Vec<u32> {
+        table.find_rows(column, value).unwrap().iter().map(Row::get_id).collect()
+    }
+
+    #[test]
+    fn test_index_is_kept_in_sync() {
+        let test_db_path = "test_index_is_kept_in_sync.db";
+        let mut table = open_test_table(test_db_path);
+        let email = |id: u32| format!("user{}@mail.com", id % 7);
+        for id in (1..=300).rev() {
+            let row = Row::new(id, format!("user{}", id), email(id));
+            Cursor::table_find(&mut table, &Key::int(id.into())).unwrap().insert(&row).unwrap();
+        }
+        let scanned = find_ids(&mut table, IndexedColumn::Email, "user3@mail.com");
+        assert_eq!(scanned, (1..=300).filter(|id| id % 7 == 3).collect::<Vec<u32>>());
+
+        table.create_index(IndexedColumn::Email, false).unwrap();
+        assert!(table.create_index(IndexedColumn::Email, true).is_err());
+        assert_eq!(table.get_pager().get_schema_version(), 1);
+        assert_eq!(find_ids(&mut table, IndexedColumn::Email, "user3@mail.com"), scanned);
+        assert!(find_ids(&mut table, IndexedColumn::Email, "user3@mail.co").is_empty());
+
+        // every kind of change goes through the index
+        for id in 301..=350 {
+            let row = Row::new(id, format!("user{}", id), email(id));
+            Cursor::table_find(&mut table, &Key::int(id.into())).unwrap().insert(&row).unwrap();
+        }
+        for id in (3..=350).step_by(14) {
+            Cursor::table_find(&mut table, &Key::int(id.into())).unwrap().delete().unwrap();
+        }
+        let row = Row::new(10, "user10".to_string(), "new@mail.com".to_string());
+        Cursor::table_find(&mut table, &Key::int(10)).unwrap().update(&row).unwrap();
+        table.flush().unwrap();
+
+        let mut table = Table::open_db_connection(test_db_path).unwrap();
+        assert!(table.get_index(IndexedColumn::Email).is_some());
+        let expected: Vec<u32> = (1..=350).filter(|id| id % 7 == 3 && id % 14 != 3 && *id != 10).collect();
+        assert_eq!(find_ids(&mut table, IndexedColumn::Email, "user3@mail.com"), expected);
+        assert_eq!(find_ids(&mut table, IndexedColumn::Email, "new@mail.com"), vec![10]);
+        // the index holds exactly one entry per row
+        let index = table.get_index(IndexedColumn::Email).unwrap().clone();
+        let mut cursor = Cursor::index_find(&mut table, &index, &Key::min()).unwrap();
+        let mut entry_count = 0;
+        while !cursor.is_end_of_table() {
+            entry_count += 1;
+            cursor.advance().unwrap();
+        }
+        drop(cursor);
+        assert_eq!(entry_count, scan_ids(&mut table).len());
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_lookup_starting_past_the_end_of_a_leaf() {
+        let test_db_path = "test_lookup_starting_past_the_end_of_a_leaf.db";
+        let mut table = open_test_table(test_db_path);
+        insert_rows(&mut table, 1..=1_000);
+        table.create_index(IndexedColumn::Email, false).unwrap();
+
+        // the first separator of the index becomes the first value of the next leaf, larger than the
+        // max entry of its own leaf like a separator that was left behind by a delete
+        let index = table.get_index(IndexedColumn::Email).unwrap().clone();
+        let pager = table.get_pager_mut();
+        let root = pager.get_node(index.get_root_page_num()).unwrap();
+        assert_eq!(root.get_node_type(), NodeType::Internal);
+        let next_page_num = root.internal_node_children(1);
+        let first_key = pager.get_node(next_page_num).unwrap().get_key(0);
+        let KeyValue::Text(value) = &first_key.values()[0] else {
+            panic!("entry key {:?} does not start with the value", first_key);
+        };
+        let separator = Key::text(value).unwrap();
+        pager.get_node_mut(index.get_root_page_num()).unwrap().internal_set_key(0, &separator);
+
+        let id: u32 = value.trim_start_matches("user").trim_end_matches("@mail.com").parse().unwrap();
+        assert_eq!(find_ids(&mut table, IndexedColumn::Email, value), vec![id]);
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_long_values_are_found_through_the_index() {
+        let test_db_path = "test_long_values_are_found_through_the_index.db";
+        let mut table = open_test_table(test_db_path);
+        table.create_index(IndexedColumn::UserName, false).unwrap();
+        let prefix = "a".repeat(100);
+        for id in 1..=3 {
+            let row = Row::new(id, format!("{}{}", prefix, id), "email".to_string());
+            Cursor::table_find(&mut table, &Key::int(id.into())).unwrap().insert(&row).unwrap();
+        }
+        assert_eq!(find_ids(&mut table, IndexedColumn::UserName, &format!("{}2", prefix)), vec![2]);
+        assert!(find_ids(&mut table, IndexedColumn::UserName, &prefix).is_empty());
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_unique_index_rejects_duplicates() {
+        let test_db_path = "test_unique_index_rejects_duplicates.db";
+        let mut table = open_test_table(test_db_path);
+        insert_rows(&mut table, 1..=100);
+        let row = Row::new(101, "user101".to_string(), "user1@mail.com".to_string());
+        Cursor::table_find(&mut table, &Key::int(101)).unwrap().insert(&row).unwrap();
+
+        // the rows there are have to be unique already
+        let page_count = table.get_pager().get_page_count();
+        let err = table.create_index(IndexedColumn::Email, true).unwrap_err();
+        assert_eq!(err.to_string(), "UNIQUE constraint failed: email = user1@mail.com already exists");
+        assert!(table.get_indexes().is_empty());
+        assert_eq!(table.get_pager().get_page_count(), page_count);
+        Cursor::table_find(&mut table, &Key::int(101)).unwrap().delete().unwrap();
+        table.create_index(IndexedColumn::Email, true).unwrap();
+
+        let row = Row::new(101, "user101".to_string(), "user42@mail.com".to_string());
+        let err = Cursor::table_find(&mut table, &Key::int(101)).unwrap().insert(&row).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<StorageError>(),
+            Some(StorageError::ConstraintViolation { column, value }) if column == "email" && value == "user42@mail.com"
+        ));
+        let row = Row::new(7, "user7".to_string(), "user42@mail.com".to_string());
+        assert!(Cursor::table_find(&mut table, &Key::int(7)).unwrap().update(&row).is_err());
+        // nothing was changed by the failed statements
+        assert_eq!(scan_ids(&mut table), (1..=100).collect::<Vec<u32>>());
+        assert_eq!(find_ids(&mut table, IndexedColumn::Email, "user42@mail.com"), vec![42]);
+        assert_eq!(find_ids(&mut table, IndexedColumn::Email, "user7@mail.com"), vec![7]);
+
+        // a row may keep its own value, and a value is free again once its row is gone
+        let row = Row::new(7, "renamed".to_string(), "user7@mail.com".to_string());
+        Cursor::table_find(&mut table, &Key::int(7)).unwrap().update(&row).unwrap();
+        Cursor::table_find(&mut table, &Key::int(42)).unwrap().delete().unwrap();
+        let row = Row::new(101, "user101".to_string(), "user42@mail.com".to_string());
+        Cursor::table_find(&mut table, &Key::int(101)).unwrap().insert(&row).unwrap();
+        table.flush().unwrap();
+
+        let mut table = Table::open_db_connection(test_db_path).unwrap();
+        assert!(table.get_index(IndexedColumn::Email).unwrap().is_unique());
+        assert_eq!(find_ids(&mut table, IndexedColumn::Email, "user42@mail.com"), vec![101]);
+        let row = Row::new(102, "user102".to_string(), "user42@mail.com".to_string());
+        assert!(Cursor::table_find(&mut table, &Key::int(102)).unwrap().insert(&row).is_err());
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_failed_update_keeps_the_row() {
+        let test_db_path = "test_failed_update_keeps_the_row.db";
+        let mut table = open_test_table(test_db_path);
+        insert_rows(&mut table, 1..=100);
+        table.create_index(IndexedColumn::UserName, false).unwrap();
+        table.create_index(IndexedColumn::Email, false).unwrap();
+        // the user name entry of row 42 goes missing, so updating the row fails after it was deleted
+        let index = table.get_index(IndexedColumn::UserName).unwrap().clone();
+        let key = index.get_entry_key(&Row::new(42, "user42".to_string(), "user42@mail.com".to_string()));
+        let cursor = Cursor::index_find(&mut table, &index, &key).unwrap();
+        let (page_num, cell_num) = (cursor.get_page_num(), cursor.get_cell_num());
+        drop(cursor);
+        table.delete_key_value(page_num, cell_num).unwrap();
+
+        let row = Row::new(42, "renamed".to_string(), "renamed@mail.com".to_string());
+        assert!(Cursor::table_find(&mut table, &Key::int(42)).unwrap().update(&row).is_err());
+        assert_eq!(scan_ids(&mut table), (1..=100).collect::<Vec<u32>>());
+        assert_eq!(find_ids(&mut table, IndexedColumn::Email, "user42@mail.com"), vec![42]);
+        assert!(find_ids(&mut table, IndexedColumn::Email, "renamed@mail.com").is_empty());
+        assert!(find_ids(&mut table, IndexedColumn::UserName, "renamed").is_empty());
+        table.flush().unwrap();
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_failed_statements_keep_the_indexes_in_sync() {
+        let test_db_path = "test_failed_statements_keep_the_indexes_in_sync.db";
+        let mut table = open_test_table(test_db_path);
+        insert_rows(&mut table, 1..=300);
+        for id in 201..=300 {
+            Cursor::table_find(&mut table, &Key::int(id)).unwrap().delete().unwrap();
+        }
+
+        // the free list leads out of the file, building the index fails once it needs a second page
+        let page_count = table.get_pager().get_page_count();
+        let free_page_nums: Vec<usize> = (1..page_count)
+            .filter(|&page_num| table.get_pager_mut().get_node(page_num).unwrap().get_node_type() == NodeType::Free)
+            .collect();
+        assert!(!free_page_nums.is_empty());
+        for &page_num in &free_page_nums {
+            table.get_pager_mut().get_node_mut(page_num).unwrap().set_next_free_page_num(page_count + 10);
+        }
+        assert!(table.create_index(IndexedColumn::Email, false).is_err());
+        assert!(table.get_indexes().is_empty());
+        assert_eq!(table.get_pager().get_page_count(), page_count);
+        // the page the index took is back at the head of the list
+        assert!(table.get_pager_mut().get_free_page_count().is_err());
+        for &page_num in &free_page_nums {
+            table.get_pager_mut().get_node_mut(page_num).unwrap().set_next_free_page_num(0);
+        }
+        table.create_index(IndexedColumn::UserName, false).unwrap();
+        table.create_index(IndexedColumn::Email, false).unwrap();
+
+        // an entry in the way of the email entry of the new row, the row goes in with its user name entry first
+        let row = Row::new(201, "user201".to_string(), "user201@mail.com".to_string());
+        let (page_num, cell_num) = entry_position(&mut table, IndexedColumn::Email, &row);
+        let email_key = table.get_index(IndexedColumn::Email).unwrap().get_entry_key(&row);
+        table.insert_key_value(page_num, cell_num, &email_key, row.get_key().as_bytes()).unwrap();
+        assert!(Cursor::table_find(&mut table, &Key::int(201)).unwrap().insert(&row).is_err());
+        assert_eq!(scan_ids(&mut table), (1..=200).collect::<Vec<u32>>());
+        assert!(find_ids(&mut table, IndexedColumn::UserName, "user201").is_empty());
+        let (page_num, cell_num) = entry_position(&mut table, IndexedColumn::Email, &row);
+        table.delete_key_value(page_num, cell_num).unwrap();
+
+        // the user name entry of the row is missing, the email entry goes with the row only
+        let row = Row::new(42, "user42".to_string(), "user42@mail.com".to_string());
+        let (page_num, cell_num) = entry_position(&mut table, IndexedColumn::UserName, &row);
+        table.delete_key_value(page_num, cell_num).unwrap();
+        assert!(Cursor::table_find(&mut table, &Key::int(42)).unwrap().delete().is_err());
+        assert_eq!(scan_ids(&mut table), (1..=200).collect::<Vec<u32>>());
+        assert_eq!(find_ids(&mut table, IndexedColumn::Email, "user42@mail.com"), vec![42]);
+        table.flush().unwrap();
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    // where the entry of the row is or would go in the index on the column
+    fn entry_position(table: &mut Table, column: IndexedColumn, row: &Row) -> (usize, usize) {
+        let index = table.get_index(column).unwrap().clone();
+        let cursor = Cursor::index_find(table, &index, &index.get_entry_key(row)).unwrap();
+        (cursor.get_page_num(), cursor.get_cell_num())
+    }
+
+    #[test]
+    fn test_rollback_drops_created_index() {
+        let test_db_path = "test_rollback_drops_created_index.db";
+        let mut table = open_test_table(test_db_path);
+        insert_rows(&mut table, 1..=100);
+        let page_count = table.get_pager().get_page_count();
+
+        table.begin_transaction().unwrap();
+        table.create_index(IndexedColumn::UserName, false).unwrap();
+        assert_eq!(find_ids(&mut table, IndexedColumn::UserName, "user42"), vec![42]);
+        table.rollback().unwrap();
+
+        assert!(table.get_indexes().is_empty());
+        assert_eq!(table.get_pager().get_page_count(), page_count);
+        // without the index the lookup reads every row
+        assert_eq!(find_ids(&mut table, IndexedColumn::UserName, "user42"), vec![42]);
+
+        fs::remove_file(test_db_path).unwrap();
+    }
+
+    #[test]
+    fn test_in_memory_database() {
+        let test_db_path = "test_in_memory_database.db";
+        let vfs = MemoryVfs::new();
+        let config = ConnectionConfig::new().with_vfs(Arc::new(vfs.clone()));
+        let mut table = Table::open_db_connection_with_config(test_db_path, &config).unwrap();
+        insert_rows(&mut table, 1..=500);
+        table.flush().unwrap();
+
+        // connections sharing the vfs see each other's commits and locks
+        let mut other = Table::open_db_connection_with_config(test_db_path, &config).unwrap();
+        assert_eq!(scan_ids(&mut other), (1..=500).collect::<Vec<u32>>());
+        table.begin_transaction().unwrap();
+        assert!(other.begin_transaction().is_err());
+        table.rollback().unwrap();
+        drop(other);
+        drop(table);
+
+        assert!(!fs::exists(test_db_path).unwrap());
+        assert!(vfs.exists(test_db_path).unwrap());
+        // the last connection checkpointed and removed the log
+        assert!(!vfs.exists(&format!("{}-wal", test_db_path)).unwrap());
+        let mut table = Table::open_db_connection_with_config(test_db_path, &config).unwrap();
+        assert_eq!(scan_ids(&mut table), (1..=500).collect::<Vec<u32>>());
+    }
 }
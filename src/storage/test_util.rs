@@ -0,0 +1,48 @@
+use std::fs;
+
+use crate::storage::config::ConnectionConfig;
+use crate::storage::cursor::Cursor;
+use crate::storage::key::Key;
+use crate::storage::row::Row;
+use crate::storage::table::Table;
+
+// Fixtures shared by the test modules of the storage layer. Every row inserted here is
+// (id, "user{id}", "user{id}@mail.com")
+
+// opens a fresh table, whatever an earlier run left at the path is removed first
+pub(crate) fn open_test_table(test_db_path: &str) -> Table {
+    open_test_table_with_config(test_db_path, &ConnectionConfig::default())
+}
+
+pub(crate) fn open_test_table_with_config(test_db_path: &str, config: &ConnectionConfig) -> Table {
+    let _ = fs::remove_file(test_db_path);
+    Table::open_db_connection_with_config(test_db_path, config).unwrap()
+}
+
+pub(crate) fn test_row(id: u32) -> Row {
+    Row::new(id, format!("user{}", id), format!("user{}@mail.com", id))
+}
+
+pub(crate) fn insert_rows(table: &mut Table, ids: impl Iterator<Item = u32>) {
+    for id in ids {
+        Cursor::table_find(table, &Key::int(id.into())).unwrap().insert(&test_row(id)).unwrap();
+    }
+}
+
+pub(crate) fn delete_rows(table: &mut Table, ids: impl Iterator<Item = u32>) {
+    for id in ids {
+        let mut cursor = Cursor::table_find(table, &Key::int(id.into())).unwrap();
+        assert_eq!(cursor.get_key().unwrap(), Some(Key::int(id.into())));
+        cursor.delete().unwrap();
+    }
+}
+
+pub(crate) fn scan_ids(table: &mut Table) -> Vec<u32> {
+    let mut ids = Vec::new();
+    let mut cursor = Cursor::table_start(table).unwrap();
+    while !cursor.is_end_of_table() {
+        ids.push(Row::deserialize(&cursor.select().unwrap()).unwrap().get_id());
+        cursor.advance().unwrap();
+    }
+    ids
+}
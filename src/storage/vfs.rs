@@ -0,0 +1,374 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Debug;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io;
+#[cfg(unix)]
+use std::os::unix::fs::FileExt;
+#[cfg(windows)]
+use std::os::windows::fs::FileExt;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+
+use memmap2::Mmap;
+
+// Storage backend of a connection, every file the pager and the WAL touch goes through it.
+// StdVfs is the file system, MemoryVfs keeps the files in memory. Set with ConnectionConfig::with_vfs
+pub trait Vfs: Debug + Send + Sync {
+    // opens the file for reading and writing, an empty one is created if it does not exist
+    fn open(&self, path: &str) -> io::Result<Box<dyn VfsFile>>;
+
+    fn delete(&self, path: &str) -> io::Result<()>;
+
+    #[cfg(test)]
+    fn exists(&self, path: &str) -> io::Result<bool>;
+
+    // makes the creation or removal of the file durable, syncing the file itself does not cover its
+    // directory entry
+    fn sync_dir(&self, path: &str) -> io::Result<()>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockKind {
+    Shared,
+    Exclusive,
+}
+
+// An open file. Reads and writes are positional so that a file can be shared by reference.
+// Locks are advisory, they belong to the open file and exclude other open files of the same path,
+// taking a lock of the other kind converts the one already held
+pub trait VfsFile: Send + Sync {
+    // reads at most buffer.len() bytes, 0 only at the end of the file
+    fn read_at(&self, buffer: &mut [u8], offset: u64) -> io::Result<usize>;
+
+    // writes the whole buffer, the file grows as needed
+    fn write_at(&self, buffer: &[u8], offset: u64) -> io::Result<()>;
+
+    fn size(&self) -> io::Result<u64>;
+
+    // cuts or extends the file to the len
+    fn truncate(&self, len: u64) -> io::Result<()>;
+
+    // returns once the content of the file is durable
+    fn sync(&self) -> io::Result<()>;
+
+    // fails with WouldBlock instead of waiting when another open file holds a conflicting lock
+    fn try_lock(&self, kind: LockKind) -> Result<(), TryLockError>;
+
+    fn unlock(&self) -> io::Result<()>;
+
+    // read only mapping of the whole file as it is now, None if the backend has nothing to map.
+    // The file must not shrink while the mapping is alive
+    fn map(&self) -> io::Result<Option<Mmap>> {
+        Ok(None)
+    }
+}
+
+// reads len bytes at the offset or up to the end of the file
+pub(crate) fn read_up_to(file: &dyn VfsFile, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+    let mut buffer = vec![0; len];
+    let mut read = 0;
+    while read < len {
+        match file.read_at(&mut buffer[read..], offset + read as u64) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    buffer.truncate(read);
+    Ok(buffer)
+}
+
+// the file system, locks are flock style locks of the operating system
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StdVfs;
+
+impl Vfs for StdVfs {
+    fn open(&self, path: &str) -> io::Result<Box<dyn VfsFile>> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        Ok(Box::new(file))
+    }
+
+    fn delete(&self, path: &str) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    #[cfg(test)]
+    fn exists(&self, path: &str) -> io::Result<bool> {
+        fs::exists(path)
+    }
+
+    // Windows cannot open a directory as a file, the entry is durable once the file itself is synced there
+    #[cfg(windows)]
+    fn sync_dir(&self, _path: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    #[cfg(not(windows))]
+    fn sync_dir(&self, path: &str) -> io::Result<()> {
+        let dir = match Path::new(path).parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        File::open(dir)?.sync_all()
+    }
+}
+
+impl VfsFile for File {
+    #[cfg(unix)]
+    fn read_at(&self, buffer: &mut [u8], offset: u64) -> io::Result<usize> {
+        FileExt::read_at(self, buffer, offset)
+    }
+
+    #[cfg(unix)]
+    fn write_at(&self, buffer: &[u8], offset: u64) -> io::Result<()> {
+        self.write_all_at(buffer, offset)
+    }
+
+    // positioned reads move the file cursor on Windows, nothing else uses it
+    #[cfg(windows)]
+    fn read_at(&self, buffer: &mut [u8], offset: u64) -> io::Result<usize> {
+        FileExt::seek_read(self, buffer, offset)
+    }
+
+    #[cfg(windows)]
+    fn write_at(&self, mut buffer: &[u8], mut offset: u64) -> io::Result<()> {
+        while !buffer.is_empty() {
+            match FileExt::seek_write(self, buffer, offset) {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::WriteZero, "failed to write whole buffer")),
+                Ok(written) => {
+                    buffer = &buffer[written..];
+                    offset += written as u64;
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn truncate(&self, len: u64) -> io::Result<()> {
+        self.set_len(len)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.sync_data()
+    }
+
+    fn try_lock(&self, kind: LockKind) -> Result<(), TryLockError> {
+        match kind {
+            LockKind::Shared => File::try_lock_shared(self),
+            LockKind::Exclusive => File::try_lock(self),
+        }
+    }
+
+    fn unlock(&self) -> io::Result<()> {
+        File::unlock(self)
+    }
+
+    fn map(&self) -> io::Result<Option<Mmap>> {
+        // SAFETY: the database file never shrinks once it is set up, and it is only rewritten by checkpoints
+        // while no page is being read from the mapping
+        unsafe { Mmap::map(self) }.map(Some)
+    }
+}
+
+// Files kept in memory, gone once the last clone of the vfs and the last open file are dropped.
+// Clones share their files, connections opened with clones of one MemoryVfs see the same database
+#[derive(Clone, Debug, Default)]
+pub struct MemoryVfs {
+    files: Arc<Mutex<HashMap<String, Arc<MemoryFileData>>>>,
+}
+
+#[derive(Debug, Default)]
+struct MemoryFileData {
+    bytes: RwLock<Vec<u8>>,
+    locks: Mutex<MemoryLocks>,
+}
+
+// holders of the locks of a file, by handle of the open file
+#[derive(Debug, Default)]
+struct MemoryLocks {
+    next_handle: u64,
+    shared: HashSet<u64>,
+    exclusive: Option<u64>,
+}
+
+impl MemoryVfs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn get_files(&self) -> MutexGuard<'_, HashMap<String, Arc<MemoryFileData>>> {
+        self.files.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl Vfs for MemoryVfs {
+    fn open(&self, path: &str) -> io::Result<Box<dyn VfsFile>> {
+        let data = self.get_files().entry(path.to_string()).or_default().clone();
+        let handle = {
+            let mut locks = data.get_locks();
+            locks.next_handle += 1;
+            locks.next_handle
+        };
+        Ok(Box::new(MemoryFile { data, handle }))
+    }
+
+    // open files keep their content, like an unlinked file
+    fn delete(&self, path: &str) -> io::Result<()> {
+        match self.get_files().remove(path) {
+            Some(_) => Ok(()),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, format!("No file {}", path))),
+        }
+    }
+
+    #[cfg(test)]
+    fn exists(&self, path: &str) -> io::Result<bool> {
+        Ok(self.get_files().contains_key(path))
+    }
+
+    fn sync_dir(&self, _path: &str) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl MemoryFileData {
+    fn get_locks(&self) -> MutexGuard<'_, MemoryLocks> {
+        self.locks.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+pub struct MemoryFile {
+    data: Arc<MemoryFileData>,
+    handle: u64,
+}
+
+impl VfsFile for MemoryFile {
+    fn read_at(&self, buffer: &mut [u8], offset: u64) -> io::Result<usize> {
+        let bytes = self.data.bytes.read().unwrap_or_else(|err| err.into_inner());
+        let start = (offset as usize).min(bytes.len());
+        let len = buffer.len().min(bytes.len() - start);
+        buffer[..len].copy_from_slice(&bytes[start..start + len]);
+        Ok(len)
+    }
+
+    fn write_at(&self, buffer: &[u8], offset: u64) -> io::Result<()> {
+        let mut bytes = self.data.bytes.write().unwrap_or_else(|err| err.into_inner());
+        let end = offset as usize + buffer.len();
+        if bytes.len() < end {
+            bytes.resize(end, 0);
+        }
+        bytes[offset as usize..end].copy_from_slice(buffer);
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.data.bytes.read().unwrap_or_else(|err| err.into_inner()).len() as u64)
+    }
+
+    fn truncate(&self, len: u64) -> io::Result<()> {
+        self.data.bytes.write().unwrap_or_else(|err| err.into_inner()).resize(len as usize, 0);
+        Ok(())
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn try_lock(&self, kind: LockKind) -> Result<(), TryLockError> {
+        let mut locks = self.data.get_locks();
+        let held_by_other = |holder: &u64| *holder != self.handle;
+        let blocked = match kind {
+            LockKind::Shared => locks.exclusive.iter().any(held_by_other),
+            LockKind::Exclusive => locks.exclusive.iter().chain(locks.shared.iter()).any(held_by_other),
+        };
+        if blocked {
+            return Err(TryLockError::WouldBlock);
+        }
+
+        locks.shared.remove(&self.handle);
+        locks.exclusive = None;
+        match kind {
+            LockKind::Shared => {
+                locks.shared.insert(self.handle);
+            }
+            LockKind::Exclusive => locks.exclusive = Some(self.handle),
+        }
+        Ok(())
+    }
+
+    fn unlock(&self) -> io::Result<()> {
+        let mut locks = self.data.get_locks();
+        locks.shared.remove(&self.handle);
+        if locks.exclusive == Some(self.handle) {
+            locks.exclusive = None;
+        }
+        Ok(())
+    }
+}
+
+// closing a file gives up its locks
+impl Drop for MemoryFile {
+    fn drop(&mut self) {
+        let _ = self.unlock();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_memory_file_reads_and_writes() {
+        let vfs = MemoryVfs::new();
+        assert!(!vfs.exists("file").unwrap());
+        let file = vfs.open("file").unwrap();
+        file.write_at(b"world", 6).unwrap();
+        file.write_at(b"hello", 0).unwrap();
+        assert_eq!(file.size().unwrap(), 11);
+        assert_eq!(read_up_to(&*file, 0, 20).unwrap(), b"hello\0world");
+
+        // other open files see the same content, removing the path leaves them alone
+        let other = vfs.open("file").unwrap();
+        other.truncate(5).unwrap();
+        assert_eq!(read_up_to(&*file, 0, 20).unwrap(), b"hello");
+        vfs.delete("file").unwrap();
+        assert!(!vfs.exists("file").unwrap());
+        assert_eq!(read_up_to(&*other, 0, 20).unwrap(), b"hello");
+        assert_eq!(vfs.open("file").unwrap().size().unwrap(), 0);
+        assert!(vfs.delete("missing").is_err());
+    }
+
+    #[test]
+    fn test_memory_file_locks() {
+        let vfs = MemoryVfs::new();
+        let first = vfs.open("file").unwrap();
+        let second = vfs.open("file").unwrap();
+        first.try_lock(LockKind::Shared).unwrap();
+        second.try_lock(LockKind::Shared).unwrap();
+        assert!(matches!(first.try_lock(LockKind::Exclusive), Err(TryLockError::WouldBlock)));
+
+        second.unlock().unwrap();
+        first.try_lock(LockKind::Exclusive).unwrap();
+        assert!(matches!(second.try_lock(LockKind::Shared), Err(TryLockError::WouldBlock)));
+        // back to shared
+        first.try_lock(LockKind::Shared).unwrap();
+        second.try_lock(LockKind::Shared).unwrap();
+
+        // closing gives the locks up
+        drop(second);
+        first.try_lock(LockKind::Exclusive).unwrap();
+        drop(first);
+        vfs.open("file").unwrap().try_lock(LockKind::Exclusive).unwrap();
+    }
+}
@@ -0,0 +1,560 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use anyhow::bail;
+
+use crate::storage::config::SyncMode;
+use crate::storage::error::StorageError;
+use crate::storage::node::crc32;
+use crate::storage::vfs::{read_up_to, Vfs, VfsFile};
+
+// Write-ahead log, kept in a file next to the database. Modified pages are appended to it instead of being
+// written in place, the database file is only updated by checkpoints. Integers are little endian.
+//
+// WAL header:
+//   0..16    magic string, identifies a simple_db WAL
+//   16..20   format version, u32
+//   20..24   page size of the database, u32
+//
+// Followed by frames, one page each:
+//   0..8     page num, u64
+//   8..16    1 if the frame ends a commit, 0 otherwise, u64
+//   16..20   crc32 of the page num, the commit flag and the page, u32
+//   20..     the page
+//
+// A commit appends the modified pages followed by the header page as the commit frame. On open the frames
+// are scanned up to the first torn or corrupted one, frames after the last commit frame are ignored and cut
+// by the next writer. The log is shared by every connection to the database, each one keeps its own index
+// of the committed frames and catches up with the commits of the others through refresh.
+//
+// Every commit gets the next version. Older frames of a page stay readable for snapshot readers pinned at
+// an older version, a checkpoint only moves frames no reader can tell apart from the database file.
+pub const WAL_MAGIC: &[u8; WAL_MAGIC_SIZE] = b"simple_db wal\0\0\0";
+pub const WAL_FORMAT_VERSION: u32 = 1;
+
+const WAL_MAGIC_SIZE: usize = 16;
+const WAL_MAGIC_OFFSET: usize = 0;
+const WAL_FORMAT_VERSION_OFFSET: usize = WAL_MAGIC_OFFSET + WAL_MAGIC_SIZE;
+const WAL_PAGE_SIZE_OFFSET: usize = WAL_FORMAT_VERSION_OFFSET + std::mem::size_of::<u32>();
+const WAL_HEADER_SIZE: usize = WAL_PAGE_SIZE_OFFSET + std::mem::size_of::<u32>();
+
+const FRAME_PAGE_NUM_OFFSET: usize = 0;
+const FRAME_COMMIT_OFFSET: usize = FRAME_PAGE_NUM_OFFSET + std::mem::size_of::<u64>();
+const FRAME_CHECKSUM_OFFSET: usize = FRAME_COMMIT_OFFSET + std::mem::size_of::<u64>();
+const FRAME_HEADER_SIZE: usize = FRAME_CHECKSUM_OFFSET + std::mem::size_of::<u32>();
+
+// Versions pinned by snapshot readers, with the number of readers on each. Shared with the snapshots, they
+// end their read without access to the connection
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ReadVersions(Arc<Mutex<BTreeMap<u64, usize>>>);
+
+impl ReadVersions {
+    // every change leaves the map consistent, a reader that panicked in between left nothing half done
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<u64, usize>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn pin(&self, version: u64) {
+        *self.lock().entry(version).or_insert(0) += 1;
+    }
+
+    // frames only needed by the version are forgotten by the next garbage collection of the log
+    pub fn unpin(&self, version: u64) {
+        let mut versions = self.lock();
+        if let Some(count) = versions.get_mut(&version) {
+            *count -= 1;
+            if *count == 0 {
+                versions.remove(&version);
+            }
+        }
+    }
+
+    fn get_versions(&self) -> Vec<u64> {
+        self.lock().keys().copied().collect()
+    }
+
+    fn clear(&self) {
+        self.lock().clear();
+    }
+}
+
+pub(crate) struct Wal {
+    file: Box<dyn VfsFile>,
+    vfs: Arc<dyn Vfs>,
+    path: String,
+    page_size: usize,
+    // committed frames of every page as (version, offset), oldest first
+    committed_frames: HashMap<usize, Vec<(u64, u64)>>,
+    // frames appended since the last commit, readable by this connection but lost on a crash
+    pending_frames: HashMap<usize, u64>,
+    frame_count: usize,
+    len: u64,
+    // end of the last commit frame, rolling back cuts the log here
+    committed_frame_count: usize,
+    committed_len: u64,
+    // version of the last commit, kept across checkpoints
+    version: u64,
+    readers: ReadVersions,
+    sync_mode: SyncMode,
+}
+
+impl Wal {
+    // starts an empty WAL, a log left behind by a previous database of the same name is discarded
+    pub fn create(vfs: &Arc<dyn Vfs>, path: &str, page_size: usize, sync_mode: SyncMode) -> anyhow::Result<Self> {
+        let mut wal = Self::new(vfs, path, page_size, sync_mode)?;
+        wal.reset()?;
+        Ok(wal)
+    }
+
+    // opens the WAL of an existing database and recovers its committed frames
+    pub fn open(vfs: &Arc<dyn Vfs>, path: &str, page_size: usize, sync_mode: SyncMode) -> anyhow::Result<Self> {
+        let mut wal = Self::new(vfs, path, page_size, sync_mode)?;
+        let header = read_up_to(&*wal.file, 0, WAL_HEADER_SIZE)?;
+        if header.len() < WAL_HEADER_SIZE {
+            // no log or one that was cut before its header was written, nothing was committed to it
+            wal.reset()?;
+            return Ok(wal);
+        }
+
+        if &header[WAL_MAGIC_OFFSET..WAL_MAGIC_OFFSET + WAL_MAGIC_SIZE] != WAL_MAGIC {
+            bail!("File {} is not a simple_db WAL", path);
+        }
+        let wal_page_size = read_u32(&header, WAL_PAGE_SIZE_OFFSET) as usize;
+        if wal_page_size != page_size {
+            bail!("WAL {} was written for pages of {} bytes, the database uses {}", path, wal_page_size, page_size);
+        }
+
+        wal.refresh()?;
+        Ok(wal)
+    }
+
+    fn new(vfs: &Arc<dyn Vfs>, path: &str, page_size: usize, sync_mode: SyncMode) -> anyhow::Result<Self> {
+        Ok(Self {
+            file: vfs.open(path)?,
+            vfs: vfs.clone(),
+            path: path.to_string(),
+            page_size,
+            committed_frames: HashMap::new(),
+            pending_frames: HashMap::new(),
+            frame_count: 0,
+            len: WAL_HEADER_SIZE as u64,
+            committed_frame_count: 0,
+            committed_len: WAL_HEADER_SIZE as u64,
+            version: 0,
+            readers: ReadVersions::default(),
+            sync_mode,
+        })
+    }
+
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+
+    // frames in the log, committed or not
+    pub fn get_frame_count(&self) -> usize {
+        self.frame_count
+    }
+
+    pub fn get_sync_mode(&self) -> SyncMode {
+        self.sync_mode
+    }
+
+    pub fn set_sync_mode(&mut self, sync_mode: SyncMode) {
+        self.sync_mode = sync_mode;
+    }
+
+    // pins the last committed version, its frames are kept until it is unpinned through get_readers
+    pub fn begin_read(&self) -> u64 {
+        self.readers.pin(self.version);
+        self.version
+    }
+
+    pub fn get_readers(&self) -> &ReadVersions {
+        &self.readers
+    }
+
+    // the connection is closing, nobody is left to read old versions
+    pub fn end_all_reads(&mut self) {
+        self.readers.clear();
+        self.collect_garbage();
+    }
+
+    // latest version of the page in the log, None if the page has to be read from the database file
+    pub fn read_page(&self, page_num: usize) -> Result<Option<Vec<u8>>, StorageError> {
+        let offset = match self.pending_frames.get(&page_num) {
+            Some(&offset) => offset,
+            None => match self.committed_frames.get(&page_num).and_then(|frames| frames.last()) {
+                Some(&(_, offset)) => offset,
+                None => return Ok(None),
+            },
+        };
+        self.read_frame(offset).map(Some)
+    }
+
+    // the page as committed at the version, None if it has to be read from the database file
+    pub fn read_page_at(&self, page_num: usize, version: u64) -> Result<Option<Vec<u8>>, StorageError> {
+        let frame = self
+            .committed_frames
+            .get(&page_num)
+            .and_then(|frames| frames.iter().rev().find(|&&(frame_version, _)| frame_version <= version));
+        match frame {
+            Some(&(_, offset)) => self.read_frame(offset).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn read_frame(&self, offset: u64) -> Result<Vec<u8>, StorageError> {
+        // a short read is reported as a torn page by the node
+        Ok(read_up_to(&*self.file, offset + FRAME_HEADER_SIZE as u64, self.page_size)?)
+    }
+
+    // appends a page that only becomes durable with the next commit
+    pub fn append(&mut self, page_num: usize, page: &[u8]) -> Result<(), StorageError> {
+        let offset = self.write_frame(page_num, page, false)?;
+        self.pending_frames.insert(page_num, offset);
+        Ok(())
+    }
+
+    // appends the commit frame, every frame before it is committed once this returns. The commit only
+    // survives a power loss once the log is synced, right away in full mode, by the next checkpoint otherwise
+    pub fn commit(&mut self, page_num: usize, page: &[u8]) -> Result<(), StorageError> {
+        let offset = self.write_frame(page_num, page, true)?;
+        if self.sync_mode == SyncMode::Full {
+            self.file.sync()?;
+        }
+        self.pending_frames.insert(page_num, offset);
+        self.version += 1;
+        for (page_num, offset) in self.pending_frames.drain() {
+            self.committed_frames.entry(page_num).or_default().push((self.version, offset));
+        }
+        self.collect_garbage();
+        self.committed_frame_count = self.frame_count;
+        self.committed_len = self.len;
+        Ok(())
+    }
+
+    // drops the frames written since the last commit, returns the page nums they held
+    pub fn rollback(&mut self) -> Result<Vec<usize>, StorageError> {
+        let page_nums = self.pending_frames.drain().map(|(page_num, _)| page_num).collect();
+        // cut from the file as well, a later commit frame would otherwise commit them on recovery
+        self.file.truncate(self.committed_len)?;
+        self.frame_count = self.committed_frame_count;
+        self.len = self.committed_len;
+        Ok(page_nums)
+    }
+
+    // copies committed frames into the database file, up to the version of the oldest reader so that
+    // every reader still finds its version either in the log or in the file. The log starts over once
+    // nothing is left in it, unless frames written after the last commit still have to be readable
+    pub fn checkpoint(&mut self, db_file: &dyn VfsFile) -> Result<(), StorageError> {
+        let horizon = self.oldest_read_version();
+        let mut page_nums: Vec<usize> = self.committed_frames.keys().copied().collect();
+        page_nums.sort_unstable();
+        // the log has to be on disk before the database file is overwritten from it, it is replayed
+        // should the checkpoint be cut short
+        if self.sync_mode == SyncMode::Normal && !page_nums.is_empty() {
+            self.file.sync()?;
+        }
+        let mut copied = false;
+        for &page_num in &page_nums {
+            let frames = &self.committed_frames[&page_num];
+            let Some(&(_, offset)) = frames.iter().rev().find(|&&(version, _)| version <= horizon) else {
+                continue;
+            };
+            let page = self.read_frame(offset)?;
+            db_file.write_at(&page, (page_num * self.page_size) as u64)?;
+            copied = true;
+        }
+        if copied && self.sync_mode != SyncMode::Off {
+            db_file.sync()?;
+        }
+
+        // the file now holds the page as of the horizon, older frames are not needed by anyone
+        for page_num in page_nums {
+            let frames = self.committed_frames.get_mut(&page_num).unwrap();
+            frames.retain(|&(version, _)| version > horizon);
+            if frames.is_empty() {
+                self.committed_frames.remove(&page_num);
+            }
+        }
+
+        if self.committed_frames.is_empty() && self.pending_frames.is_empty() {
+            self.reset()?;
+        }
+        Ok(())
+    }
+
+    // versions up to this one can be read from the database file alone
+    fn oldest_read_version(&self) -> u64 {
+        self.readers.get_versions().first().copied().unwrap_or(self.version)
+    }
+
+    // forgets frames hidden from every reader by a newer frame of the same page. Their space in the
+    // file is reclaimed when the log starts over
+    pub fn collect_garbage(&mut self) {
+        let read_versions = self.readers.get_versions();
+        for frames in self.committed_frames.values_mut() {
+            let newest_version = frames.last().map(|&(version, _)| version);
+            let visible: Vec<u64> = read_versions
+                .iter()
+                .filter_map(|&read_version| frames.iter().rev().find(|&&(version, _)| version <= read_version))
+                .map(|&(version, _)| version)
+                .collect();
+            frames.retain(|&(version, _)| Some(version) == newest_version || visible.contains(&version));
+        }
+    }
+
+    fn reset(&mut self) -> Result<(), StorageError> {
+        let mut header = [0; WAL_HEADER_SIZE];
+        header[WAL_MAGIC_OFFSET..WAL_MAGIC_OFFSET + WAL_MAGIC_SIZE].copy_from_slice(WAL_MAGIC);
+        write_u32(&mut header, WAL_FORMAT_VERSION_OFFSET, WAL_FORMAT_VERSION);
+        write_u32(&mut header, WAL_PAGE_SIZE_OFFSET, self.page_size as u32);
+
+        self.file.truncate(0)?;
+        self.file.write_at(&header, 0)?;
+        if self.sync_mode != SyncMode::Off {
+            // the log may have just been created, its directory entry has to be durable as well
+            self.file.sync()?;
+            self.vfs.sync_dir(&self.path)?;
+        }
+
+        self.committed_frames.clear();
+        self.pending_frames.clear();
+        self.frame_count = 0;
+        self.len = WAL_HEADER_SIZE as u64;
+        self.committed_frame_count = 0;
+        self.committed_len = self.len;
+        Ok(())
+    }
+
+    fn write_frame(&mut self, page_num: usize, page: &[u8], commit: bool) -> Result<u64, StorageError> {
+        assert_eq!(page.len(), self.page_size, "Frame does not match the page size of the WAL");
+        let mut frame = vec![0; FRAME_HEADER_SIZE + self.page_size];
+        write_u64(&mut frame, FRAME_PAGE_NUM_OFFSET, page_num as u64);
+        write_u64(&mut frame, FRAME_COMMIT_OFFSET, commit as u64);
+        frame[FRAME_HEADER_SIZE..].copy_from_slice(page);
+        let checksum = frame_checksum(&frame);
+        write_u32(&mut frame, FRAME_CHECKSUM_OFFSET, checksum);
+
+        let offset = self.len;
+        self.file.write_at(&frame, offset)?;
+        self.len += frame.len() as u64;
+        self.frame_count += 1;
+        Ok(offset)
+    }
+
+    // adopts the commits appended since this connection last looked at the log, by another connection
+    // or before a crash. Frames after the last commit frame are left alone, a writer may still be
+    // adding to them. Returns the page nums the new commits changed
+    pub fn refresh(&mut self) -> Result<Vec<usize>, StorageError> {
+        if !self.pending_frames.is_empty() {
+            // only the writer appends, nobody else committed since it started
+            return Ok(Vec::new());
+        }
+
+        let file_len = self.file.size()?;
+        let frame_size = (FRAME_HEADER_SIZE + self.page_size) as u64;
+        let mut uncommitted_frames = HashMap::new();
+        let mut page_nums = Vec::new();
+        let mut offset = self.committed_len;
+        let mut frame_count = self.committed_frame_count;
+
+        while offset + frame_size <= file_len {
+            let frame = read_up_to(&*self.file, offset, frame_size as usize)?;
+            if frame.len() < frame_size as usize || read_u32(&frame, FRAME_CHECKSUM_OFFSET) != frame_checksum(&frame) {
+                break;
+            }
+
+            uncommitted_frames.insert(read_u64(&frame, FRAME_PAGE_NUM_OFFSET) as usize, offset);
+            frame_count += 1;
+            offset += frame_size;
+            if read_u64(&frame, FRAME_COMMIT_OFFSET) == 1 {
+                self.version += 1;
+                for (page_num, offset) in uncommitted_frames.drain() {
+                    self.committed_frames.entry(page_num).or_default().push((self.version, offset));
+                    page_nums.push(page_num);
+                }
+                self.committed_frame_count = frame_count;
+                self.committed_len = offset;
+            }
+        }
+        self.frame_count = self.committed_frame_count;
+        self.len = self.committed_len;
+        self.collect_garbage();
+
+        page_nums.sort_unstable();
+        page_nums.dedup();
+        Ok(page_nums)
+    }
+
+    // the connection is about to append, whatever a crashed writer left after the last commit frame
+    // is cut so that it cannot be mistaken for part of the next commit
+    pub fn begin_write(&mut self) -> Result<(), StorageError> {
+        if self.pending_frames.is_empty() && self.file.size()? > self.committed_len {
+            self.file.truncate(self.committed_len)?;
+            if self.sync_mode != SyncMode::Off {
+                self.file.sync()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn frame_checksum(frame: &[u8]) -> u32 {
+    let mut bytes = frame[..FRAME_CHECKSUM_OFFSET].to_vec();
+    bytes.extend_from_slice(&frame[FRAME_HEADER_SIZE..]);
+    crc32(&bytes)
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fn write_u64(bytes: &mut [u8], offset: usize, value: u64) {
+    bytes[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::fs::OpenOptions;
+
+    use crate::storage::vfs::StdVfs;
+
+    use super::*;
+
+    const PAGE_SIZE: usize = 512;
+
+    fn vfs() -> Arc<dyn Vfs> {
+        Arc::new(StdVfs)
+    }
+
+    fn page(byte: u8) -> Vec<u8> {
+        vec![byte; PAGE_SIZE]
+    }
+
+    #[test]
+    fn test_committed_frames_survive_reopen() {
+        let wal_path = "test_committed_frames_survive_reopen.db-wal";
+        let mut wal = Wal::create(&vfs(), wal_path, PAGE_SIZE, SyncMode::Full).unwrap();
+        wal.append(3, &page(1)).unwrap();
+        wal.append(3, &page(2)).unwrap();
+        wal.commit(0, &page(9)).unwrap();
+        // written after the last commit, lost on reopen
+        wal.append(4, &page(5)).unwrap();
+        assert_eq!(wal.read_page(4).unwrap(), Some(page(5)));
+        drop(wal);
+
+        let wal = Wal::open(&vfs(), wal_path, PAGE_SIZE, SyncMode::Full).unwrap();
+        assert_eq!(wal.get_frame_count(), 3);
+        assert_eq!(wal.read_page(3).unwrap(), Some(page(2)));
+        assert_eq!(wal.read_page(0).unwrap(), Some(page(9)));
+        assert_eq!(wal.read_page(4).unwrap(), None);
+
+        fs::remove_file(wal_path).unwrap();
+    }
+
+    #[test]
+    fn test_torn_frame_is_dropped() {
+        let wal_path = "test_torn_frame_is_dropped.db-wal";
+        let mut wal = Wal::create(&vfs(), wal_path, PAGE_SIZE, SyncMode::Full).unwrap();
+        wal.commit(1, &page(1)).unwrap();
+        wal.append(2, &page(2)).unwrap();
+        wal.commit(0, &page(3)).unwrap();
+        drop(wal);
+
+        // the second commit frame is only half written
+        let len = fs::metadata(wal_path).unwrap().len();
+        let file = OpenOptions::new().write(true).open(wal_path).unwrap();
+        file.set_len(len - 100).unwrap();
+        drop(file);
+
+        let mut wal = Wal::open(&vfs(), wal_path, PAGE_SIZE, SyncMode::Full).unwrap();
+        assert_eq!(wal.read_page(1).unwrap(), Some(page(1)));
+        assert_eq!(wal.read_page(2).unwrap(), None);
+        assert_eq!(wal.read_page(0).unwrap(), None);
+        // the torn tail stays until the next writer cuts it
+        wal.begin_write().unwrap();
+        assert_eq!(fs::metadata(wal_path).unwrap().len(), (WAL_HEADER_SIZE + FRAME_HEADER_SIZE + PAGE_SIZE) as u64);
+
+        fs::remove_file(wal_path).unwrap();
+    }
+
+    #[test]
+    fn test_corrupted_frame_ends_the_log() {
+        let wal_path = "test_corrupted_frame_ends_the_log.db-wal";
+        let mut wal = Wal::create(&vfs(), wal_path, PAGE_SIZE, SyncMode::Full).unwrap();
+        wal.commit(1, &page(1)).unwrap();
+        wal.commit(1, &page(2)).unwrap();
+        drop(wal);
+
+        let mut bytes = fs::read(wal_path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        fs::write(wal_path, &bytes).unwrap();
+
+        let wal = Wal::open(&vfs(), wal_path, PAGE_SIZE, SyncMode::Full).unwrap();
+        assert_eq!(wal.read_page(1).unwrap(), Some(page(1)));
+
+        fs::remove_file(wal_path).unwrap();
+    }
+
+    #[test]
+    fn test_rolled_back_frames_are_not_recovered() {
+        let wal_path = "test_rolled_back_frames_are_not_recovered.db-wal";
+        let mut wal = Wal::create(&vfs(), wal_path, PAGE_SIZE, SyncMode::Full).unwrap();
+        wal.commit(0, &page(1)).unwrap();
+        wal.append(2, &page(2)).unwrap();
+        assert_eq!(wal.rollback().unwrap(), vec![2]);
+        assert_eq!(wal.read_page(2).unwrap(), None);
+        wal.commit(0, &page(3)).unwrap();
+        drop(wal);
+
+        let wal = Wal::open(&vfs(), wal_path, PAGE_SIZE, SyncMode::Full).unwrap();
+        assert_eq!(wal.get_frame_count(), 2);
+        assert_eq!(wal.read_page(2).unwrap(), None);
+        assert_eq!(wal.read_page(0).unwrap(), Some(page(3)));
+
+        fs::remove_file(wal_path).unwrap();
+    }
+
+    #[test]
+    fn test_checkpoint_copies_committed_frames() {
+        let wal_path = "test_checkpoint_copies_committed_frames.db-wal";
+        let db_path = "test_checkpoint_copies_committed_frames.db";
+        let _ = fs::remove_file(db_path);
+        let db_file = StdVfs.open(db_path).unwrap();
+        let mut wal = Wal::create(&vfs(), wal_path, PAGE_SIZE, SyncMode::Full).unwrap();
+        wal.append(2, &page(2)).unwrap();
+        wal.commit(0, &page(7)).unwrap();
+
+        wal.checkpoint(&*db_file).unwrap();
+        assert_eq!(wal.get_frame_count(), 0);
+        assert_eq!(wal.read_page(2).unwrap(), None);
+        assert_eq!(fs::metadata(wal_path).unwrap().len(), WAL_HEADER_SIZE as u64);
+
+        let bytes = fs::read(db_path).unwrap();
+        assert_eq!(&bytes[..PAGE_SIZE], &page(7)[..]);
+        assert_eq!(&bytes[2 * PAGE_SIZE..], &page(2)[..]);
+
+        fs::remove_file(wal_path).unwrap();
+        fs::remove_file(db_path).unwrap();
+    }
+
+    #[test]
+    fn test_wal_of_other_page_size_is_rejected() {
+        let wal_path = "test_wal_of_other_page_size_is_rejected.db-wal";
+        Wal::create(&vfs(), wal_path, PAGE_SIZE, SyncMode::Full).unwrap();
+        assert!(Wal::open(&vfs(), wal_path, 2 * PAGE_SIZE, SyncMode::Full).is_err());
+
+        fs::remove_file(wal_path).unwrap();
+    }
+}